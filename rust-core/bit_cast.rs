@@ -1,3 +1,7 @@
+use core::num::{
+    NonZeroI8, NonZeroI16, NonZeroI32, NonZeroI64, NonZeroU8, NonZeroU16, NonZeroU32, NonZeroU64,
+};
+
 /// All encoding and decoding is done via u64.
 /// So all types must implement this trait to be able to be encoded and decoded.
 pub trait BitCast: Sized {
@@ -6,6 +10,12 @@ pub trait BitCast: Sized {
     /// Convert a u64 to the value.
     fn from_u64(value: u64) -> Self;
 }
+/// Fallible counterpart of `BitCast` for types where not every u64 maps to a value,
+/// such as enums. `#[derive(BitCast)]` implements it as well.
+pub trait TryBitCast: BitCast {
+    /// Convert a u64 to the value, returning `None` if it does not represent one.
+    fn try_from_u64(value: u64) -> Option<Self>;
+}
 macro_rules! impl_bitcast {
     ($($t:ty),*) => {
         $(
@@ -45,3 +55,153 @@ impl BitCast for bool {
         value != 0
     }
 }
+impl BitCast for char {
+    fn to_u64(&self) -> u64 {
+        u32::from(*self) as u64
+    }
+    /// # Panics
+    /// If the value is not a valid Unicode scalar value (e.g. a surrogate)
+    fn from_u64(value: u64) -> Self {
+        u32::try_from(value)
+            .ok()
+            .and_then(char::from_u32)
+            .unwrap_or_else(|| panic!("Invalid char value: {}", value))
+    }
+}
+impl TryBitCast for char {
+    fn try_from_u64(value: u64) -> Option<Self> {
+        u32::try_from(value).ok().and_then(char::from_u32)
+    }
+}
+macro_rules! impl_bitcast_nonzero {
+    ($($t:ty => $inner:ty),*) => {
+        $(
+            impl BitCast for $t {
+                fn to_u64(&self) -> u64 {
+                    self.get() as u64
+                }
+                /// # Panics
+                /// If the value is zero
+                fn from_u64(value: u64) -> Self {
+                    Self::try_from_u64(value)
+                        .unwrap_or_else(|| panic!("Invalid {} value: {}", stringify!($t), value))
+                }
+            }
+            impl TryBitCast for $t {
+                fn try_from_u64(value: u64) -> Option<Self> {
+                    <$t>::new(value as $inner)
+                }
+            }
+        )*
+    };
+}
+impl_bitcast_nonzero!(
+    NonZeroU8 => u8, NonZeroI8 => i8, NonZeroU16 => u16, NonZeroI16 => i16,
+    NonZeroU32 => u32, NonZeroI32 => i32, NonZeroU64 => u64, NonZeroI64 => i64
+);
+
+/// A protobuf `sfixed32` value: an `i32` stored as 4 bytes of two's complement.
+/// Use it with `write_fixed_field` and `read_fixed` to mark signed fixed fields at the type level.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SFixed32(pub i32);
+impl BitCast for SFixed32 {
+    fn to_u64(&self) -> u64 {
+        self.0 as u64
+    }
+    fn from_u64(value: u64) -> Self {
+        SFixed32(value as i32)
+    }
+}
+
+/// A protobuf `sfixed64` value: an `i64` stored as 8 bytes of two's complement.
+/// Use it with `write_fixed_field` and `read_fixed` to mark signed fixed fields at the type level.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SFixed64(pub i64);
+impl BitCast for SFixed64 {
+    fn to_u64(&self) -> u64 {
+        self.0 as u64
+    }
+    fn from_u64(value: u64) -> Self {
+        SFixed64(value as i64)
+    }
+}
+
+/// A type that can be stored as a protobuf fixed value: exactly 32 or 64 bits on every
+/// platform. `usize`, `isize`, `bool` and other widths do not implement it, so they are
+/// rejected at compile time by every fixed reader and writer, the signed packed and explicit
+/// byte order ones included. Implement it for your own `BitCast` types that wrap a 32 or 64
+/// bit value:
+///
+/// ```rust
+/// use pbf_core::{BitCast, Fixed, Protobuf};
+///
+/// #[derive(Debug, PartialEq)]
+/// struct Id(u32);
+/// impl BitCast for Id {
+///     fn to_u64(&self) -> u64 {
+///         self.0 as u64
+///     }
+///     fn from_u64(value: u64) -> Self {
+///         Id(value as u32)
+///     }
+/// }
+/// impl Fixed for Id {}
+///
+/// let mut pb = Protobuf::new();
+/// pb.write_fixed(Id(7));
+/// assert_eq!(Protobuf::from_input(pb.take()).read_fixed::<Id>(), Id(7));
+/// ```
+///
+/// ```compile_fail
+/// pbf_core::Protobuf::new().write_fixed(1_usize);
+/// ```
+///
+/// ```compile_fail
+/// pbf_core::Protobuf::from_input(vec![1; 8]).read_fixed::<bool>();
+/// ```
+///
+/// ```compile_fail
+/// pbf_core::Protobuf::new().write_fixed_field(1, 1_u8);
+/// ```
+///
+/// ```compile_fail
+/// pbf_core::Protobuf::new().write_packed_s_fixed(1, &[-1_i16]);
+/// ```
+///
+/// ```compile_fail
+/// pbf_core::Protobuf::new().write_fixed_be(7_usize);
+/// ```
+///
+/// ```compile_fail
+/// pbf_core::Protobuf::new().write_fixed_le(true);
+/// ```
+pub trait Fixed: BitCast {}
+macro_rules! impl_fixed {
+    ($($t:ty),*) => {
+        $(
+            impl Fixed for $t {}
+        )*
+    };
+}
+impl_fixed!(
+    u32, i32, f32, char, NonZeroU32, NonZeroI32, SFixed32, u64, i64, f64, NonZeroU64, NonZeroI64,
+    SFixed64
+);
+
+/// Saturating conversion from an i64, used to read signed varints into narrower integers.
+pub trait FromI64Saturating {
+    /// Convert an i64, clamping it to the bounds of the type.
+    fn from_i64_saturating(value: i64) -> Self;
+}
+macro_rules! impl_from_i64_saturating {
+    ($($t:ty),*) => {
+        $(
+            impl FromI64Saturating for $t {
+                fn from_i64_saturating(value: i64) -> Self {
+                    <$t>::try_from(value).unwrap_or(if value < 0 { <$t>::MIN } else { <$t>::MAX })
+                }
+            }
+        )*
+    };
+}
+impl_from_i64_saturating!(u8, i8, u16, i16, u32, i32, u64, i64, usize, isize);
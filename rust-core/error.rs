@@ -0,0 +1,72 @@
+use alloc::string::String;
+use core::fmt;
+
+/// The `PbfError` enum represents the ways decoding a protobuf buffer can fail.
+/// It is returned by the fallible `try_*` family of methods on `Protobuf`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PbfError {
+    /// The buffer ended before the value could be fully read
+    UnexpectedEof,
+    /// A varint did not terminate within 10 bytes (the maximum for a 64-bit value)
+    /// or its 10th byte holds bits beyond bit 63
+    VarintOverflow,
+    /// The wire type of a field key is not a supported protobuf wire type
+    InvalidWireType(u8),
+    /// A bool varint held a value other than 0 or 1
+    InvalidBool(u64),
+    /// A varint did not represent a valid value of the type being read, such as an
+    /// unknown enum discriminant
+    InvalidValue(u64),
+    /// A string field did not hold valid UTF-8
+    InvalidUtf8,
+    /// A length-delimited value did not have the length the reader expected
+    LengthMismatch {
+        /// The length the reader expected
+        expected: usize,
+        /// The length found in the buffer
+        found: usize,
+    },
+    /// A field's value was not read according to its wire type, e.g. `read_string` on a
+    /// varint field. Raised by `read_fields_strict`.
+    WireTypeMismatch {
+        /// The tag of the field
+        tag: u64,
+        /// The wire type found in the field key
+        wire_type: u8,
+    },
+    /// Messages or groups were nested deeper than the `Protobuf`'s `max_depth`
+    RecursionLimitExceeded,
+    /// A group was closed by an `EndGroup` key with a different field number
+    GroupMismatch {
+        /// The field number of the `StartGroup` key
+        expected: u64,
+        /// The field number of the `EndGroup` key
+        found: u64,
+    },
+    /// A custom error, raised by the serde bridge when converting to or from protobuf bytes
+    Custom(String),
+}
+impl fmt::Display for PbfError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PbfError::UnexpectedEof => write!(f, "unexpected EOF"),
+            PbfError::VarintOverflow => write!(f, "varint exceeds 10 bytes"),
+            PbfError::InvalidWireType(t) => write!(f, "invalid wire type: {}", t),
+            PbfError::InvalidBool(v) => write!(f, "invalid bool: {}", v),
+            PbfError::InvalidValue(v) => write!(f, "invalid value: {}", v),
+            PbfError::InvalidUtf8 => write!(f, "invalid utf-8 in string"),
+            PbfError::LengthMismatch { expected, found } => {
+                write!(f, "length mismatch: expected {} bytes, found {}", expected, found)
+            }
+            PbfError::WireTypeMismatch { tag, wire_type } => {
+                write!(f, "field {} was not read as its wire type {}", tag, wire_type)
+            }
+            PbfError::RecursionLimitExceeded => write!(f, "recursion limit exceeded"),
+            PbfError::GroupMismatch { expected, found } => {
+                write!(f, "group {} was closed by an EndGroup with tag {}", expected, found)
+            }
+            PbfError::Custom(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+impl core::error::Error for PbfError {}
@@ -56,22 +56,37 @@
 /// All encoding and decoding is done via u64.
 /// So all types must implement this trait to be able to be encoded and decoded.
 pub mod bit_cast;
+/// Errors returned by the fallible decoding methods.
+pub mod error;
+/// Convert serde types to and from protobuf bytes.
+#[cfg(feature = "serde")]
+pub mod serde_bridge;
+/// Google's well-known message types, such as `Timestamp` and `Duration`.
+pub mod well_known;
 
 extern crate alloc;
+#[cfg(feature = "std")]
+extern crate std;
 
 use alloc::{
+    format,
     string::{String, ToString},
     vec::Vec,
 };
 pub use bit_cast::*;
-use core::mem::size_of;
+use core::{marker::PhantomData, mem::size_of};
+pub use error::*;
+#[cfg(feature = "serde")]
+pub use serde_bridge::*;
 
-const MAX_VARINT_LENGTH: usize = u64::BITS as usize * 8 / 7 + 1;
+const MAX_VARINT_LENGTH: usize = u64::BITS as usize / 7 + 1;
 const BIT_SHIFT: [u64; 10] = [0, 7, 14, 21, 28, 35, 42, 49, 56, 63];
+/// The width reserved for the length of a field started with `begin_len_delimited`.
+const LEN_PLACEHOLDER: usize = 5;
 
 /// The `Type` enum represents the different types that a field can have in a protobuf message.
 /// The `Type` enum is used to determine how to encode and decode the field.
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Type {
     /// Varint may be: int32, int64, uint32, uint64, sint32, sint64, bool, enum
     Varint = 0,
@@ -81,25 +96,40 @@ pub enum Type {
     /// This includes any len-delimited tyles:
     /// string, bytes, embedded messages, packed repeated fields
     Bytes = 2,
+    /// Start of a group (deprecated proto2 feature)
+    /// The group's fields follow until the matching `EndGroup`
+    StartGroup = 3,
+    /// End of a group (deprecated proto2 feature)
+    EndGroup = 4,
     /// Fixed 32-bit numbers will take up exactly 64 bits of space
     /// They may be an u32, i32, or f32
     Fixed32 = 5,
-    /// This is a null type
+    /// This is a null type (non-standard, used by the derives for unit enum variants)
+    /// It carries no value: `skip` and `read_none` consume zero bytes
     None = 7,
 }
+impl Type {
+    /// Convert a u8 to a Type, returning an error if the value is not a valid Type.
+    /// Only the lowest 3 bits are considered.
+    pub fn try_from_u8(val: u8) -> Result<Self, PbfError> {
+        match val & 0x7 {
+            0 => Ok(Type::Varint),
+            1 => Ok(Type::Fixed64),
+            2 => Ok(Type::Bytes),
+            3 => Ok(Type::StartGroup),
+            4 => Ok(Type::EndGroup),
+            5 => Ok(Type::Fixed32),
+            7 => Ok(Type::None),
+            t => Err(PbfError::InvalidWireType(t)),
+        }
+    }
+}
 impl From<u8> for Type {
     /// Convert a u8 to a Type
     /// # Panics
     /// If the value is not a valid Type
     fn from(val: u8) -> Self {
-        match val & 0x7 {
-            0 => Type::Varint,
-            1 => Type::Fixed64,
-            2 => Type::Bytes,
-            5 => Type::Fixed32,
-            7 => Type::None,
-            _ => panic!("Invalid value for Type"),
-        }
+        Type::try_from_u8(val).unwrap_or_else(|_| panic!("Invalid value for Type"))
     }
 }
 impl From<Type> for u64 {
@@ -108,6 +138,8 @@ impl From<Type> for u64 {
             Type::Varint => 0,
             Type::Fixed64 => 1,
             Type::Bytes => 2,
+            Type::StartGroup => 3,
+            Type::EndGroup => 4,
             Type::Fixed32 => 5,
             Type::None => 7,
         }
@@ -117,13 +149,122 @@ impl From<Type> for u64 {
 /// The `Field` struct contains a tag and a type.
 /// The tag is used to track the data type in the message for decoding.
 /// The type is used to determine how to encode and decode the field.
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct Field {
     /// The tag is used to track the data type in the message for decoding.
     pub tag: u64,
     /// The type is used to determine how to encode and decode the field.
     pub r#type: Type,
 }
+impl Field {
+    /// get the encoded key of the field, `(tag << 3) | type`, as written before its value
+    pub fn key(&self) -> u64 {
+        (self.tag << 3) | u64::from(self.r#type)
+    }
+
+    /// write the key of the field into the buffer, just like `Protobuf::write_field`
+    pub fn write_key(&self, pbf: &mut Protobuf) {
+        pbf.write_field(self.tag, self.r#type);
+    }
+}
+
+/// The `WireValue` enum holds the value of a field decoded without a schema, as returned by
+/// `Protobuf::read_all`. Interpreting it (signedness, floats, strings or sub messages) is
+/// left to the caller.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WireValue {
+    /// The raw value of a varint field
+    Varint(u64),
+    /// The 4 bytes of a fixed32 field, in wire (little-endian) order
+    Fixed32([u8; 4]),
+    /// The 8 bytes of a fixed64 field, in wire (little-endian) order
+    Fixed64([u8; 8]),
+    /// The payload of a length-delimited field
+    Bytes(Vec<u8>),
+    /// The fields of a group, up to its `EndGroup`
+    Group(Vec<(u64, WireValue)>),
+    /// A field of the non-standard `None` type, which carries no value
+    None,
+}
+
+/// The `UnknownFields` struct stores the raw bytes (field key and value) of every field
+/// that a `ProtoRead` implementation did not consume.
+/// It is produced by `Protobuf::read_fields_collecting` and allows re-emitting those fields
+/// verbatim for lossless round-tripping.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct UnknownFields {
+    buf: Vec<u8>,
+}
+impl UnknownFields {
+    /// check if no unknown fields were collected
+    pub fn is_empty(&self) -> bool {
+        self.buf.is_empty()
+    }
+
+    /// get the raw encoded bytes of the collected fields
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.buf
+    }
+
+    /// write the collected fields back into a buffer exactly as they were read
+    pub fn write_to(&self, pbf: &mut Protobuf) {
+        pbf.write_raw(&self.buf);
+    }
+}
+
+/// The `RequiredFields` struct lists the tags a message must contain, for proto2-style
+/// `required` validation of a `ProtoRead` implementation.
+/// Check it against the tags returned by `Protobuf::read_fields_tracked`.
+///
+/// ```rust
+/// use pbf_core::{ProtoRead, Protobuf, RequiredFields};
+///
+/// #[derive(Default)]
+/// struct Header {
+///     id: u32,
+///     name: String,
+/// }
+/// impl ProtoRead for Header {
+///     fn read(&mut self, tag: u64, pbf: &mut Protobuf) {
+///         match tag {
+///             1 => self.id = pbf.read_varint(),
+///             2 => self.name = pbf.read_string(),
+///             _ => {}
+///         }
+///     }
+/// }
+///
+/// let mut pb = Protobuf::new();
+/// pb.write_varint_field(1, 5_u32);
+/// let mut pb = Protobuf::from_input(pb.take());
+///
+/// let mut header = Header::default();
+/// let seen = pb.read_fields_tracked(&mut header, None);
+/// assert_eq!(RequiredFields::new().require(1).require(2).missing(&seen), vec![2]);
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RequiredFields {
+    tags: Vec<u64>,
+}
+impl RequiredFields {
+    /// create an empty set of required tags
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// register a tag as required
+    pub fn require(mut self, tag: u64) -> Self {
+        if !self.tags.contains(&tag) {
+            self.tags.push(tag);
+        }
+        self
+    }
+
+    /// get the required tags that do not appear in `seen`, in registration order
+    pub fn missing(&self, seen: &[u64]) -> Vec<u64> {
+        self.tags.iter().copied().filter(|tag| !seen.contains(tag)).collect()
+    }
+}
 
 /// The `ProtoRead` trait is used to read a protobuf **message**.
 /// This crate forces the user to implement this trait in order to read a protobuf message.
@@ -216,6 +357,19 @@ pub trait ProtoRead {
     /// }
     /// ```
     fn read(&mut self, tag: u64, pbf: &mut Protobuf);
+
+    /// The `init` method is called on a fresh message before any of its fields are read:
+    /// by `read_message`, `try_read_message`, `read_group`, `decode` and
+    /// `read_length_delimited`. Use it to set fallback values for fields that may be absent
+    /// from the buffer. `read_fields` and its variants do not call it, since they may be
+    /// merging into a target that already holds data.
+    /// The default implementation does nothing.
+    fn init(&mut self) {}
+
+    /// The tags this type reads when used as a `pbf(oneof)` field: derived enums list the tag
+    /// of every variant, so a struct only routes those tags to it and rejects a field reusing
+    /// one at compile time. The empty default routes every tag no other field claims.
+    const TAGS: &'static [u64] = &[];
 }
 
 /// The `ProtoWrite` trait is used to write a protobuf **message**.
@@ -302,6 +456,9 @@ pub trait ProtoWrite {
     /// }
     /// ```
     fn write(&self, pbf: &mut Protobuf);
+
+    /// The tags this type writes when used as a `pbf(oneof)` field (see `ProtoRead::TAGS`).
+    const TAGS: &'static [u64] = &[];
 }
 
 /// The `Protobuf` struct is used to read and write protobuf messages.
@@ -322,26 +479,154 @@ pub trait ProtoWrite {
 /// // OR use the From trait
 /// // let mut pbf: Protobuf = buf.into();
 /// ```
-#[derive(Clone, Debug, Default)]
+#[derive(Clone, Debug)]
 pub struct Protobuf {
     buf: Vec<u8>,
     pos: usize,
+    /// when set, writes only add to `counted` instead of producing bytes
+    count_only: bool,
+    counted: usize,
+    /// how many `read_message`/`read_group` calls are currently nested
+    depth: usize,
+    max_depth: usize,
+    /// how many `begin_len_delimited` marks are still open
+    open_marks: usize,
+    /// `(start, len)` of the ended marks whose placeholders await compaction
+    ended_marks: Vec<(usize, usize)>,
+    /// the tag of the last `StartGroup` key read, matched against the group's `EndGroup`
+    group_tag: Option<u64>,
+}
+impl Default for Protobuf {
+    fn default() -> Protobuf {
+        Protobuf {
+            buf: Vec::new(),
+            pos: 0,
+            count_only: false,
+            counted: 0,
+            depth: 0,
+            max_depth: DEFAULT_MAX_DEPTH,
+            open_marks: 0,
+            ended_marks: Vec::new(),
+            group_tag: None,
+        }
+    }
 }
+/// The default limit on how deeply `read_message` and `read_group` calls may nest.
+pub const DEFAULT_MAX_DEPTH: usize = 100;
 impl From<Vec<u8>> for Protobuf {
     fn from(buf: Vec<u8>) -> Protobuf {
         Protobuf::from_input(buf)
     }
 }
+impl From<&[u8]> for Protobuf {
+    fn from(buf: &[u8]) -> Protobuf {
+        Protobuf::from_slice(buf)
+    }
+}
 impl Protobuf {
     /// Create a new Protobuf instance.
     pub fn new() -> Protobuf {
-        let buf = Vec::new();
-        Protobuf { buf, pos: 0 }
+        Protobuf::default()
+    }
+
+    /// Create a new Protobuf instance with room for at least `capacity` bytes
+    /// before the write buffer needs to reallocate.
+    pub fn with_capacity(capacity: usize) -> Protobuf {
+        Protobuf { buf: Vec::with_capacity(capacity), ..Default::default() }
     }
 
     /// Create a Protobuf instance from a byte buffer.
     pub fn from_input(buf: Vec<u8>) -> Protobuf {
-        Protobuf { buf, pos: 0 }
+        Protobuf { buf, ..Default::default() }
+    }
+
+    /// Create a Protobuf instance from anything convertible into a byte buffer, such as
+    /// a `Vec<u8>` (moved), a `&[u8]` or a `&str` (both copied).
+    pub fn from_bytes(buf: impl Into<Vec<u8>>) -> Protobuf {
+        Protobuf::from_input(buf.into())
+    }
+
+    /// Merge two encodings of the same message type. Protobuf defines the concatenation of
+    /// two encodings as their merge: when read back, the scalars of `b` win over those of `a`
+    /// and the repeated fields of both are appended. Decoding the result with `read_fields` is
+    /// the same as reading `a` and then `b` into the same target.
+    ///
+    /// Derived `ProtoRead` implementations replace a singular nested message with its last
+    /// occurrence rather than merging the two field by field, in either case.
+    pub fn merge_bytes(a: &[u8], b: &[u8]) -> Vec<u8> {
+        [a, b].concat()
+    }
+
+    /// Create a Protobuf instance from a borrowed byte slice.
+    /// The bytes are copied into the internal buffer; use `from_input` to hand over
+    /// an existing `Vec<u8>` without copying.
+    ///
+    /// ```rust
+    /// use pbf_core::{ProtoRead, Protobuf};
+    ///
+    /// #[derive(Default)]
+    /// struct BlobHeader {
+    ///     r#type: String,
+    ///     datasize: i32,
+    /// }
+    /// impl ProtoRead for BlobHeader {
+    ///     fn read(&mut self, tag: u64, pbf: &mut Protobuf) {
+    ///         match tag {
+    ///             1 => self.r#type = pbf.read_string(),
+    ///             3 => self.datasize = pbf.read_varint(),
+    ///             _ => {}
+    ///         }
+    ///     }
+    /// }
+    ///
+    /// let bytes = [0x0A, 0x07, b'O', b'S', b'M', b'D', b'a', b't', b'a', 0x18, 0x96, 0x01];
+    /// let mut pbf = Protobuf::from_slice(&bytes);
+    /// let mut header = BlobHeader::default();
+    /// pbf.read_fields(&mut header, None);
+    /// assert_eq!(header.r#type, "OSMData");
+    /// assert_eq!(header.datasize, 150);
+    /// ```
+    pub fn from_slice(buf: &[u8]) -> Protobuf {
+        Protobuf { buf: buf.to_vec(), ..Default::default() }
+    }
+
+    /// Reserve room for at least `additional` more bytes in the write buffer.
+    pub fn reserve(&mut self, additional: usize) {
+        self.buf.reserve(additional);
+    }
+
+    /// Empty the buffer and reset the position to 0, keeping the allocated capacity
+    /// so the same instance can be reused for the next message. Marks still open from
+    /// `begin_len_delimited` are dropped along with their placeholders.
+    pub fn clear(&mut self) {
+        self.buf.clear();
+        self.pos = 0;
+        self.counted = 0;
+        self.open_marks = 0;
+        self.ended_marks.clear();
+        self.group_tag = None;
+    }
+
+    /// Set how deeply `read_message`, `try_read_message` and `read_group` calls may nest
+    /// (`DEFAULT_MAX_DEPTH` by default). A recursive `ProtoRead` fed adversarial input
+    /// then fails with `RecursionLimitExceeded` instead of overflowing the stack.
+    pub fn set_max_depth(&mut self, max_depth: usize) {
+        self.max_depth = max_depth;
+    }
+
+    /// get the nesting limit set by `set_max_depth`
+    pub fn max_depth(&self) -> usize {
+        self.max_depth
+    }
+
+    /// Enter a nested message or group, failing if it would exceed `max_depth`.
+    fn enter(&mut self) -> Result<(), PbfError> {
+        if self.depth >= self.max_depth {
+            return Err(PbfError::RecursionLimitExceeded);
+        }
+        self.depth += 1;
+
+        Ok(())
     }
 
     /// Set the position to read from the buffer next.
@@ -354,6 +639,42 @@ impl Protobuf {
         self.pos
     }
 
+    /// get the current position (same as `get_pos`)
+    pub fn pos(&self) -> usize {
+        self.pos
+    }
+
+    /// Save the current position so a speculative read can be undone with `restore`.
+    pub fn checkpoint(&self) -> usize {
+        self.pos
+    }
+
+    /// Return to a position saved with `checkpoint`.
+    pub fn restore(&mut self, cp: usize) {
+        self.pos = cp;
+    }
+
+    /// Run a speculative read, restoring the position if it returns `None`.
+    pub fn with_checkpoint<R>(&mut self, f: impl FnOnce(&mut Self) -> Option<R>) -> Option<R> {
+        let cp = self.checkpoint();
+        let res = f(self);
+        if res.is_none() {
+            self.restore(cp);
+        }
+
+        res
+    }
+
+    /// get the number of bytes left to read after the current position
+    pub fn remaining(&self) -> usize {
+        self.len().saturating_sub(self.pos)
+    }
+
+    /// check if there are bytes left to read after the current position
+    pub fn has_remaining(&self) -> bool {
+        self.remaining() > 0
+    }
+
     /// get the length of the bufer
     pub fn len(&self) -> usize {
         self.buf.len()
@@ -367,627 +688,3774 @@ impl Protobuf {
     // === READING =================================================================
 
     /// Decode a varint from the buffer at the current position.
+    ///
+    /// # Panics
+    /// Panics if the buffer ends before the varint does or the varint overflows 64 bits.
+    /// Use `try_decode_varint` for untrusted input.
     pub fn decode_varint(&mut self) -> u64 {
-        if self.pos >= self.len() {
-            panic!("EOF");
-        }
+        self.try_decode_varint().unwrap_or_else(|err| panic!("{}", err))
+    }
 
+    /// Decode a varint from the buffer at the current position.
+    /// Returns an error instead of panicking if the buffer is truncated, the
+    /// varint is longer than `MAX_VARINT_LENGTH` bytes or it does not fit in 64 bits.
+    /// The position is left untouched on error.
+    pub fn try_decode_varint(&mut self) -> Result<u64, PbfError> {
+        let (val, len) = self.varint_at(self.pos)?;
+        self.pos += len;
+
+        Ok(val)
+    }
+
+    /// Decode the varint starting at `pos`, returning its value and encoded length.
+    fn varint_at(&self, pos: usize) -> Result<(u64, usize), PbfError> {
         let mut val: u64 = 0;
 
-        for (n, shift) in BIT_SHIFT.iter().enumerate().take(MAX_VARINT_LENGTH) {
-            let b = self.buf[self.pos] as u64;
-            self.pos += 1;
-            if n == 0 {
-                if b & 0x80 == 0 {
-                    return b;
-                }
-                val = b & 0x7f;
-            } else {
-                val |= (b & 0x7f) << shift;
+        for (i, &shift) in BIT_SHIFT.iter().take(MAX_VARINT_LENGTH).enumerate() {
+            let Some(&b) = self.buf.get(pos + i) else {
+                return Err(PbfError::UnexpectedEof);
+            };
+            // the 10th byte only holds bit 63, anything more is an overflow
+            if shift == 63 && b > 1 {
+                return Err(PbfError::VarintOverflow);
             }
+            val |= ((b & 0x7f) as u64) << shift;
             if b < 0x80 {
-                break;
+                return Ok((val, i + 1));
+            }
+        }
+
+        Err(PbfError::VarintOverflow)
+    }
+
+    /// Check that the top level framing of the buffer is sound without allocating: every
+    /// field key and varint terminates, every wire type is recognized, every fixed or
+    /// length-delimited value fits within the buffer and every group is closed.
+    /// Embedded messages are not descended into. An `EndGroup` without a matching
+    /// `StartGroup` is reported as `InvalidWireType(4)`.
+    pub fn validate(&self) -> Result<(), PbfError> {
+        let mut pos = 0;
+        let mut depth: usize = 0;
+        while pos < self.buf.len() {
+            let (key, len) = self.varint_at(pos)?;
+            pos += len;
+            let size = match Type::try_from_u8((key & 0x7) as u8)? {
+                Type::Varint => self.varint_at(pos)?.1,
+                Type::Fixed64 => 8,
+                Type::Fixed32 => 4,
+                Type::Bytes => {
+                    let (size, len) = self.varint_at(pos)?;
+                    pos += len;
+                    size as usize
+                }
+                Type::StartGroup => {
+                    depth += 1;
+                    0
+                }
+                Type::EndGroup => {
+                    depth = depth.checked_sub(1).ok_or(PbfError::InvalidWireType(4))?;
+                    0
+                }
+                Type::None => 0,
+            };
+            if size > self.buf.len() - pos {
+                return Err(PbfError::UnexpectedEof);
             }
+            pos += size;
+        }
+        if depth != 0 {
+            return Err(PbfError::UnexpectedEof);
         }
 
-        val
+        Ok(())
     }
 
     /// After reading a field, you can choose to skip it's value
     /// in the buffer if it is not needed.
+    /// Skipping a `StartGroup` skips every field up to and including the matching `EndGroup`,
+    /// which must carry the same field number as the `StartGroup` key read last.
     pub fn skip(&mut self, t: Type) {
         match t {
             Type::Varint => _ = self.decode_varint(),
             Type::Fixed64 => self.pos += 8,
             Type::Fixed32 => self.pos += 4,
             Type::Bytes => self.pos += self.decode_varint() as usize,
-            Type::None => { /* Do nothing */ }
+            Type::StartGroup => self.skip_group(),
+            Type::EndGroup | Type::None => { /* Do nothing */ }
         };
     }
 
-    /// Read a field from the buffer.
-    pub fn read_field(&mut self) -> Field {
-        let val = self.decode_varint();
-        Field { tag: val >> 3, r#type: Type::from((val & 0x7) as u8) }
+    /// After reading a field, skip its value. Unlike `skip` this never panics: it returns
+    /// an error if the value runs past the end of the buffer, or a group is not closed by an
+    /// `EndGroup` of its own field number. The position is left untouched on error.
+    pub fn try_skip(&mut self, t: Type) -> Result<(), PbfError> {
+        let size = match t {
+            Type::Varint => return self.try_decode_varint().map(|_| ()),
+            Type::Bytes => return self.try_skip_message(),
+            Type::StartGroup => {
+                let tag = self.group_tag.take();
+                return self.try_skip_group(tag);
+            }
+            Type::Fixed64 => 8,
+            Type::Fixed32 => 4,
+            Type::EndGroup | Type::None => 0,
+        };
+        if size > self.remaining() {
+            return Err(PbfError::UnexpectedEof);
+        }
+        self.pos += size;
+
+        Ok(())
     }
 
-    /// Read in bytes from the buffer.
-    pub fn read_bytes(&mut self) -> Vec<u8> {
-        let end = self.decode_varint() as usize + self.pos;
-        let bytes = self.buf[self.pos..end].to_vec();
-        self.pos += end - self.pos;
+    /// After reading a field, return the raw encoded bytes of its value and move past it:
+    /// the varint bytes, the 4 or 8 fixed bytes, the length prefix followed by the payload,
+    /// or a group's fields including its `EndGroup` key. Writing the field key followed by
+    /// these bytes reproduces the original field.
+    ///
+    /// # Panics
+    /// Panics if the value runs past the end of the buffer.
+    pub fn read_field_raw(&mut self, ty: Type) -> Vec<u8> {
+        let start = self.pos;
+        self.try_skip(ty).unwrap_or_else(|err| panic!("{}", err));
 
-        bytes
+        self.buf[start..self.pos].to_vec()
     }
 
-    /// Read in a string from the buffer.
-    pub fn read_string(&mut self) -> String {
-        String::from_utf8_lossy(&self.read_bytes()).to_string()
-    }
+    /// Skip the fields of the group started at `tag`, returning an error instead of panicking.
+    /// Without a `tag` (no `StartGroup` key was read) any `EndGroup` closes the group.
+    /// The position is left untouched on error.
+    fn try_skip_group(&mut self, tag: Option<u64>) -> Result<(), PbfError> {
+        let pos = self.pos;
+        let res = self.try_skip_group_fields(tag);
+        if res.is_err() {
+            self.pos = pos;
+        }
 
-    /// Read in a fixed size value from the buffer.
-    pub fn read_fixed<T>(&mut self) -> T
-    where
-        T: BitCast,
-    {
-        let mut val: u64 = 0;
-        let size = size_of::<T>();
+        res
+    }
 
-        let mut n = 0;
-        while n < size {
-            val |= (self.buf[self.pos] as u64) << (n << 3);
-            self.pos += 1;
-            n += 1;
+    /// Skip fields until the `EndGroup` closing the current group. Nested groups are tracked
+    /// with a stack of their tags rather than recursion, so deeply nested input cannot overflow
+    /// the stack, and every `EndGroup` must match the innermost open group.
+    fn try_skip_group_fields(&mut self, tag: Option<u64>) -> Result<(), PbfError> {
+        let mut open = alloc::vec![tag];
+        while let Some(&expected) = open.last() {
+            let field = self.try_read_field()?;
+            match field.r#type {
+                Type::StartGroup => open.push(Some(field.tag)),
+                Type::EndGroup => {
+                    check_group_end(expected, field.tag)?;
+                    open.pop();
+                }
+                t => self.try_skip(t)?,
+            }
         }
 
-        if cfg!(target_endian = "big") {
-            val = val.swap_bytes();
-        }
+        Ok(())
+    }
 
-        T::from_u64(val)
+    /// Skip an entire embedded message (or any length-delimited value) after its field
+    /// has been read.
+    ///
+    /// # Panics
+    /// Panics if the length prefix exceeds the remaining bytes in the buffer.
+    pub fn skip_message(&mut self) {
+        self.try_skip_message().unwrap_or_else(|err| panic!("{}", err))
     }
 
-    /// Read in a variable size value from the buffer.
-    pub fn read_varint<T>(&mut self) -> T
-    where
-        T: BitCast,
-    {
-        let val = self.decode_varint();
-        T::from_u64(val)
+    /// Skip an entire embedded message (or any length-delimited value) after its field
+    /// has been read. Returns an error if the length prefix exceeds the remaining bytes.
+    /// The position is left untouched on error.
+    pub fn try_skip_message(&mut self) -> Result<(), PbfError> {
+        let pos = self.pos;
+        let len = self.try_decode_varint()? as usize;
+        if len > self.remaining() {
+            self.pos = pos;
+            return Err(PbfError::UnexpectedEof);
+        }
+        self.pos += len;
+
+        Ok(())
     }
 
-    /// Read in a signed variable size value from the buffer.
+    /// Skip the fields of a group, nested groups included, until its `EndGroup` is consumed.
     ///
     /// # Panics
-    /// Panics if the conversion from `i64` to `T` fails.
-    pub fn read_s_varint<T>(&mut self) -> T
-    where
-        T: TryFrom<i64>,
-    {
-        T::try_from(zagzig(self.decode_varint()))
-            .unwrap_or_else(|_| panic!("read_s_varint: Invalid conversion"))
+    /// Panics if the buffer ends before the group is closed.
+    fn skip_group(&mut self) {
+        let tag = self.group_tag.take();
+        self.try_skip_group(tag).unwrap_or_else(|err| panic!("{}", err))
     }
 
-    /// Read in a packed value from the buffer.
-    pub fn read_packed<T>(&mut self) -> Vec<T>
-    where
-        T: BitCast,
-    {
-        let end = self.decode_varint() as usize + self.pos;
-        let mut res: Vec<T> = Vec::new();
-        while self.pos < end {
-            res.push(self.read_varint::<T>());
-        }
+    /// Read a field from the buffer.
+    pub fn read_field(&mut self) -> Field {
+        let val = self.decode_varint();
+        let field = Field { tag: val >> 3, r#type: Type::from((val & 0x7) as u8) };
+        self.note_group_start(field);
 
-        res
+        field
     }
 
-    /// Read in a signed packed value from the buffer.
-    pub fn read_s_packed<T>(&mut self) -> Vec<T>
-    where
-        T: TryFrom<i64>,
-    {
-        let end = self.decode_varint() as usize + self.pos;
-        let mut res: Vec<T> = Vec::new();
-        while self.pos < end {
-            res.push(self.read_s_varint::<T>());
-        }
+    /// Read a field from the buffer.
+    /// Returns an error if the buffer is truncated or the wire type is not supported.
+    pub fn try_read_field(&mut self) -> Result<Field, PbfError> {
+        let val = self.try_decode_varint()?;
+        let field = Field { tag: val >> 3, r#type: Type::try_from_u8((val & 0x7) as u8)? };
+        self.note_group_start(field);
 
-        res
+        Ok(field)
     }
 
-    /// Read a message from the buffer. This is the alternative to `read_message`
-    /// which does the same thing but you may already know the size of the message.
-    /// The other case is top level data may have fields but no message length.
-    pub fn read_fields<T: ProtoRead>(&mut self, t: &mut T, end: Option<usize>) {
-        let end = end.unwrap_or(self.len());
-
-        while self.pos < end {
-            let field = self.read_field();
-            let start_pos = self.pos;
-            t.read(field.tag, self);
-            if start_pos == self.pos {
-                self.skip(field.r#type);
-            }
+    /// Remember the tag of a `StartGroup` key, so skipping or reading the group that follows
+    /// can check its `EndGroup`.
+    fn note_group_start(&mut self, field: Field) {
+        if field.r#type == Type::StartGroup {
+            self.group_tag = Some(field.tag);
         }
     }
 
-    /// Read in an entire message from the buffer.
-    /// This is usually used to read in a struct or enum.
-    pub fn read_message<T: ProtoRead>(&mut self, t: &mut T) {
-        let end = self.decode_varint() as usize + self.pos;
+    /// Read the next field from the buffer, or `None` once the end of the buffer is reached.
+    /// The caller must read or `skip` the field's value before calling this again.
+    ///
+    /// ```rust
+    /// use pbf_core::Protobuf;
+    ///
+    /// let mut pbf = Protobuf::from_input(vec![0x08, 0x01, 0x10, 0x02]);
+    /// let mut sum = 0;
+    /// while let Some(field) = pbf.next_field() {
+    ///     sum += field.tag * pbf.read_varint::<u64>();
+    /// }
+    /// assert_eq!(sum, 5);
+    /// ```
+    pub fn next_field(&mut self) -> Option<Field> {
+        if self.pos >= self.len() {
+            return None;
+        }
 
-        self.read_fields(t, Some(end));
+        Some(self.read_field())
     }
 
-    // === WRITING =================================================================
+    /// Decode every field of the buffer without a schema into `(tag, WireValue)` pairs, in
+    /// the order they appear. The position is left unchanged.
+    ///
+    /// # Panics
+    /// Panics if the buffer is malformed or groups nest deeper than `max_depth`.
+    pub fn read_all(&mut self) -> Vec<(u64, WireValue)> {
+        let pos = self.pos;
+        self.pos = 0;
+        let fields = self.read_wire_values();
+        self.pos = pos;
 
-    /// Write a u64 to the buffer.
-    pub fn write_varint<T: BitCast>(&mut self, val: T) {
-        let mut val = val.to_u64();
+        fields
+    }
 
-        while val >= 0x80 {
-            self.buf.push((val & 0x7f) as u8 | 0x80);
-            val >>= 7;
+    /// Decode fields until the end of the buffer or an `EndGroup` key.
+    fn read_wire_values(&mut self) -> Vec<(u64, WireValue)> {
+        let mut fields = Vec::new();
+        while let Some(field) = self.next_field() {
+            let value = match field.r#type {
+                Type::Varint => WireValue::Varint(self.decode_varint()),
+                Type::Fixed32 => WireValue::Fixed32(self.read_fixed32_bytes()),
+                Type::Fixed64 => WireValue::Fixed64(self.read_fixed64_bytes()),
+                Type::Bytes => WireValue::Bytes(self.read_bytes()),
+                Type::StartGroup => {
+                    self.enter().unwrap_or_else(|err| panic!("{}", err));
+                    let group = self.read_wire_values();
+                    self.depth -= 1;
+                    WireValue::Group(group)
+                }
+                Type::EndGroup => break,
+                Type::None => WireValue::None,
+            };
+            fields.push((field.tag, value));
         }
-        self.buf.push(val as u8);
-    }
 
-    /// Write an i64 to the buffer.
-    pub fn write_s_varint(&mut self, val: i64) {
-        self.write_varint(zigzag(val));
+        fields
     }
 
-    /// Write a fixed size value to the buffer. This will not compress the value.
-    pub fn write_fixed<T>(&mut self, val: T)
-    where
-        T: BitCast,
-    {
-        let size = size_of::<T>();
-        let mut val: u64 = val.to_u64();
-
-        if cfg!(target_endian = "big") {
-            val = val.swap_bytes();
+    /// Scan forward to the next field with `target_tag`, skipping the values of every other
+    /// field. Returns the field with the position at its value, or `None` with the position
+    /// at the end of the buffer if no such field follows.
+    pub fn seek_to_field(&mut self, target_tag: u64) -> Option<Field> {
+        while let Some(field) = self.next_field() {
+            if field.tag == target_tag {
+                return Some(field);
+            }
+            self.skip(field.r#type);
         }
 
-        let mut n = 0;
-        while n < size {
-            self.buf.push((val >> (n << 3)) as u8);
-            n += 1;
+        None
+    }
+
+    /// Count how many times each tag appears in the top-level fields of the buffer, without
+    /// interpreting their values. Returns `(tag, count)` pairs sorted by tag. The buffer is
+    /// walked from the start and the position is restored afterwards.
+    pub fn field_histogram(&mut self) -> Vec<(u64, usize)> {
+        let pos = self.pos;
+        self.pos = 0;
+        let mut histogram: Vec<(u64, usize)> = Vec::new();
+        while let Some(field) = self.next_field() {
+            match histogram.binary_search_by_key(&field.tag, |&(tag, _)| tag) {
+                Ok(i) => histogram[i].1 += 1,
+                Err(i) => histogram.insert(i, (field.tag, 1)),
+            }
+            self.skip(field.r#type);
         }
+        self.pos = pos;
+
+        histogram
     }
 
-    /// write a field of "tag" and "type" to the buffer.
-    pub fn write_field(&mut self, tag: u64, r#type: Type) {
-        let b: u64 = (tag << 3) | Into::<u64>::into(r#type);
-        self.write_varint(b);
+    /// Render the top-level fields of the buffer as text, one line per field:
+    /// `tag=<n> type=<Type> value=<...>`. Varints are shown as integers, fixed values as hex,
+    /// and length-delimited values (including nested messages, which are not interpreted) as
+    /// their length plus a hex preview of the first 16 bytes. Malformed data ends the dump with
+    /// an `error=` line. The buffer is walked from the start and the position is restored.
+    pub fn debug_dump(&mut self) -> String {
+        let pos = self.pos;
+        self.pos = 0;
+        let mut out = String::new();
+        while self.pos < self.len() {
+            if let Err(err) = self.try_dump_field(&mut out) {
+                out.push_str(&format!("error={}\n", err));
+                break;
+            }
+        }
+        self.pos = pos;
+
+        out
     }
 
-    /// write a tag with the size of the buffer to be appended to the internal buffer.
-    pub fn write_length_varint(&mut self, tag: u64, val: usize) {
-        self.write_field(tag, Type::Bytes);
-        self.write_varint(val);
+    /// Render the next field for `debug_dump`.
+    fn try_dump_field(&mut self, out: &mut String) -> Result<(), PbfError> {
+        let field = self.try_read_field()?;
+        let value = match field.r#type {
+            Type::Varint => self.try_decode_varint()?.to_string(),
+            Type::Fixed32 => format!("0x{:08x}", self.try_read_fixed::<u32>()?),
+            Type::Fixed64 => format!("0x{:016x}", self.try_read_fixed::<u64>()?),
+            Type::Bytes => {
+                let len = self.try_decode_varint()? as usize;
+                let Some(bytes) = self.buf.get(self.pos..self.pos.saturating_add(len)) else {
+                    return Err(PbfError::UnexpectedEof);
+                };
+                let mut value = format!("len={}", len);
+                if len > 0 {
+                    value.push(' ');
+                }
+                for b in bytes.iter().take(16) {
+                    value.push_str(&format!("{:02x}", b));
+                }
+                if len > 16 {
+                    value.push_str("..");
+                }
+                self.pos += len;
+                value
+            }
+            Type::StartGroup => {
+                let start = self.pos;
+                self.try_skip_group(Some(field.tag))?;
+                format!("len={}", self.pos - start)
+            }
+            Type::EndGroup | Type::None => "-".to_string(),
+        };
+        out.push_str(&format!("tag={} type={:?} value={}\n", field.tag, field.r#type, value));
+
+        Ok(())
     }
 
-    /// write a variable sized number, bool, or enum into to the buffer.
-    pub fn write_varint_field<T>(&mut self, tag: u64, val: T)
-    where
-        T: BitCast,
-    {
-        self.write_field(tag, Type::Varint);
-        self.write_varint(val);
+    /// Read the next field from the buffer without advancing the position.
+    pub fn peek_field(&mut self) -> Field {
+        let pos = self.pos;
+        let field = self.read_field();
+        self.pos = pos;
+
+        field
     }
 
-    /// write a signed variable sized number into to the buffer.
-    pub fn write_s_varint_field<T>(&mut self, tag: u64, val: T)
+    /// Read the value of a `Type::None` field. This is a no-op since the type carries no value,
+    /// it exists so readers of unit enum variants state that intent explicitly.
+    pub fn read_none(&mut self) {}
+
+    /// Read in bytes from the buffer.
+    pub fn read_bytes(&mut self) -> Vec<u8> {
+        self.read_bytes_ref().to_vec()
+    }
+
+    /// Read in bytes from the buffer without copying them.
+    /// The returned slice borrows directly from the internal buffer.
+    pub fn read_bytes_ref(&mut self) -> &[u8] {
+        let len = self.decode_varint() as usize;
+        let start = self.pos;
+        self.pos += len;
+
+        &self.buf[start..self.pos]
+    }
+
+    /// Read in a fixed size byte array from the buffer without allocating.
+    ///
+    /// # Panics
+    /// Panics if the length prefix is not exactly `N` or the buffer is truncated.
+    pub fn read_array<const N: usize>(&mut self) -> [u8; N] {
+        self.try_read_array().unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    /// Read in a fixed size byte array from the buffer without allocating.
+    /// Returns an error if the length prefix is not exactly `N` or the buffer is truncated.
+    /// The position is left untouched on error.
+    pub fn try_read_array<const N: usize>(&mut self) -> Result<[u8; N], PbfError> {
+        let pos = self.pos;
+        let len = self.try_decode_varint()? as usize;
+        if len != N {
+            self.pos = pos;
+            return Err(PbfError::LengthMismatch { expected: N, found: len });
+        }
+        let Some(bytes) = self.buf.get(self.pos..self.pos + N) else {
+            self.pos = pos;
+            return Err(PbfError::UnexpectedEof);
+        };
+        let mut res = [0; N];
+        res.copy_from_slice(bytes);
+        self.pos += N;
+
+        Ok(res)
+    }
+
+    /// Read in a string from the buffer.
+    /// Invalid UTF-8 sequences are replaced with `U+FFFD`, just like `read_string_lossy`.
+    pub fn read_string(&mut self) -> String {
+        self.read_string_lossy()
+    }
+
+    /// Read in a string from the buffer, replacing invalid UTF-8 sequences with `U+FFFD`
+    /// so data with encoding errors can still be processed.
+    pub fn read_string_lossy(&mut self) -> String {
+        String::from_utf8_lossy(self.read_bytes_ref()).into_owned()
+    }
+
+    /// Read in a string from the buffer.
+    /// Errors with `InvalidUtf8` if it is not valid UTF-8, or `UnexpectedEof` if it is
+    /// truncated. The position is left untouched on error.
+    pub fn try_read_string(&mut self) -> Result<String, PbfError> {
+        let pos = self.pos;
+        let len = self.try_decode_varint()? as usize;
+        let res = match self.buf.get(self.pos..self.pos.saturating_add(len)) {
+            None => Err(PbfError::UnexpectedEof),
+            Some(bytes) => String::from_utf8(bytes.to_vec()).map_err(|_| PbfError::InvalidUtf8),
+        };
+        self.pos = if res.is_ok() { self.pos + len } else { pos };
+
+        res
+    }
+
+    /// Read in a string from the buffer into `out` without allocating, returning its length
+    /// in bytes. Errors with `LengthMismatch` if the string is longer than `out`, or with
+    /// `InvalidUtf8` if it is not valid UTF-8. The position is left untouched on error.
+    pub fn read_string_into(&mut self, out: &mut [u8]) -> Result<usize, PbfError> {
+        let pos = self.pos;
+        let len = self.try_decode_varint()? as usize;
+        let res = match self.buf.get(self.pos..self.pos.saturating_add(len)) {
+            None => Err(PbfError::UnexpectedEof),
+            Some(_) if len > out.len() => {
+                Err(PbfError::LengthMismatch { expected: out.len(), found: len })
+            }
+            Some(bytes) if core::str::from_utf8(bytes).is_err() => Err(PbfError::InvalidUtf8),
+            Some(bytes) => {
+                out[..len].copy_from_slice(bytes);
+                Ok(len)
+            }
+        };
+        self.pos = if res.is_ok() { self.pos + len } else { pos };
+
+        res
+    }
+
+    /// Read in a fixed size value from the buffer.
+    ///
+    /// # Panics
+    /// Panics if fewer than `size_of::<T>()` bytes remain in the buffer.
+    pub fn read_fixed<T>(&mut self) -> T
     where
-        T: Into<i64>,
+        T: Fixed,
     {
-        self.write_field(tag, Type::Varint);
-        self.write_s_varint(val.into());
+        self.try_read_fixed().unwrap_or_else(|err| panic!("{}", err))
     }
 
-    /// write a vector packed variable sized number, bool, or enum into to the buffer.
-    pub fn write_packed_varint<T>(&mut self, tag: u64, val: &[T])
+    /// Read in a fixed size value from the buffer.
+    /// Returns an error if fewer than `size_of::<T>()` bytes remain in the buffer.
+    pub fn try_read_fixed<T>(&mut self) -> Result<T, PbfError>
     where
-        T: BitCast + Copy,
+        T: Fixed,
     {
-        let mut pbf = Protobuf::new();
+        self.try_read_fixed_sized(size_of::<T>()).map(T::from_u64)
+    }
 
-        for &v in val {
-            pbf.write_varint::<T>(v);
+    /// Read in `size` bytes as a fixed size value.
+    fn try_read_fixed_sized(&mut self, size: usize) -> Result<u64, PbfError> {
+        let Some(bytes) = self.buf.get(self.pos..self.pos + size) else {
+            return Err(PbfError::UnexpectedEof);
+        };
+
+        let mut val: u64 = 0;
+        for (n, &b) in bytes.iter().enumerate() {
+            val |= (b as u64) << (n << 3);
+        }
+        self.pos += size;
+
+        if cfg!(target_endian = "big") {
+            val = val.swap_bytes();
         }
 
-        self.write_bytes_field(tag, &(pbf.take()));
+        Ok(val)
     }
 
-    /// write a vector packed signed variable sized number into to the buffer.
-    pub fn write_packed_s_varint<T>(&mut self, tag: u64, val: &[T])
+    /// Read in a 16-bit value written by `write_fixed16_field`.
+    ///
+    /// # Panics
+    /// Panics if the length prefix is not exactly 2 or the buffer is truncated.
+    pub fn read_fixed16(&mut self) -> u16 {
+        u16::from_le_bytes(self.read_array())
+    }
+
+    /// Read in the raw 4 bytes of a fixed32 value, in wire (little-endian) order.
+    ///
+    /// # Panics
+    /// Panics if fewer than 4 bytes remain in the buffer.
+    pub fn read_fixed32_bytes(&mut self) -> [u8; 4] {
+        self.try_read_raw().unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    /// Read in the raw 8 bytes of a fixed64 value, in wire (little-endian) order.
+    ///
+    /// # Panics
+    /// Panics if fewer than 8 bytes remain in the buffer.
+    pub fn read_fixed64_bytes(&mut self) -> [u8; 8] {
+        self.try_read_raw().unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    /// Copy the next `N` bytes out of the buffer.
+    fn try_read_raw<const N: usize>(&mut self) -> Result<[u8; N], PbfError> {
+        let Some(bytes) = self.buf.get(self.pos..self.pos + N) else {
+            return Err(PbfError::UnexpectedEof);
+        };
+        let mut res = [0; N];
+        res.copy_from_slice(bytes);
+        self.pos += N;
+
+        Ok(res)
+    }
+
+    /// Read in a fixed size value stored in big-endian byte order, regardless of the host.
+    /// The field-level helpers always use the protobuf standard little-endian order.
+    ///
+    /// # Panics
+    /// Panics if fewer than `size_of::<T>()` bytes remain in the buffer.
+    pub fn read_fixed_be<T>(&mut self) -> T
     where
-        T: Into<i64> + Copy,
+        T: Fixed,
     {
-        let mut pbf = Protobuf::new();
+        self.try_read_fixed_ordered(size_of::<T>(), true)
+            .map(T::from_u64)
+            .unwrap_or_else(|err| panic!("{}", err))
+    }
 
-        for &v in val {
-            pbf.write_s_varint(v.into());
-        }
+    /// Read in a fixed size value stored in little-endian byte order, regardless of the host.
+    ///
+    /// # Panics
+    /// Panics if fewer than `size_of::<T>()` bytes remain in the buffer.
+    pub fn read_fixed_le<T>(&mut self) -> T
+    where
+        T: Fixed,
+    {
+        self.try_read_fixed_ordered(size_of::<T>(), false)
+            .map(T::from_u64)
+            .unwrap_or_else(|err| panic!("{}", err))
+    }
 
-        self.write_bytes_field(tag, &(pbf.take()));
+    /// Read in a fixed float, replacing any NaN with `f32::NAN` so that decoded values
+    /// compare, dedup and hash by their bits deterministically.
+    ///
+    /// # Panics
+    /// Panics if fewer than 4 bytes remain in the buffer.
+    pub fn read_fixed_f32_canonical(&mut self) -> f32 {
+        let val: f32 = self.read_fixed();
+        if val.is_nan() { f32::NAN } else { val }
     }
 
-    /// write a fixed sized number into to the buffer. No compression is done.
-    /// Supports 32 and 64 bit numbers.
+    /// Read in a fixed double, replacing any NaN with `f64::NAN` so that decoded values
+    /// compare, dedup and hash by their bits deterministically.
     ///
     /// # Panics
-    /// Panics if the size of the type is not 32 or 64 bits.
-    pub fn write_fixed_field<T>(&mut self, tag: u64, val: T)
+    /// Panics if fewer than 8 bytes remain in the buffer.
+    pub fn read_fixed_f64_canonical(&mut self) -> f64 {
+        let val: f64 = self.read_fixed();
+        if val.is_nan() { f64::NAN } else { val }
+    }
+
+    /// Read in `size` bytes in an explicit byte order.
+    fn try_read_fixed_ordered(&mut self, size: usize, big_endian: bool) -> Result<u64, PbfError> {
+        let Some(bytes) = self.buf.get(self.pos..self.pos + size) else {
+            return Err(PbfError::UnexpectedEof);
+        };
+
+        let val = if big_endian {
+            bytes.iter().fold(0, |val, &b| (val << 8) | b as u64)
+        } else {
+            bytes.iter().rev().fold(0, |val, &b| (val << 8) | b as u64)
+        };
+        self.pos += size;
+
+        Ok(val)
+    }
+
+    /// Read in a variable size value from the buffer.
+    pub fn read_varint<T>(&mut self) -> T
     where
-        T: BitCast + Copy,
+        T: BitCast,
     {
-        let type_ = match size_of::<T>() {
-            4 => Type::Fixed32,
-            8 => Type::Fixed64,
-            _ => panic!("Invalid fixed type"),
-        };
+        let val = self.decode_varint();
+        T::from_u64(val)
+    }
 
-        self.write_field(tag, type_);
-        self.write_fixed(val);
+    /// Read in a variable size value from the buffer for a type where not every value is
+    /// valid, such as an enum. Returns an error and leaves the position untouched if the
+    /// varint does not represent a value of `T`.
+    pub fn try_read_varint<T>(&mut self) -> Result<T, PbfError>
+    where
+        T: TryBitCast,
+    {
+        let pos = self.pos;
+        let val = self.try_decode_varint()?;
+        T::try_from_u64(val).ok_or_else(|| {
+            self.pos = pos;
+            PbfError::InvalidValue(val)
+        })
     }
 
-    /// write only the string to the buffer
-    pub fn write_string(&mut self, val: &str) {
-        self.write_varint(val.len());
-        self.buf.extend_from_slice(val.as_bytes());
+    /// Read in an enum from the buffer.
+    ///
+    /// # Panics
+    /// Panics if `BitCast::from_u64` does (derived enums panic on unknown discriminants).
+    pub fn read_enum<E: BitCast>(&mut self) -> E {
+        self.read_varint()
     }
 
-    /// write a string into to the buffer.
-    pub fn write_string_field(&mut self, tag: u64, val: &str) {
-        self.write_length_varint(tag, val.len());
-        self.buf.extend_from_slice(val.as_bytes());
+    /// Read in an enum from the buffer, falling back to `default` for discriminants that
+    /// `E` does not know about. Useful for enums that may gain variants in newer schemas.
+    pub fn read_enum_or<E: TryBitCast>(&mut self, default: E) -> E {
+        E::try_from_u64(self.decode_varint()).unwrap_or(default)
     }
 
-    /// write a byte array into to the buffer.
-    pub fn write_bytes_field(&mut self, tag: u64, val: &[u8]) {
-        self.write_length_varint(tag, val.len());
-        self.buf.extend_from_slice(val)
+    /// Read in a bool from the buffer. Any nonzero value is read as `true`.
+    pub fn read_bool(&mut self) -> bool {
+        self.decode_varint() != 0
     }
 
-    /// write a message into to the buffer.
-    /// The message must implement the ProtoWrite trait.
-    /// This is usually reserved for structs and enums.
-    pub fn write_message<T: ProtoWrite>(&mut self, tag: u64, t: &T) {
-        let mut pbf = Protobuf::new();
-        t.write(&mut pbf);
-        let bytes = pbf.take();
-        self.write_bytes_field(tag, &bytes);
+    /// Read in a bool from the buffer, accepting only the canonical values 0 and 1.
+    /// Returns an error and leaves the position untouched for any other value.
+    pub fn try_read_bool(&mut self) -> Result<bool, PbfError> {
+        let pos = self.pos;
+        match self.try_decode_varint()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            val => {
+                self.pos = pos;
+                Err(PbfError::InvalidBool(val))
+            }
+        }
     }
 
-    /// write a collection of fields into to the buffer.
-    /// The collection must implement the ProtoWrite trait.
-    /// This is usually reserved for top level structs and enums.
-    pub fn write_fields<T: ProtoWrite>(&mut self, t: &T) {
-        t.write(self);
+    /// Read in a signed variable size value from the buffer.
+    ///
+    /// # Panics
+    /// Panics if the conversion from `i64` to `T` fails.
+    pub fn read_s_varint<T>(&mut self) -> T
+    where
+        T: TryFrom<i64>,
+    {
+        T::try_from(zagzig(self.decode_varint()))
+            .unwrap_or_else(|_| panic!("read_s_varint: Invalid conversion"))
     }
 
-    /// When done writing to the buffer, call this function to take ownership
-    pub fn take(&mut self) -> Vec<u8> {
-        core::mem::take(&mut self.buf)
+    /// Read in a signed variable size value from the buffer.
+    /// Returns an error and leaves the position untouched if the value does not fit in `T`.
+    pub fn try_read_s_varint<T>(&mut self) -> Result<T, PbfError>
+    where
+        T: TryFrom<i64>,
+    {
+        let pos = self.pos;
+        let val = self.try_decode_varint()?;
+        T::try_from(zagzig(val)).map_err(|_| {
+            self.pos = pos;
+            PbfError::InvalidValue(val)
+        })
     }
-}
 
-/// convert a signed integer to an unsigned integer using zigzag encoding.
-pub fn zigzag(val: i64) -> u64 {
-    ((val << 1) ^ (val >> 63)) as u64
-}
+    /// Read in a signed variable size value from the buffer,
+    /// clamping it to `T::MIN`/`T::MAX` if it does not fit in `T`.
+    pub fn read_s_varint_saturating<T>(&mut self) -> T
+    where
+        T: FromI64Saturating,
+    {
+        T::from_i64_saturating(zagzig(self.decode_varint()))
+    }
 
-/// convert an unsigned integer to a signed integer using zigzag decoding.
-pub fn zagzig(val: u64) -> i64 {
-    (val >> 1) as i64 ^ -((val & 1) as i64)
-}
+    /// Read in a packed value from the buffer.
+    pub fn read_packed<T>(&mut self) -> Vec<T>
+    where
+        T: BitCast,
+    {
+        let mut res: Vec<T> = Vec::new();
+        self.read_packed_append(&mut res);
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use alloc::vec;
+        res
+    }
 
-    #[test]
-    fn it_works() {
-        let buf = vec![];
-        let pb = Protobuf::from_input(buf);
-        assert_eq!(pb.pos, 0);
+    /// Read in a packed block of lengths, such as an index of message sizes.
+    pub fn read_packed_lengths(&mut self) -> Vec<usize> {
+        self.read_packed::<u64>().into_iter().map(|len| len as usize).collect()
     }
 
-    #[test]
-    fn test_zigzag() {
-        assert_eq!(zigzag(0), 0);
-        assert_eq!(zagzig(0), 0);
-        assert_eq!(zagzig(zigzag(0)), 0);
-        assert_eq!(zagzig(zigzag(5)), 5);
-        assert_eq!(zagzig(zigzag(-5)), -5);
+    /// Split the rest of the buffer, a concatenation of length-delimited messages, into the
+    /// `(offset, length)` of each message body without copying it. Set the position to an
+    /// offset and read `length` bytes to access a message lazily.
+    /// The position is left at the end of the buffer.
+    ///
+    /// # Panics
+    /// Panics if a length prefix is malformed or a message runs past the end of the buffer.
+    pub fn split_messages(&mut self) -> Vec<(usize, usize)> {
+        let mut res = Vec::new();
+        while self.pos < self.len() {
+            let len = self.decode_varint() as usize;
+            if len > self.remaining() {
+                panic!("{}", PbfError::UnexpectedEof);
+            }
+            res.push((self.pos, len));
+            self.pos += len;
+        }
 
-        let max_i64 = i64::MAX;
-        let min_i64 = i64::MIN;
-        assert_eq!(zagzig(zigzag(max_i64)), max_i64);
-        assert_eq!(zagzig(zigzag(min_i64)), min_i64);
+        res
     }
 
-    #[test]
-    #[should_panic(expected = "Invalid value for Type")]
-    fn test_write_field_panic() {
-        let _t: Type = Type::from(22);
+    /// Read in a packed value from the buffer lazily: the length prefix is read now and each
+    /// element is decoded as the returned iterator is advanced, without building a `Vec`.
+    /// Drain the iterator to move past the whole field.
+    pub fn packed_iter<T: BitCast>(&mut self) -> PackedIter<'_, T> {
+        let end = self.decode_varint() as usize + self.pos;
+        PackedIter { pbf: self, end, _marker: PhantomData }
     }
 
-    #[test]
-    #[should_panic(expected = "EOF")]
-    fn test_read_past_end_panic() {
-        let mut pb = Protobuf::new();
-        pb.write_varint(1);
-        pb.write_varint(300);
+    /// Read in a packed value from the buffer, appending the elements to `out` instead of
+    /// returning a new `Vec`. Reading several occurrences of a field into the same `Vec`
+    /// concatenates them, as protobuf merge semantics require for repeated fields.
+    pub fn read_packed_append<T>(&mut self, out: &mut Vec<T>)
+    where
+        T: BitCast,
+    {
+        let end = self.decode_varint() as usize + self.pos;
+        while self.pos < end {
+            out.push(self.read_varint::<T>());
+        }
+    }
+
+    /// Read in a packed value from the buffer, converting each raw varint with `f`.
+    /// Elements for which `f` returns `None` are dropped. This lets packed enums handle
+    /// discriminants they do not know about, where `BitCast::from_u64` would panic.
+    pub fn read_packed_with<T, F>(&mut self, mut f: F) -> Vec<T>
+    where
+        F: FnMut(u64) -> Option<T>,
+    {
+        let end = self.decode_varint() as usize + self.pos;
+        let mut res: Vec<T> = Vec::new();
+        while self.pos < end {
+            if let Some(val) = f(self.decode_varint()) {
+                res.push(val);
+            }
+        }
+
+        res
+    }
+
+    /// Read in a signed packed value from the buffer.
+    pub fn read_s_packed<T>(&mut self) -> Vec<T>
+    where
+        T: TryFrom<i64>,
+    {
+        let end = self.decode_varint() as usize + self.pos;
+        let mut res: Vec<T> = Vec::new();
+        while self.pos < end {
+            res.push(self.read_s_varint::<T>());
+        }
+
+        res
+    }
+
+    /// Read in a packed value from the buffer into `out` without allocating.
+    /// Returns the number of values written. If `out` fills up before the field ends, the
+    /// remaining values are skipped so the position still lands after the field; a return
+    /// value equal to `out.len()` may therefore mean values were dropped.
+    pub fn read_packed_into<T>(&mut self, out: &mut [T]) -> usize
+    where
+        T: BitCast,
+    {
+        let end = self.decode_varint() as usize + self.pos;
+        let mut count = 0;
+        for slot in out.iter_mut() {
+            if self.pos >= end {
+                break;
+            }
+            *slot = self.read_varint::<T>();
+            count += 1;
+        }
+        self.pos = end;
+
+        count
+    }
+
+    /// Read exactly `n` variable size values from the current position.
+    /// Unlike `read_packed` no length prefix is expected, the count is usually carried
+    /// in a separate field.
+    pub fn read_packed_n<T>(&mut self, n: usize) -> Vec<T>
+    where
+        T: BitCast,
+    {
+        (0..n).map(|_| self.read_varint::<T>()).collect()
+    }
+
+    /// Read exactly `n` signed variable size values from the current position.
+    /// Unlike `read_s_packed` no length prefix is expected, the count is usually carried
+    /// in a separate field.
+    pub fn read_s_packed_n<T>(&mut self, n: usize) -> Vec<T>
+    where
+        T: TryFrom<i64>,
+    {
+        (0..n).map(|_| self.read_s_varint::<T>()).collect()
+    }
+
+    /// Read in a packed fixed size value from the buffer.
+    /// Each element occupies exactly `size_of::<T>()` bytes.
+    pub fn read_packed_fixed<T>(&mut self) -> Vec<T>
+    where
+        T: Fixed,
+    {
+        let end = self.decode_varint() as usize + self.pos;
+        let mut res: Vec<T> = Vec::new();
+        while self.pos < end {
+            res.push(self.read_fixed::<T>());
+        }
+
+        res
+    }
+
+    /// Read in a signed packed fixed size value from the buffer.
+    /// Each element occupies exactly `size_of::<T>()` bytes and is zigzag decoded.
+    ///
+    /// # Panics
+    /// Panics if the conversion from `i64` to `T` fails.
+    pub fn read_s_packed_fixed<T>(&mut self) -> Vec<T>
+    where
+        T: Fixed + TryFrom<i64>,
+    {
+        let size = size_of::<T>();
+        let end = self.decode_varint() as usize + self.pos;
+        let mut res: Vec<T> = Vec::new();
+        while self.pos < end {
+            let val = self.try_read_fixed_sized(size).unwrap_or_else(|err| panic!("{}", err));
+            res.push(
+                T::try_from(zagzig(val))
+                    .unwrap_or_else(|_| panic!("read_s_packed_fixed: Invalid conversion")),
+            );
+        }
+
+        res
+    }
+
+    /// Read in a proto2 group after its `StartGroup` field has been read, consuming every
+    /// field up to and including the matching `EndGroup`.
+    ///
+    /// # Panics
+    /// Panics with `GroupMismatch` if the `EndGroup` carries another field number than the
+    /// `StartGroup`.
+    pub fn read_group<T: ProtoRead>(&mut self, t: &mut T) {
+        let tag = self.group_tag.take();
+        self.enter().unwrap_or_else(|err| panic!("{}", err));
+        t.init();
+
+        loop {
+            let field = self.read_field();
+            if field.r#type == Type::EndGroup {
+                check_group_end(tag, field.tag).unwrap_or_else(|err| panic!("{}", err));
+                break;
+            }
+            let start_pos = self.pos;
+            t.read(field.tag, self);
+            if start_pos == self.pos {
+                self.skip(field.r#type);
+            }
+        }
+        self.depth -= 1;
+    }
+
+    /// Read a message from the buffer. This is the alternative to `read_message`
+    /// which does the same thing but you may already know the size of the message.
+    /// The other case is top level data may have fields but no message length.
+    ///
+    /// Reading into a target that already holds a message merges into it, as protobuf
+    /// specifies (see `merge_bytes`): scalars present in the new data overwrite, repeated
+    /// fields append. `ProtoRead::init` is not called, so to read top level data into a
+    /// fresh value with its fallbacks use `decode`, or call `init` first.
+    pub fn read_fields<T: ProtoRead>(&mut self, t: &mut T, end: Option<usize>) {
+        let end = end.unwrap_or(self.len());
+
+        while self.pos < end {
+            let field = self.read_field();
+            let start_pos = self.pos;
+            t.read(field.tag, self);
+            if start_pos == self.pos {
+                self.skip(field.r#type);
+            }
+        }
+    }
+
+    /// Read a message from the buffer just like `read_fields`, but check that every value the
+    /// `ProtoRead` implementation reads spans exactly what its wire type says: the bytes of one
+    /// varint, 4 or 8 fixed bytes, or a length prefix and its payload. A `read` that decodes a
+    /// field with the wrong method (for instance `read_string` on a varint) is reported as a
+    /// `WireTypeMismatch` with the position reset to the field's key, instead of silently
+    /// desynchronizing the rest of the message. Groups are not checked.
+    pub fn read_fields_strict<T: ProtoRead>(
+        &mut self,
+        t: &mut T,
+        end: Option<usize>,
+    ) -> Result<(), PbfError> {
+        let end = end.unwrap_or(self.len());
+
+        while self.pos < end {
+            let key_pos = self.pos;
+            let field = self.read_field();
+            let start_pos = self.pos;
+            t.read(field.tag, self);
+            if start_pos == self.pos {
+                self.skip(field.r#type);
+                continue;
+            }
+            let expected = match field.r#type {
+                Type::Varint => self.varint_at(start_pos)?.1,
+                Type::Fixed64 => 8,
+                Type::Fixed32 => 4,
+                Type::Bytes => {
+                    let (len, len_size) = self.varint_at(start_pos)?;
+                    len_size + len as usize
+                }
+                Type::StartGroup | Type::EndGroup | Type::None => continue,
+            };
+            if self.pos - start_pos != expected {
+                let wire_type = Into::<u64>::into(field.r#type) as u8;
+                self.pos = key_pos;
+                return Err(PbfError::WireTypeMismatch { tag: field.tag, wire_type });
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Read a message from the buffer just like `read_fields`, but without any panic path in
+    /// the framing: field keys are read and unconsumed values skipped with the fallible
+    /// primitives, so malformed input (for instance from a fuzzer) returns an error.
+    ///
+    /// The `ProtoRead` implementation must not panic either, so it should only use the
+    /// `try_*` readers. `ProtoRead::read` cannot return their errors: a value it fails to
+    /// read leaves the position untouched and is then skipped by its wire type. The skip
+    /// reports framing errors (`UnexpectedEof`, `VarintOverflow`), but a well framed value
+    /// rejected by its reader (`InvalidUtf8`, `InvalidValue`, `InvalidBool`) is dropped and
+    /// reading continues with the next field.
+    pub fn try_read_fields<T: ProtoRead>(
+        &mut self,
+        t: &mut T,
+        end: Option<usize>,
+    ) -> Result<(), PbfError> {
+        let end = end.unwrap_or(self.len());
+        if end > self.len() {
+            return Err(PbfError::UnexpectedEof);
+        }
+
+        while self.pos < end {
+            let field = self.try_read_field()?;
+            let start_pos = self.pos;
+            t.read(field.tag, self);
+            if start_pos == self.pos {
+                self.try_skip(field.r#type)?;
+            }
+            if self.pos > end {
+                return Err(PbfError::UnexpectedEof);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Read a message from the buffer just like `read_fields`, but also return every tag
+    /// that was dispatched to the `ProtoRead` implementation, once each, in order of first
+    /// appearance. Pair it with `RequiredFields` to detect missing required fields.
+    pub fn read_fields_tracked<T: ProtoRead>(&mut self, t: &mut T, end: Option<usize>) -> Vec<u64> {
+        let end = end.unwrap_or(self.len());
+        let mut seen = Vec::new();
+
+        while self.pos < end {
+            let field = self.read_field();
+            if !seen.contains(&field.tag) {
+                seen.push(field.tag);
+            }
+            let start_pos = self.pos;
+            t.read(field.tag, self);
+            if start_pos == self.pos {
+                self.skip(field.r#type);
+            }
+        }
+
+        seen
+    }
+
+    /// Read a message from the buffer just like `read_fields`, but collect every field
+    /// the `ProtoRead` implementation did not consume instead of discarding it.
+    pub fn read_fields_collecting<T: ProtoRead>(
+        &mut self,
+        t: &mut T,
+        end: Option<usize>,
+    ) -> UnknownFields {
+        let end = end.unwrap_or(self.len());
+        let mut unknown = UnknownFields::default();
+
+        while self.pos < end {
+            let key_pos = self.pos;
+            let field = self.read_field();
+            let start_pos = self.pos;
+            t.read(field.tag, self);
+            if start_pos == self.pos {
+                self.skip(field.r#type);
+                unknown.buf.extend_from_slice(&self.buf[key_pos..self.pos]);
+            }
+        }
+
+        unknown
+    }
+
+    /// Read in an entire message from the buffer.
+    /// This is usually used to read in a struct or enum.
+    ///
+    /// # Panics
+    /// Panics with `RecursionLimitExceeded` if messages are nested deeper than `max_depth`.
+    pub fn read_message<T: ProtoRead>(&mut self, t: &mut T) {
+        let end = self.decode_varint() as usize + self.pos;
+
+        self.enter().unwrap_or_else(|err| panic!("{}", err));
+        t.init();
+        self.read_fields(t, Some(end));
+        self.depth -= 1;
+    }
+
+    /// Read in an entire message from the buffer like `read_message`, with the framing of
+    /// `try_read_fields`. Returns `RecursionLimitExceeded` if messages are nested deeper than
+    /// `max_depth`.
+    pub fn try_read_message<T: ProtoRead>(&mut self, t: &mut T) -> Result<(), PbfError> {
+        self.enter()?;
+        let res = self.try_decode_varint().and_then(|len| {
+            if len as usize > self.remaining() {
+                return Err(PbfError::UnexpectedEof);
+            }
+            let end = self.pos + len as usize;
+            t.init();
+            self.try_read_fields(t, Some(end))
+        });
+        self.depth -= 1;
+
+        res
+    }
+
+    /// Read exactly `len` bytes of message fields from the current position, for when the
+    /// body length is known from elsewhere (such as an index) rather than a length prefix.
+    pub fn read_fields_len<T: ProtoRead>(&mut self, t: &mut T, len: usize) {
+        let end = self.pos + len;
+
+        self.read_fields(t, Some(end));
+    }
+
+    /// Read a length-delimited value, usually an embedded message, into its own `Protobuf`.
+    /// The returned `Protobuf` is positioned at the start of the message body and this
+    /// buffer's position is advanced past it.
+    pub fn read_message_slice(&mut self) -> Protobuf {
+        Protobuf::from_slice(self.read_bytes_ref())
+    }
+
+    /// Read a single protobuf `map` entry written by `write_map_field`.
+    /// A key or value missing from the entry is left at its zero value.
+    pub fn read_map_entry<K, V>(&mut self) -> (K, V)
+    where
+        K: BitCast,
+        V: BitCast,
+    {
+        let end = self.decode_varint() as usize + self.pos;
+        let mut key = 0;
+        let mut value = 0;
+        while self.pos < end {
+            let field = self.read_field();
+            match field.tag {
+                1 => key = self.decode_varint(),
+                2 => value = self.decode_varint(),
+                _ => self.skip(field.r#type),
+            }
+        }
+
+        (K::from_u64(key), V::from_u64(value))
+    }
+
+    // === WRITING =================================================================
+
+    /// Compute the number of bytes `t` writes via `write_fields` without producing any output.
+    ///
+    /// ```rust
+    /// use pbf_core::{ProtoWrite, Protobuf};
+    ///
+    /// struct Point(i32, i32);
+    /// impl ProtoWrite for Point {
+    ///     fn write(&self, pbf: &mut Protobuf) {
+    ///         pbf.write_s_varint_field(1, self.0);
+    ///         pbf.write_s_varint_field(2, self.1);
+    ///     }
+    /// }
+    ///
+    /// let point = Point(-300, 5);
+    /// let mut pbf = Protobuf::with_capacity(Protobuf::encoded_len(&point));
+    /// pbf.write_fields(&point);
+    /// assert_eq!(pbf.take().len(), 5);
+    /// ```
+    pub fn encoded_len<T: ProtoWrite>(t: &T) -> usize {
+        let mut counter = Protobuf { count_only: true, ..Default::default() };
+        t.write(&mut counter);
+
+        counter.counted
+    }
+
+    /// Append a single byte to the buffer, or only count it.
+    fn push(&mut self, byte: u8) {
+        if self.count_only {
+            self.counted += 1;
+        } else {
+            self.buf.push(byte);
+        }
+    }
+
+    /// Append bytes to the buffer, or only count them.
+    fn extend(&mut self, bytes: &[u8]) {
+        if self.count_only {
+            self.counted += bytes.len();
+        } else {
+            self.buf.extend_from_slice(bytes);
+        }
+    }
+
+    /// The number of bytes written so far.
+    fn written(&self) -> usize {
+        if self.count_only { self.counted } else { self.buf.len() }
+    }
+
+    /// Create an empty scratch buffer for a length-delimited value, in the same mode as `self`.
+    fn scratch(&self) -> Protobuf {
+        Protobuf { count_only: self.count_only, ..Default::default() }
+    }
+
+    /// write the contents of a scratch buffer as a length-delimited field.
+    fn write_scratch_field(&mut self, tag: u64, scratch: Protobuf) {
+        if self.count_only {
+            self.write_length_varint(tag, scratch.counted);
+            self.counted += scratch.counted;
+        } else {
+            self.write_bytes_like(tag, &scratch.buf);
+        }
+    }
+
+    /// write a length-delimited field: the key, the length and the payload.
+    /// Room for the whole field is reserved up front so the buffer grows at most once.
+    fn write_bytes_like(&mut self, tag: u64, payload: &[u8]) {
+        let len = payload.len();
+        if !self.count_only {
+            self.buf.reserve(varint_len(tag << 3) + varint_len(len as u64) + len);
+        }
+        self.write_length_varint(tag, len);
+        self.extend(payload);
+    }
+
+    /// Write a u64 to the buffer.
+    pub fn write_varint<T: BitCast>(&mut self, val: T) {
+        let mut val = val.to_u64();
+
+        while val >= 0x80 {
+            self.push((val & 0x7f) as u8 | 0x80);
+            val >>= 7;
+        }
+        self.push(val as u8);
+    }
+
+    /// Write an i64 to the buffer.
+    pub fn write_s_varint(&mut self, val: i64) {
+        self.write_varint(zigzag(val));
+    }
+
+    /// Write a fixed size value to the buffer. This will not compress the value.
+    /// Only `Fixed` types are accepted: 32 or 64 bits, the only fixed widths protobuf knows.
+    pub fn write_fixed<T>(&mut self, val: T)
+    where
+        T: Fixed,
+    {
+        self.write_fixed_sized(val.to_u64(), size_of::<T>());
+    }
+
+    /// Write the lowest `size` bytes of a value as a fixed size value.
+    fn write_fixed_sized(&mut self, mut val: u64, size: usize) {
+        if cfg!(target_endian = "big") {
+            val = val.swap_bytes();
+        }
+
+        let mut n = 0;
+        while n < size {
+            self.push((val >> (n << 3)) as u8);
+            n += 1;
+        }
+    }
+
+    /// Write a fixed size value in big-endian byte order, regardless of the host.
+    /// The field-level helpers always use the protobuf standard little-endian order.
+    pub fn write_fixed_be<T>(&mut self, val: T)
+    where
+        T: Fixed,
+    {
+        let val = val.to_u64();
+        for n in (0..size_of::<T>()).rev() {
+            self.push((val >> (n << 3)) as u8);
+        }
+    }
+
+    /// Write a fixed size value in little-endian byte order, regardless of the host.
+    pub fn write_fixed_le<T>(&mut self, val: T)
+    where
+        T: Fixed,
+    {
+        let val = val.to_u64();
+        for n in 0..size_of::<T>() {
+            self.push((val >> (n << 3)) as u8);
+        }
+    }
+
+    /// write a field of "tag" and "type" to the buffer.
+    pub fn write_field(&mut self, tag: u64, r#type: Type) {
+        let b: u64 = (tag << 3) | Into::<u64>::into(r#type);
+        self.write_varint(b);
+    }
+
+    /// Start a field at `tag` whose value is written by the returned `FieldWriter`.
+    /// Each value method hands the `Protobuf` back, so fields can be chained fluently.
+    ///
+    /// ```rust
+    /// use pbf_core::Protobuf;
+    ///
+    /// let mut fluent = Protobuf::new();
+    /// fluent.field(1).varint(5_u32).field(2).string("x").field(3).s_varint(-1);
+    ///
+    /// let mut statements = Protobuf::new();
+    /// statements.write_varint_field(1, 5_u32);
+    /// statements.write_string_field(2, "x");
+    /// statements.write_s_varint_field(3, -1);
+    ///
+    /// assert_eq!(fluent.take(), statements.take());
+    /// ```
+    pub fn field(&mut self, tag: u64) -> FieldWriter<'_> {
+        FieldWriter { pbf: self, tag }
+    }
+
+    /// write a tag with the size of the buffer to be appended to the internal buffer.
+    pub fn write_length_varint(&mut self, tag: u64, val: usize) {
+        self.write_field(tag, Type::Bytes);
+        self.write_varint(val);
+    }
+
+    /// write a variable sized number, bool, or enum into to the buffer.
+    pub fn write_varint_field<T>(&mut self, tag: u64, val: T)
+    where
+        T: BitCast,
+    {
+        self.write_field(tag, Type::Varint);
+        self.write_varint(val);
+    }
+
+    /// write an enum into to the buffer.
+    pub fn write_enum_field<E: BitCast>(&mut self, tag: u64, e: &E) {
+        self.write_field(tag, Type::Varint);
+        self.write_varint(e.to_u64());
+    }
+
+    /// write a signed variable sized number into to the buffer.
+    pub fn write_s_varint_field<T>(&mut self, tag: u64, val: T)
+    where
+        T: Into<i64>,
+    {
+        self.write_field(tag, Type::Varint);
+        self.write_s_varint(val.into());
+    }
+
+    /// write a vector of variable sized numbers, bools, or enums unpacked: one key and value
+    /// per element (proto2 repeated), as opposed to `write_packed_varint`.
+    pub fn write_repeated_varint<T>(&mut self, tag: u64, vals: &[T])
+    where
+        T: BitCast + Copy,
+    {
+        for &v in vals {
+            self.write_varint_field(tag, v);
+        }
+    }
+
+    /// write a vector packed variable sized number, bool, or enum into to the buffer.
+    pub fn write_packed_varint<T>(&mut self, tag: u64, val: &[T])
+    where
+        T: BitCast + Copy,
+    {
+        let mut pbf = self.scratch();
+
+        for &v in val {
+            pbf.write_varint::<T>(v);
+        }
+
+        self.write_scratch_field(tag, pbf);
+    }
+
+    /// write a vector packed signed variable sized number into to the buffer.
+    pub fn write_packed_s_varint<T>(&mut self, tag: u64, val: &[T])
+    where
+        T: Into<i64> + Copy,
+    {
+        let mut pbf = self.scratch();
+
+        for &v in val {
+            pbf.write_s_varint(v.into());
+        }
+
+        self.write_scratch_field(tag, pbf);
+    }
+
+    /// write a vector packed fixed sized number into to the buffer. No compression is done.
+    /// Each element is written with exactly `size_of::<T>()` bytes.
+    pub fn write_packed_fixed<T>(&mut self, tag: u64, val: &[T])
+    where
+        T: Fixed + Copy,
+    {
+        let mut pbf = self.scratch();
+
+        for &v in val {
+            pbf.write_fixed::<T>(v);
+        }
+
+        self.write_scratch_field(tag, pbf);
+    }
+
+    /// write a vector packed signed fixed sized number into to the buffer.
+    /// Each element is zigzag encoded and written with exactly `size_of::<T>()` bytes.
+    pub fn write_packed_s_fixed<T>(&mut self, tag: u64, val: &[T])
+    where
+        T: Fixed + Into<i64> + Copy,
+    {
+        let size = size_of::<T>();
+        let mut pbf = self.scratch();
+
+        for &v in val {
+            pbf.write_fixed_sized(zigzag(v.into()), size);
+        }
+
+        self.write_scratch_field(tag, pbf);
+    }
+
+    /// write a fixed sized number into to the buffer. No compression is done.
+    /// Supports 32 and 64 bit numbers.
+    pub fn write_fixed_field<T>(&mut self, tag: u64, val: T)
+    where
+        T: Fixed + Copy,
+    {
+        let type_ = if size_of::<T>() == 4 { Type::Fixed32 } else { Type::Fixed64 };
+
+        self.write_field(tag, type_);
+        self.write_fixed(val);
+    }
+
+    /// write only the string to the buffer
+    pub fn write_string(&mut self, val: &str) {
+        self.write_varint(val.len());
+        self.extend(val.as_bytes());
+    }
+
+    /// write a string into to the buffer.
+    pub fn write_string_field(&mut self, tag: u64, val: &str) {
+        self.write_bytes_like(tag, val.as_bytes());
+    }
+
+    /// write a byte array into to the buffer.
+    pub fn write_bytes_field(&mut self, tag: u64, val: &[u8]) {
+        self.write_bytes_like(tag, val);
+    }
+
+    /// write a 16-bit value (such as the bits of a half float) into to the buffer.
+    /// Protobuf has no 2-byte wire type, so it is framed as a `Type::Bytes` field of length 2
+    /// holding the value in little-endian order.
+    pub fn write_fixed16_field(&mut self, tag: u64, bits: u16) {
+        self.write_array_field(tag, &bits.to_le_bytes());
+    }
+
+    /// write a fixed size byte array into to the buffer.
+    pub fn write_array_field<const N: usize>(&mut self, tag: u64, val: &[u8; N]) {
+        self.write_bytes_field(tag, val);
+    }
+
+    /// write a message into to the buffer.
+    /// The message must implement the ProtoWrite trait.
+    /// This is usually reserved for structs and enums.
+    pub fn write_message<T: ProtoWrite>(&mut self, tag: u64, t: &T) {
+        let mut pbf = self.scratch();
+        t.write(&mut pbf);
+        self.write_scratch_field(tag, pbf);
+    }
+
+    /// write a message built by a closure into to the buffer.
+    /// `f` writes the message body into a fresh `Protobuf`, which is then framed with the
+    /// tag and its length. Handy for one-off nested messages that have no `ProtoWrite` type.
+    ///
+    /// ```rust
+    /// use pbf_core::{Protobuf, Type};
+    ///
+    /// let mut pb = Protobuf::new();
+    /// pb.write_message_closure(1, |inner| {
+    ///     inner.write_varint_field(1, 7_u32);
+    ///     inner.write_string_field(2, "point");
+    /// });
+    ///
+    /// let mut pb = Protobuf::from_input(pb.take());
+    /// let field = pb.read_field();
+    /// assert_eq!((field.tag, field.r#type), (1, Type::Bytes));
+    /// let end = pb.decode_varint() as usize + pb.get_pos();
+    /// pb.read_field();
+    /// assert_eq!(pb.read_varint::<u32>(), 7);
+    /// pb.read_field();
+    /// assert_eq!(pb.read_string(), "point");
+    /// assert_eq!(pb.get_pos(), end);
+    /// ```
+    pub fn write_message_closure<F: FnOnce(&mut Protobuf)>(&mut self, tag: u64, f: F) {
+        let mut pbf = self.scratch();
+        f(&mut pbf);
+        self.write_scratch_field(tag, pbf);
+    }
+
+    /// write a message into to the buffer without allocating a temporary `Protobuf`.
+    /// The message body is written directly into this buffer and its length prefix is
+    /// back-patched afterwards, producing the same bytes as `write_message`.
+    /// The `ProtoWrite` implementation must only append to the buffer.
+    pub fn write_message_inplace<T: ProtoWrite>(&mut self, tag: u64, t: &T) {
+        let mark = self.begin_len_delimited(tag);
+        t.write(self);
+        self.end_len_delimited(mark);
+    }
+
+    /// Start a length-delimited field whose payload is written straight into this buffer:
+    /// the key and a 5 byte length placeholder are written now, and `end_len_delimited`
+    /// fills in the real length. Marks of nested fields must be ended innermost first.
+    ///
+    /// ```rust
+    /// use pbf_core::Protobuf;
+    ///
+    /// let mut pb = Protobuf::new();
+    /// let mark = pb.begin_len_delimited(1);
+    /// pb.write_varint_field(1, 7_u32);
+    /// pb.end_len_delimited(mark);
+    /// assert_eq!(pb.take(), vec![10, 2, 8, 7]);
+    /// ```
+    pub fn begin_len_delimited(&mut self, tag: u64) -> LenMark {
+        self.write_field(tag, Type::Bytes);
+        let start = self.written();
+        if self.count_only {
+            // the final length is added on top once it is known
+            self.counted += 1;
+        } else {
+            self.buf.extend_from_slice(&[0; LEN_PLACEHOLDER]);
+            self.open_marks += 1;
+        }
+
+        LenMark { start }
+    }
+
+    /// Finish a length-delimited field started with `begin_len_delimited`, writing the
+    /// length of everything written since. The lengths of nested marks are only recorded;
+    /// when the outermost mark ends, every placeholder is shrunk to its minimal varint in a
+    /// single pass over the buffer, so the payload is moved once however deep it nests.
+    ///
+    /// # Panics
+    /// Panics if no mark is open (it was already ended, or dropped by `clear`), or if the
+    /// payload is 2^35 bytes or more, the most a 5 byte varint holds.
+    pub fn end_len_delimited(&mut self, mark: LenMark) {
+        if self.count_only {
+            let len = self.counted - mark.start - 1;
+            self.counted += varint_len(len as u64) - 1;
+            return;
+        }
+        assert!(self.open_marks > 0, "end_len_delimited called without an open mark");
+        let len = self.buf.len() - mark.start - LEN_PLACEHOLDER;
+        assert!(len < 1 << 35, "length-delimited field of {} bytes is too large", len);
+        self.ended_marks.push((mark.start, len));
+        self.open_marks -= 1;
+        if self.open_marks == 0 {
+            self.compact_len_placeholders();
+        }
+    }
+
+    /// Replace every recorded placeholder with the minimal varint of its length, shifting
+    /// the bytes in between towards the start of the buffer.
+    fn compact_len_placeholders(&mut self) {
+        let mut marks = core::mem::take(&mut self.ended_marks);
+        marks.sort_unstable_by_key(|&(start, _)| start);
+
+        // a placeholder nested in another shrinks the outer length too: walk from the
+        // innermost outwards, folding the savings of each subtree into its parent
+        let mut shrunk = alloc::vec![(0, 0); marks.len()];
+        let mut subtrees: Vec<(usize, usize)> = Vec::new();
+        for (i, &(start, len)) in marks.iter().enumerate().rev() {
+            let mut inner = 0;
+            while let Some(&(next, saved)) = subtrees.last() {
+                if next >= start + LEN_PLACEHOLDER + len {
+                    break;
+                }
+                inner += saved;
+                subtrees.pop();
+            }
+            let len = len - inner;
+            let saved = LEN_PLACEHOLDER - varint_len(len as u64);
+            shrunk[i] = (len, saved);
+            subtrees.push((start, inner + saved));
+        }
+
+        let mut shift = 0;
+        let mut read = marks[0].0;
+        for (&(start, _), &(len, saved)) in marks.iter().zip(&shrunk) {
+            self.buf.copy_within(read..start, read - shift);
+            let mut at = start - shift;
+            let mut len = len as u64;
+            while len >= 0x80 {
+                self.buf[at] = (len & 0x7f) as u8 | 0x80;
+                len >>= 7;
+                at += 1;
+            }
+            self.buf[at] = len as u8;
+            shift += saved;
+            read = start + LEN_PLACEHOLDER;
+        }
+        let end = self.buf.len();
+        self.buf.copy_within(read..end, read - shift);
+        self.buf.truncate(end - shift);
+    }
+
+    /// write a protobuf `map` into to the buffer. Each entry is written as its own
+    /// length-delimited message with the key at tag 1 and the value at tag 2,
+    /// both as variable sized numbers.
+    pub fn write_map_field<K, V>(&mut self, tag: u64, entries: impl Iterator<Item = (K, V)>)
+    where
+        K: BitCast + Copy,
+        V: BitCast + Copy,
+    {
+        for (key, value) in entries {
+            let mut pbf = self.scratch();
+            pbf.write_varint_field(1, key);
+            pbf.write_varint_field(2, value);
+            self.write_scratch_field(tag, pbf);
+        }
+    }
+
+    /// write pre-encoded bytes into to the buffer as is, without any tag or length framing.
+    /// Useful to splice in cached field sequences or re-emit `UnknownFields`.
+    pub fn write_raw(&mut self, bytes: &[u8]) {
+        self.extend(bytes);
+    }
+
+    /// Move the bytes of `other` onto the end of the buffer, leaving `other` empty.
+    /// Useful to stitch together message parts that were encoded separately.
+    pub fn append(&mut self, other: &mut Protobuf) {
+        if self.count_only {
+            self.counted += other.written();
+        } else {
+            self.buf.append(&mut other.buf);
+        }
+        other.clear();
+    }
+
+    /// write a message as a proto2 group: its fields between a `StartGroup` and an
+    /// `EndGroup` key, both at `tag`, instead of a length prefix.
+    pub fn write_group<T: ProtoWrite>(&mut self, tag: u64, t: &T) {
+        self.write_field(tag, Type::StartGroup);
+        t.write(self);
+        self.write_field(tag, Type::EndGroup);
+    }
+
+    /// write a message prefixed only by its varint length, without a field key.
+    /// Repeated calls produce the length-delimited stream format read by
+    /// `read_length_delimited`.
+    pub fn write_length_delimited<T: ProtoWrite>(&mut self, t: &T) {
+        let mut pbf = self.scratch();
+        t.write(&mut pbf);
+        self.write_varint(pbf.written());
+        if self.count_only {
+            self.counted += pbf.counted;
+        } else {
+            self.buf.extend_from_slice(&pbf.buf);
+        }
+    }
+
+    /// write a collection of fields into to the buffer.
+    /// The collection must implement the ProtoWrite trait.
+    /// This is usually reserved for top level structs and enums.
+    pub fn write_fields<T: ProtoWrite>(&mut self, t: &T) {
+        t.write(self);
+    }
+
+    /// When done writing to the buffer, call this function to take ownership
+    pub fn take(&mut self) -> Vec<u8> {
+        debug_assert_eq!(self.open_marks, 0, "take called with an open len-delimited mark");
+        core::mem::take(&mut self.buf)
+    }
+
+    /// Write every byte buffered so far into `w` and clear the buffer, so writing can continue
+    /// with bounded memory. Returns the number of bytes flushed.
+    /// Flush only between top level fields: a message written with `write_message_inplace`
+    /// must be complete before its bytes leave the buffer.
+    ///
+    /// ```rust
+    /// use pbf_core::Protobuf;
+    ///
+    /// let mut sink = Vec::new();
+    /// let mut pbf = Protobuf::new();
+    /// pbf.write_varint_field(1, 1_u32);
+    /// assert_eq!(pbf.flush_to(&mut sink).unwrap(), 2);
+    /// pbf.write_varint_field(2, 2_u32);
+    /// pbf.flush_to(&mut sink).unwrap();
+    /// assert_eq!(sink, vec![8, 1, 16, 2]);
+    /// ```
+    #[cfg(feature = "std")]
+    pub fn flush_to<W: std::io::Write>(&mut self, w: &mut W) -> std::io::Result<usize> {
+        debug_assert_eq!(self.open_marks, 0, "flush_to called with an open len-delimited mark");
+        w.write_all(&self.buf)?;
+        let len = self.buf.len();
+        self.buf.clear();
+
+        Ok(len)
+    }
+
+    /// View the bytes written so far without taking ownership of the buffer.
+    pub fn as_slice(&self) -> &[u8] {
+        &self.buf
+    }
+
+    /// Copy the bytes written so far, leaving the buffer intact for further writes.
+    pub fn clone_bytes(&self) -> Vec<u8> {
+        self.buf.clone()
+    }
+}
+
+/// The `FieldWriter` struct writes the value of a single field started with `Protobuf::field`.
+/// Every method writes the field with the matching `write_*_field` method and returns the
+/// `Protobuf` to continue the chain.
+#[derive(Debug)]
+pub struct FieldWriter<'a> {
+    pbf: &'a mut Protobuf,
+    tag: u64,
+}
+impl<'a> FieldWriter<'a> {
+    /// write a variable sized number, bool, or enum.
+    pub fn varint<T: BitCast>(self, val: T) -> &'a mut Protobuf {
+        self.pbf.write_varint_field(self.tag, val);
+        self.pbf
+    }
+
+    /// write a signed variable sized number.
+    pub fn s_varint<T: Into<i64>>(self, val: T) -> &'a mut Protobuf {
+        self.pbf.write_s_varint_field(self.tag, val);
+        self.pbf
+    }
+
+    /// write a fixed sized number.
+    pub fn fixed<T: Fixed + Copy>(self, val: T) -> &'a mut Protobuf {
+        self.pbf.write_fixed_field(self.tag, val);
+        self.pbf
+    }
+
+    /// write a string.
+    pub fn string(self, val: &str) -> &'a mut Protobuf {
+        self.pbf.write_string_field(self.tag, val);
+        self.pbf
+    }
+
+    /// write a byte array.
+    pub fn bytes(self, val: &[u8]) -> &'a mut Protobuf {
+        self.pbf.write_bytes_field(self.tag, val);
+        self.pbf
+    }
+
+    /// write a packed vector of variable sized numbers, bools, or enums.
+    pub fn packed_varint<T: BitCast + Copy>(self, val: &[T]) -> &'a mut Protobuf {
+        self.pbf.write_packed_varint(self.tag, val);
+        self.pbf
+    }
+
+    /// write a packed vector of signed variable sized numbers.
+    pub fn packed_s_varint<T: Into<i64> + Copy>(self, val: &[T]) -> &'a mut Protobuf {
+        self.pbf.write_packed_s_varint(self.tag, val);
+        self.pbf
+    }
+
+    /// write a nested message.
+    pub fn message<T: ProtoWrite>(self, t: &T) -> &'a mut Protobuf {
+        self.pbf.write_message(self.tag, t);
+        self.pbf
+    }
+}
+
+/// The `LenMark` struct marks the length placeholder of a field started with
+/// `Protobuf::begin_len_delimited`, to be handed back to `Protobuf::end_len_delimited`.
+#[derive(Debug)]
+#[must_use = "the length is only written by end_len_delimited"]
+pub struct LenMark {
+    start: usize,
+}
+
+/// The `PackedIter` struct lazily decodes the elements of a packed varint field, created by
+/// `Protobuf::packed_iter`. It borrows the `Protobuf` until it is dropped and stops at the end
+/// of the field, leaving the position just past any elements it yielded.
+#[derive(Debug)]
+pub struct PackedIter<'a, T> {
+    pbf: &'a mut Protobuf,
+    end: usize,
+    _marker: PhantomData<T>,
+}
+impl<T: BitCast> Iterator for PackedIter<'_, T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        if self.pbf.pos < self.end { Some(self.pbf.read_varint()) } else { None }
+    }
+}
+
+/// The `VarintDecoder` struct decodes a varint fed one byte at a time, for sources such as
+/// ring buffers where the whole value is not available at once.
+///
+/// ```rust
+/// use pbf_core::VarintDecoder;
+///
+/// let mut decoder = VarintDecoder::new();
+/// assert_eq!(decoder.push(0xac), Ok(None));
+/// assert_eq!(decoder.push(0x02), Ok(Some(300)));
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct VarintDecoder {
+    val: u64,
+    len: usize,
+}
+impl VarintDecoder {
+    /// create a decoder with no bytes accumulated
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// feed the next byte of the varint. Returns the value once the final byte (the one without
+    /// the continuation bit) is pushed, after which the decoder is ready for the next varint.
+    /// Returns `VarintOverflow` (and resets) if the varint does not fit in 64 bits.
+    pub fn push(&mut self, byte: u8) -> Result<Option<u64>, PbfError> {
+        if self.len == 9 && byte > 1 {
+            *self = Self::default();
+            return Err(PbfError::VarintOverflow);
+        }
+        self.val |= ((byte & 0x7f) as u64) << BIT_SHIFT[self.len];
+        self.len += 1;
+        if byte < 0x80 {
+            let val = self.val;
+            *self = Self::default();
+            return Ok(Some(val));
+        }
+
+        Ok(None)
+    }
+
+    /// check if the decoder holds the bytes of an unfinished varint
+    pub fn is_pending(&self) -> bool {
+        self.len > 0
+    }
+}
+
+/// Panic at compile time if a `pbf(oneof)` field's tags overlap the tags of the other fields of
+/// its struct. Called from `const` contexts generated by the derive macros.
+#[doc(hidden)]
+pub const fn assert_oneof_tags(oneof: &[u64], fields: &[u64]) {
+    let mut i = 0;
+    while i < oneof.len() {
+        let mut j = 0;
+        while j < fields.len() {
+            if oneof[i] == fields[j] {
+                panic!("a pbf(oneof) variant tag is already used by another field of the struct");
+            }
+            j += 1;
+        }
+        i += 1;
+    }
+}
+
+/// Check that an `EndGroup` key with tag `found` closes the group started at `expected`.
+/// A group whose `StartGroup` tag is unknown accepts any `EndGroup`.
+fn check_group_end(expected: Option<u64>, found: u64) -> Result<(), PbfError> {
+    match expected {
+        Some(expected) if expected != found => Err(PbfError::GroupMismatch { expected, found }),
+        _ => Ok(()),
+    }
+}
+
+/// get the number of bytes a value occupies once varint encoded.
+pub fn varint_len(val: u64) -> usize {
+    match val {
+        0 => 1,
+        _ => (u64::BITS - val.leading_zeros()).div_ceil(7) as usize,
+    }
+}
+
+/// get the number of bytes a signed value occupies once zigzag and varint encoded.
+pub fn s_varint_len(val: i64) -> usize {
+    varint_len(zigzag(val))
+}
+
+/// encode a message into a new buffer with `write_fields`, returning the bytes.
+///
+/// ```rust
+/// use pbf_core::{ProtoWrite, Protobuf, encode};
+///
+/// struct Point {
+///     x: u32,
+/// }
+/// impl ProtoWrite for Point {
+///     fn write(&self, pbf: &mut Protobuf) {
+///         pbf.write_varint_field(1, self.x);
+///     }
+/// }
+///
+/// assert_eq!(encode(&Point { x: 150 }), vec![0x08, 0x96, 0x01]);
+/// ```
+pub fn encode<T: ProtoWrite>(t: &T) -> Vec<u8> {
+    let mut pb = Protobuf::new();
+    pb.write_fields(t);
+
+    pb.take()
+}
+
+/// decode a message from bytes with `read_fields`, starting from `T::default()`.
+///
+/// # Panics
+/// Panics if the `ProtoRead` implementation panics on malformed input.
+///
+/// ```rust
+/// use pbf_core::{ProtoRead, ProtoWrite, Protobuf, decode, encode};
+///
+/// #[derive(Debug, Default, PartialEq)]
+/// struct Point {
+///     x: u32,
+///     label: String,
+/// }
+/// impl ProtoWrite for Point {
+///     fn write(&self, pbf: &mut Protobuf) {
+///         pbf.write_varint_field(1, self.x);
+///         pbf.write_string_field(2, &self.label);
+///     }
+/// }
+/// impl ProtoRead for Point {
+///     fn read(&mut self, tag: u64, pbf: &mut Protobuf) {
+///         match tag {
+///             1 => self.x = pbf.read_varint(),
+///             2 => self.label = pbf.read_string(),
+///             _ => {}
+///         }
+///     }
+/// }
+///
+/// let point = Point { x: 3, label: "origin".into() };
+/// assert_eq!(decode::<Point>(&encode(&point)), point);
+/// ```
+pub fn decode<T: ProtoRead + Default>(bytes: &[u8]) -> T {
+    let mut t = T::default();
+    t.init();
+    Protobuf::from_input(bytes.to_vec()).read_fields(&mut t, None);
+
+    t
+}
+
+/// Read one length-delimited message from a stream, such as a sequence of length-prefixed
+/// blobs. The varint length is read first, then exactly that many bytes are parsed as a
+/// fresh message, like `read_message`.
+///
+/// Returns `Ok(false)` if the stream ended cleanly before the next message.
+/// A length prefix or body cut short by the end of the stream is an `UnexpectedEof` error.
+#[cfg(feature = "std")]
+pub fn read_length_delimited<R, T>(r: &mut R, t: &mut T) -> std::io::Result<bool>
+where
+    R: std::io::Read,
+    T: ProtoRead,
+{
+    use std::io::{Error, ErrorKind, Read};
+
+    let mut len: u64 = 0;
+    for (i, shift) in BIT_SHIFT.iter().enumerate() {
+        let mut byte = [0_u8];
+        match r.read_exact(&mut byte) {
+            Err(err) if i == 0 && err.kind() == ErrorKind::UnexpectedEof => return Ok(false),
+            res => res?,
+        }
+        len |= ((byte[0] & 0x7f) as u64) << shift;
+        if byte[0] < 0x80 {
+            // grow with the bytes actually read rather than trusting the prefix up front
+            let mut buf = Vec::new();
+            r.by_ref().take(len).read_to_end(&mut buf)?;
+            if buf.len() as u64 != len {
+                return Err(ErrorKind::UnexpectedEof.into());
+            }
+            t.init();
+            Protobuf::from_input(buf).read_fields(t, None);
+            return Ok(true);
+        }
+    }
+
+    Err(Error::new(ErrorKind::InvalidData, PbfError::VarintOverflow))
+}
+
+/// convert a signed integer to an unsigned integer using zigzag encoding.
+pub fn zigzag(val: i64) -> u64 {
+    ((val << 1) ^ (val >> 63)) as u64
+}
+
+/// convert an unsigned integer to a signed integer using zigzag decoding.
+pub fn zagzig(val: u64) -> i64 {
+    (val >> 1) as i64 ^ -((val & 1) as i64)
+}
+
+/// convert a 32-bit signed integer to an unsigned integer using zigzag encoding ("sint32").
+///
+/// For every `i32` this yields the same number as `zigzag(val as i64)`, so sint32 and sint64
+/// values share one encoding on the wire and `write_s_varint_field` needs no width-specific
+/// path. The two only differ on decode: `zagzig32` truncates a value outside the `i32` range
+/// the way protobuf does, where `read_s_varint::<i32>` rejects it.
+pub fn zigzag32(val: i32) -> u32 {
+    ((val << 1) ^ (val >> 31)) as u32
+}
+
+/// convert a 32-bit unsigned integer to a signed integer using zigzag decoding ("sint32").
+pub fn zagzig32(val: u32) -> i32 {
+    (val >> 1) as i32 ^ -((val & 1) as i32)
+}
+
+/// convert an f32 to the bits of an IEEE 754 half precision float, rounding to nearest even.
+/// Values too large for a half float become infinity, NaN stays NaN.
+pub fn f32_to_f16_bits(val: f32) -> u16 {
+    let bits = val.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exp = ((bits >> 23) & 0xff) as i32;
+    let man = bits & 0x7f_ffff;
+
+    if exp == 0xff {
+        let nan = if man != 0 { 0x200 | (man >> 13) as u16 } else { 0 };
+        return sign | 0x7c00 | nan;
+    }
+    // re-bias the exponent from f32 (127) to f16 (15)
+    let exp = exp - 112;
+    if exp >= 0x1f {
+        return sign | 0x7c00;
+    }
+    let (half, rem, halfway) = if exp <= 0 {
+        // subnormal: shift the full 24-bit significand down to a 2^-24 unit
+        if exp < -10 {
+            return sign;
+        }
+        let man = man | 0x80_0000;
+        let shift = (14 - exp) as u32;
+        (man >> shift, man & ((1 << shift) - 1), 1 << (shift - 1))
+    } else {
+        (((exp as u32) << 10) | (man >> 13), man & 0x1fff, 0x1000)
+    };
+    // a carry out of the mantissa correctly bumps the exponent (up to infinity)
+    let round = rem > halfway || (rem == halfway && half & 1 == 1);
+
+    sign | (half + round as u32) as u16
+}
+
+/// convert the bits of an IEEE 754 half precision float to an f32. The conversion is exact.
+pub fn f16_bits_to_f32(bits: u16) -> f32 {
+    let sign = ((bits & 0x8000) as u32) << 16;
+    let exp = ((bits >> 10) & 0x1f) as u32;
+    let man = (bits & 0x3ff) as u32;
+
+    let val = match exp {
+        0 => (man as f32 / (1 << 24) as f32).to_bits(),
+        0x1f => 0x7f80_0000 | (man << 13),
+        _ => ((exp + 112) << 23) | (man << 13),
+    };
+
+    f32::from_bits(sign | val)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    #[test]
+    fn it_works() {
+        let buf = vec![];
+        let pb = Protobuf::from_input(buf);
+        assert_eq!(pb.pos, 0);
+    }
+
+    #[test]
+    fn from_slice() {
+        let bytes: &[u8] = &[8, 5, 18, 2, 104, 105];
+        let mut pb = Protobuf::from_slice(bytes);
+        assert_eq!(pb.len(), 6);
+        assert_eq!(pb.read_field(), Field { tag: 1, r#type: Type::Varint });
+        assert_eq!(pb.read_varint::<u8>(), 5);
+
+        let mut pb: Protobuf = bytes.into();
+        pb.set_pos(2);
+        assert_eq!(pb.read_field(), Field { tag: 2, r#type: Type::Bytes });
+        assert_eq!(pb.read_string(), "hi");
+    }
+
+    #[test]
+    fn with_capacity() {
+        let mut pb = Protobuf::with_capacity(1024);
+        assert_eq!(pb.len(), 0);
+        assert!(pb.is_empty());
+        let ptr = pb.buf.as_ptr();
+        // 204 fields of 5 bytes each fit in the first 1024 bytes
+        for i in 0..204_u32 {
+            pb.write_fixed_field(1, i);
+        }
+        assert_eq!(pb.len(), 1020);
+        assert_eq!(pb.buf.as_ptr(), ptr);
+
+        pb.reserve(2048);
+        assert!(pb.buf.capacity() >= pb.len() + 2048);
+
+        let bytes = pb.take();
+        let mut pb = Protobuf::from_input(bytes);
+        for i in 0..204_u32 {
+            assert_eq!(pb.read_field(), Field { tag: 1, r#type: Type::Fixed32 });
+            assert_eq!(pb.read_fixed::<u32>(), i);
+        }
+    }
+
+    #[test]
+    fn test_zigzag() {
+        assert_eq!(zigzag(0), 0);
+        assert_eq!(zagzig(0), 0);
+        assert_eq!(zagzig(zigzag(0)), 0);
+        assert_eq!(zagzig(zigzag(5)), 5);
+        assert_eq!(zagzig(zigzag(-5)), -5);
+
+        let max_i64 = i64::MAX;
+        let min_i64 = i64::MIN;
+        assert_eq!(zagzig(zigzag(max_i64)), max_i64);
+        assert_eq!(zagzig(zigzag(min_i64)), min_i64);
+    }
+
+    #[test]
+    #[should_panic(expected = "Invalid value for Type")]
+    fn test_write_field_panic() {
+        let _t: Type = Type::from(22);
+    }
+
+    #[test]
+    fn test_try_read_field() {
+        let mut pb = Protobuf::new();
+        pb.write_field(1, Type::Bytes);
+        pb.write_varint((1 << 3) | 6);
+
+        let bytes = pb.take();
+        let mut pb = Protobuf::from_input(bytes);
+        assert_eq!(pb.try_read_field(), Ok(Field { tag: 1, r#type: Type::Bytes }));
+        assert_eq!(pb.try_read_field(), Err(PbfError::InvalidWireType(6)));
+        assert_eq!(pb.try_read_field(), Err(PbfError::UnexpectedEof));
+        assert_eq!(Type::try_from_u8(6), Err(PbfError::InvalidWireType(6)));
+        assert_eq!(Type::try_from_u8(5), Ok(Type::Fixed32));
+    }
+
+    #[test]
+    #[should_panic(expected = "EOF")]
+    fn test_read_past_end_panic() {
+        let mut pb = Protobuf::new();
+        pb.write_varint(1);
+        pb.write_varint(300);
+
+        let bytes = pb.take();
+        let mut pb = Protobuf::from_input(bytes);
+        pb.read_varint::<u64>();
+        pb.read_varint::<u64>();
+        pb.read_varint::<u64>();
+    }
+
+    #[test]
+    fn test_varint() {
+        let mut pb = Protobuf::new();
+        pb.write_varint(1);
+        pb.write_varint(300);
+        pb.write_varint(0x7fffffffffffffff_u64);
+
+        let bytes = pb.take();
+        assert_eq!(bytes, &[1, 172, 2, 255, 255, 255, 255, 255, 255, 255, 255, 127]);
+
+        let mut pb = Protobuf::from_input(bytes);
+        assert_eq!(pb.read_varint::<u64>(), 1);
+        assert_eq!(pb.read_varint::<u64>(), 300);
+        assert_eq!(pb.read_varint::<u64>(), 0x7fffffffffffffff);
+    }
+
+    #[test]
+    fn test_try_decode_varint() {
+        let mut pb = Protobuf::from_input(vec![172, 2]);
+        assert_eq!(pb.try_decode_varint(), Ok(300));
+        assert_eq!(pb.try_decode_varint(), Err(PbfError::UnexpectedEof));
+
+        // truncated
+        let mut pb = Protobuf::from_input(vec![0xFF, 0xFF]);
+        assert_eq!(pb.try_decode_varint(), Err(PbfError::UnexpectedEof));
+
+        // too long
+        let mut pb = Protobuf::from_input(vec![0xFF; 11]);
+        assert_eq!(pb.try_decode_varint(), Err(PbfError::VarintOverflow));
+
+        // the longest legitimate varint
+        let mut pb = Protobuf::new();
+        pb.write_varint(u64::MAX);
+        let bytes = pb.take();
+        assert_eq!(bytes.len(), 10);
+        assert_eq!(Protobuf::from_input(bytes).try_decode_varint(), Ok(u64::MAX));
+
+        // 11 bytes where the 10th byte keeps the continuation bit set
+        let mut bytes = vec![0x80; 10];
+        bytes.push(0x00);
+        assert_eq!(Protobuf::from_input(bytes).try_decode_varint(), Err(PbfError::VarintOverflow));
+
+        // bits beyond bit 63 in the 10th byte
+        let mut bytes = vec![0xFF; 9];
+        bytes.push(0x02);
+        assert_eq!(Protobuf::from_input(bytes).try_decode_varint(), Err(PbfError::VarintOverflow));
+    }
+
+    #[test]
+    fn test_validate() {
+        let mut pb = Protobuf::new();
+        pb.write_varint_field(1, u64::MAX);
+        pb.write_s_varint_field(2, -5);
+        pb.write_fixed_field(3, 1.5_f32);
+        pb.write_fixed_field(4, 1.5_f64);
+        pb.write_string_field(5, "hello");
+        pb.write_packed_varint(6, &[1_u32, 2, 3]);
+        pb.write_field(7, Type::StartGroup);
+        pb.write_varint_field(1, 1_u8);
+        pb.write_field(7, Type::EndGroup);
+        pb.write_field(8, Type::None);
+        let bytes = pb.take();
+        assert_eq!(Protobuf::from_input(bytes.clone()).validate(), Ok(()));
+        assert_eq!(Protobuf::new().validate(), Ok(()));
+
+        // a length field overrunning the end
+        let mut overrun = bytes.clone();
+        overrun.extend([0x12, 10, 1, 2]);
+        assert_eq!(Protobuf::from_input(overrun).validate(), Err(PbfError::UnexpectedEof));
+        // a truncated varint value
+        let mut truncated = bytes.clone();
+        truncated.extend([0x08, 0x80]);
+        assert_eq!(Protobuf::from_input(truncated).validate(), Err(PbfError::UnexpectedEof));
+        // a truncated fixed value
+        assert_eq!(Protobuf::from_input(vec![0x0d, 1, 2]).validate(), Err(PbfError::UnexpectedEof));
+        // an unsupported wire type
+        assert_eq!(Protobuf::from_input(vec![0x0e]).validate(), Err(PbfError::InvalidWireType(6)));
+        // unbalanced groups
+        assert_eq!(Protobuf::from_input(vec![0x0b]).validate(), Err(PbfError::UnexpectedEof));
+        assert_eq!(Protobuf::from_input(vec![0x0c]).validate(), Err(PbfError::InvalidWireType(4)));
+    }
+
+    #[test]
+    #[should_panic(expected = "varint exceeds 10 bytes")]
+    fn test_decode_varint_overflow_panic() {
+        let mut pb = Protobuf::from_input(vec![0xFF; 11]);
+        pb.decode_varint();
+    }
+
+    #[test]
+    fn test_varint_len() {
+        assert_eq!(varint_len(0), 1);
+        assert_eq!(varint_len(127), 1);
+        assert_eq!(varint_len(128), 2);
+        assert_eq!(varint_len(16_383), 2);
+        assert_eq!(varint_len(16_384), 3);
+        assert_eq!(varint_len(u64::MAX), 10);
+
+        assert_eq!(s_varint_len(0), 1);
+        assert_eq!(s_varint_len(-1), 1);
+        assert_eq!(s_varint_len(-64), 1);
+        assert_eq!(s_varint_len(64), 2);
+        assert_eq!(s_varint_len(i64::MIN), 10);
+
+        for val in [0, 1, 300, u32::MAX as u64, u64::MAX] {
+            let mut pb = Protobuf::new();
+            pb.write_varint(val);
+            assert_eq!(varint_len(val), pb.take().len());
+        }
+    }
+
+    #[test]
+    fn test_varint_boundaries() {
+        let mut pb = Protobuf::new();
+        pb.write_varint(128);
+        pb.write_varint(16383);
+        pb.write_varint(16384);
+        pb.write_varint(0x3fff);
+        pb.write_varint(0x4000);
+
+        let bytes = pb.take();
+        assert_eq!(bytes, &[128, 1, 255, 127, 128, 128, 1, 255, 127, 128, 128, 1]);
+
+        let mut pb = Protobuf::from_input(bytes);
+        assert_eq!(pb.read_varint::<u64>(), 128);
+        assert_eq!(pb.read_varint::<u64>(), 16383);
+        assert_eq!(pb.read_varint::<u64>(), 16384);
+        assert_eq!(pb.read_varint::<u64>(), 0x3fff);
+        assert_eq!(pb.read_varint::<u64>(), 0x4000);
+        assert_eq!(pb.get_pos(), pb.len());
+    }
+
+    #[test]
+    fn test_varint_field() {
+        let mut pb = Protobuf::new();
+        // unsigned
+        pb.write_varint_field(0, 5_u8);
+        pb.write_varint_field(1, 1_u16);
+        pb.write_varint_field(2, 300_u32);
+        pb.write_varint_field(3, 0x7fffffffffffffff_u64);
+        // signed
+        pb.write_varint_field(4, -5_i8);
+        pb.write_varint_field(5, -1_i16);
+        pb.write_varint_field(6, -300_i32);
+        pb.write_varint_field(7, -94949494949_i64);
+        // bool
+        pb.write_varint_field(8, true);
+        pb.write_varint_field(9, false);
+        // enum
+        #[derive(Debug, PartialEq)]
+        enum TestEnum {
+            A = 1,
+            B = 2,
+            C = 3,
+        }
+        impl BitCast for TestEnum {
+            fn from_u64(val: u64) -> Self {
+                match val {
+                    1 => TestEnum::A,
+                    2 => TestEnum::B,
+                    3 => TestEnum::C,
+                    _ => panic!("Invalid enum value"),
+                }
+            }
+            fn to_u64(&self) -> u64 {
+                match self {
+                    TestEnum::A => 1,
+                    TestEnum::B => 2,
+                    TestEnum::C => 3,
+                }
+            }
+        }
+        pb.write_varint_field(10, TestEnum::B);
+        pb.write_varint_field(11, TestEnum::A);
+        pb.write_varint_field(12, TestEnum::C);
+        // float
+        pb.write_varint_field(13, core::f32::consts::PI);
+        pb.write_varint_field(14, -core::f64::consts::PI);
+
+        let bytes = pb.take();
+        let mut pb = Protobuf::from_input(bytes);
+
+        // unsigned
+        // tag 0
+        assert_eq!(pb.read_field(), Field { tag: 0, r#type: Type::Varint });
+        assert_eq!(pb.read_varint::<u8>(), 5);
+        // tag 1
+        assert_eq!(pb.read_field(), Field { tag: 1, r#type: Type::Varint });
+        assert_eq!(pb.read_varint::<u16>(), 1);
+        // tag 2
+        assert_eq!(pb.read_field(), Field { tag: 2, r#type: Type::Varint });
+        assert_eq!(pb.read_varint::<u32>(), 300);
+        // tag 3
+        assert_eq!(pb.read_field(), Field { tag: 3, r#type: Type::Varint });
+        assert_eq!(pb.read_varint::<u64>(), 0x7fffffffffffffff);
+
+        // signed
+        // tag 4
+        assert_eq!(pb.read_field(), Field { tag: 4, r#type: Type::Varint });
+        assert_eq!(pb.read_varint::<i8>(), -5);
+        // tag 5
+        assert_eq!(pb.read_field(), Field { tag: 5, r#type: Type::Varint });
+        assert_eq!(pb.read_varint::<i16>(), -1);
+        // tag 6
+        assert_eq!(pb.read_field(), Field { tag: 6, r#type: Type::Varint });
+        assert_eq!(pb.read_varint::<i32>(), -300);
+        // tag 7
+        assert_eq!(pb.read_field(), Field { tag: 7, r#type: Type::Varint });
+        assert_eq!(pb.read_varint::<i64>(), -94949494949);
+
+        // bool
+        // tag 8
+        assert_eq!(pb.read_field(), Field { tag: 8, r#type: Type::Varint });
+        assert!(pb.read_varint::<bool>());
+        // tag 9
+        assert_eq!(pb.read_field(), Field { tag: 9, r#type: Type::Varint });
+        assert!(!pb.read_varint::<bool>());
+
+        // enum
+        // tag 10
+        assert_eq!(pb.read_field(), Field { tag: 10, r#type: Type::Varint });
+        assert_eq!(pb.read_varint::<TestEnum>(), TestEnum::B);
+        // tag 11
+        assert_eq!(pb.read_field(), Field { tag: 11, r#type: Type::Varint });
+        assert_eq!(pb.read_varint::<TestEnum>(), TestEnum::A);
+        // tag 12
+        assert_eq!(pb.read_field(), Field { tag: 12, r#type: Type::Varint });
+        assert_eq!(pb.read_varint::<TestEnum>(), TestEnum::C);
+
+        // float
+        // tag 13
+        assert_eq!(pb.read_field(), Field { tag: 13, r#type: Type::Varint });
+        assert_eq!(pb.read_varint::<f32>(), core::f32::consts::PI);
+        // tag 14
+        assert_eq!(pb.read_field(), Field { tag: 14, r#type: Type::Varint });
+        assert_eq!(pb.read_varint::<f64>(), -core::f64::consts::PI);
+    }
+
+    #[test]
+    fn test_char() {
+        let mut pb = Protobuf::new();
+        pb.write_varint_field(1, 'λ');
+        pb.write_varint_field(2, '🦀');
+        pb.write_varint('a');
+
+        let bytes = pb.take();
+        let mut pb = Protobuf::from_input(bytes);
+
+        assert_eq!(pb.read_field(), Field { tag: 1, r#type: Type::Varint });
+        assert_eq!(pb.read_varint::<char>(), 'λ');
+        assert_eq!(pb.read_field(), Field { tag: 2, r#type: Type::Varint });
+        assert_eq!(pb.read_varint::<char>(), '🦀');
+        assert_eq!(pb.read_varint::<char>(), 'a');
+    }
+
+    #[test]
+    #[should_panic(expected = "Invalid char value: 55296")]
+    fn test_char_surrogate_panic() {
+        let mut pb = Protobuf::new();
+        pb.write_varint(0xD800_u32);
+
+        let bytes = pb.take();
+        let mut pb = Protobuf::from_input(bytes);
+        pb.read_varint::<char>();
+    }
+
+    #[test]
+    fn test_varint_field_2() {
+        let mut pb = Protobuf::new();
+        pb.write_varint_field(1, 5_u8);
+        pb.write_varint_field(2, 5_u16);
+        pb.write_varint_field(3, 5_u32);
+        pb.write_varint_field(4, 5_u64);
+        pb.write_varint_field(5, 5_usize);
+        pb.write_varint_field(6, -5_isize);
+
+        let bytes = pb.take();
+        let mut pb: Protobuf = bytes.into();
+
+        assert_eq!(pb.read_field(), Field { tag: 1, r#type: Type::Varint });
+        assert_eq!(pb.read_varint::<u8>(), 5);
+        assert_eq!(pb.read_field(), Field { tag: 2, r#type: Type::Varint });
+        assert_eq!(pb.read_varint::<u16>(), 5);
+        assert_eq!(pb.read_field(), Field { tag: 3, r#type: Type::Varint });
+        assert_eq!(pb.read_varint::<u32>(), 5);
+        assert_eq!(pb.read_field(), Field { tag: 4, r#type: Type::Varint });
+        assert_eq!(pb.read_varint::<u64>(), 5);
+        assert_eq!(pb.read_field(), Field { tag: 5, r#type: Type::Varint });
+        assert_eq!(pb.read_varint::<usize>(), 5);
+        assert_eq!(pb.read_field(), Field { tag: 6, r#type: Type::Varint });
+        assert_eq!(pb.read_varint::<isize>(), -5);
+    }
+
+    #[test]
+    fn test_s_varint_field() {
+        let mut pb = Protobuf::new();
+        pb.write_s_varint_field(1, 5_i8);
+        pb.write_s_varint_field(2, 5_i16);
+        pb.write_s_varint_field(3, 5_i32);
+        pb.write_s_varint_field(4, 5_i64);
+        pb.write_s_varint_field(5, -5_i8);
+        pb.write_s_varint_field(6, -5_i16);
+        pb.write_s_varint_field(7, -5_i32);
+        pb.write_s_varint_field(8, -5_i64);
+
+        let bytes = pb.take();
+        let mut pb = Protobuf::from_input(bytes);
+
+        assert_eq!(pb.read_field(), Field { tag: 1, r#type: Type::Varint });
+        assert_eq!(pb.read_s_varint::<i8>(), 5);
+        assert_eq!(pb.read_field(), Field { tag: 2, r#type: Type::Varint });
+        assert_eq!(pb.read_s_varint::<i16>(), 5);
+        assert_eq!(pb.read_field(), Field { tag: 3, r#type: Type::Varint });
+        assert_eq!(pb.read_s_varint::<i32>(), 5);
+        assert_eq!(pb.read_field(), Field { tag: 4, r#type: Type::Varint });
+        assert_eq!(pb.read_s_varint::<i64>(), 5);
+        assert_eq!(pb.read_field(), Field { tag: 5, r#type: Type::Varint });
+        assert_eq!(pb.read_s_varint::<i8>(), -5);
+        assert_eq!(pb.read_field(), Field { tag: 6, r#type: Type::Varint });
+        assert_eq!(pb.read_s_varint::<i16>(), -5);
+        assert_eq!(pb.read_field(), Field { tag: 7, r#type: Type::Varint });
+        assert_eq!(pb.read_s_varint::<i32>(), -5);
+        assert_eq!(pb.read_field(), Field { tag: 8, r#type: Type::Varint });
+        assert_eq!(pb.read_s_varint::<i64>(), -5);
+    }
+
+    #[test]
+    fn test_fixed() {
+        let mut pb = Protobuf::new();
+        pb.write_fixed_field(1, 5_u32);
+        pb.write_fixed_field(2, -5_i32);
+        pb.write_fixed_field(3, 5.5_f32);
+        pb.write_fixed_field(4, 5_u64);
+        pb.write_fixed_field(5, -5_i64);
+        pb.write_fixed_field(6, 5.5_f64);
+
+        let bytes = pb.take();
+        let mut pb = Protobuf::from_input(bytes);
+
+        assert_eq!(pb.read_field(), Field { tag: 1, r#type: Type::Fixed32 });
+        assert_eq!(pb.read_fixed::<u32>(), 5);
+        assert_eq!(pb.read_field(), Field { tag: 2, r#type: Type::Fixed32 });
+        assert_eq!(pb.read_fixed::<i32>(), -5);
+        assert_eq!(pb.read_field(), Field { tag: 3, r#type: Type::Fixed32 });
+        assert_eq!(pb.read_fixed::<f32>(), 5.5);
+        assert_eq!(pb.read_field(), Field { tag: 4, r#type: Type::Fixed64 });
+        assert_eq!(pb.read_fixed::<u64>(), 5);
+        assert_eq!(pb.read_field(), Field { tag: 5, r#type: Type::Fixed64 });
+        assert_eq!(pb.read_fixed::<i64>(), -5);
+        assert_eq!(pb.read_field(), Field { tag: 6, r#type: Type::Fixed64 });
+        assert_eq!(pb.read_fixed::<f64>(), 5.5);
+    }
+
+    #[test]
+    fn test_try_read_fixed() {
+        let mut pb = Protobuf::new();
+        pb.write_fixed(5_u32);
+        pb.write_fixed(6_u32);
+
+        let mut bytes = pb.take();
+        bytes.truncate(7);
+        let mut pb = Protobuf::from_input(bytes);
+
+        assert_eq!(pb.try_read_fixed::<u32>(), Ok(5));
+        assert_eq!(pb.try_read_fixed::<u32>(), Err(PbfError::UnexpectedEof));
+        assert_eq!(pb.get_pos(), 4);
+    }
+
+    #[test]
+    #[should_panic(expected = "unexpected EOF")]
+    fn test_read_fixed_eof_panic() {
+        let mut pb = Protobuf::from_input(vec![1, 2, 3]);
+        pb.read_fixed::<u64>();
+    }
+
+    #[test]
+    fn test_string() {
+        let mut pb = Protobuf::new();
+        pb.write_string_field(1, "hello");
+        pb.write_string_field(2, "world");
+
+        let bytes = pb.take();
+        let mut pb = Protobuf::from_input(bytes);
+
+        assert_eq!(pb.read_field(), Field { tag: 1, r#type: Type::Bytes });
+        assert_eq!(pb.read_string(), "hello");
+        assert_eq!(pb.read_field(), Field { tag: 2, r#type: Type::Bytes });
+        assert_eq!(pb.read_string(), "world");
+    }
+
+    #[test]
+    fn test_bytes() {
+        let mut pb = Protobuf::new();
+        pb.write_bytes_field(1, &[1, 2, 3]);
+        pb.write_bytes_field(2, &[4, 5, 6]);
+
+        let bytes = pb.take();
+        let mut pb = Protobuf::from_input(bytes);
+
+        assert_eq!(pb.read_field(), Field { tag: 1, r#type: Type::Bytes });
+        assert_eq!(pb.read_bytes(), &[1, 2, 3]);
+        assert_eq!(pb.read_field(), Field { tag: 2, r#type: Type::Bytes });
+        assert_eq!(pb.read_bytes(), &[4, 5, 6]);
+    }
+
+    #[test]
+    fn test_bytes_ref() {
+        let mut pb = Protobuf::new();
+        pb.write_bytes_field(1, &[1, 2, 3]);
+        pb.write_string_field(2, "hello");
+
+        let bytes = pb.take();
+        let mut pb = Protobuf::from_input(bytes);
+
+        assert_eq!(pb.read_field(), Field { tag: 1, r#type: Type::Bytes });
+        assert_eq!(pb.read_bytes_ref(), &[1, 2, 3]);
+        assert_eq!(pb.get_pos(), 5);
+        assert_eq!(pb.read_field(), Field { tag: 2, r#type: Type::Bytes });
+        assert_eq!(pb.read_bytes_ref(), b"hello");
+        assert_eq!(pb.get_pos(), pb.len());
+    }
+
+    #[test]
+    fn test_array() {
+        let hash: [u8; 32] = core::array::from_fn(|i| i as u8 * 7);
+        let mut pb = Protobuf::new();
+        pb.write_array_field(1, &hash);
+        pb.write_array_field(2, &[1, 2, 3]);
+
+        let mut pb = Protobuf::from_input(pb.take());
+        assert_eq!(pb.read_field(), Field { tag: 1, r#type: Type::Bytes });
+        assert_eq!(pb.read_array::<32>(), hash);
+        assert_eq!(pb.read_field(), Field { tag: 2, r#type: Type::Bytes });
+        let pos = pb.get_pos();
+        assert_eq!(
+            pb.try_read_array::<4>(),
+            Err(PbfError::LengthMismatch { expected: 4, found: 3 })
+        );
+        // the length prefix is not consumed, the field can be read another way
+        assert_eq!(pb.get_pos(), pos);
+        assert_eq!(pb.try_read_array::<3>(), Ok([1, 2, 3]));
+
+        // truncated
+        let mut pb = Protobuf::from_input(vec![3, 1, 2]);
+        assert_eq!(pb.try_read_array::<3>(), Err(PbfError::UnexpectedEof));
+        assert_eq!(pb.get_pos(), 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "length mismatch: expected 2 bytes, found 3")]
+    fn test_array_mismatch_panic() {
+        let mut pb = Protobuf::from_input(vec![3, 1, 2, 3]);
+        pb.read_array::<2>();
+    }
+
+    #[test]
+    fn test_write_field() {
+        let mut pb = Protobuf::new();
+        pb.write_field(1, Type::Varint);
+        pb.write_field(2, Type::None);
+
+        let bytes = pb.take();
+        let mut pb = Protobuf::from_input(bytes);
+
+        assert_eq!(pb.read_field(), Field { tag: 1, r#type: Type::Varint });
+        assert_eq!(pb.read_field(), Field { tag: 2, r#type: Type::None });
+    }
+
+    #[test]
+    fn test_peek_field() {
+        let mut pb = Protobuf::new();
+        pb.write_varint_field(300, 5);
+        pb.write_varint_field(2, 5);
+
+        let bytes = pb.take();
+        let mut pb = Protobuf::from_input(bytes);
+
+        assert_eq!(pb.peek_field(), Field { tag: 300, r#type: Type::Varint });
+        assert_eq!(pb.peek_field(), Field { tag: 300, r#type: Type::Varint });
+        assert_eq!(pb.get_pos(), 0);
+        assert_eq!(pb.read_field(), Field { tag: 300, r#type: Type::Varint });
+        assert_eq!(pb.get_pos(), 2);
+        assert_eq!(pb.read_varint::<u8>(), 5);
+        assert_eq!(pb.peek_field(), Field { tag: 2, r#type: Type::Varint });
+    }
+
+    #[test]
+    fn test_next_field() {
+        let mut pb = Protobuf::new();
+        pb.write_varint_field(1, 5);
+        pb.write_string_field(2, "hello");
+        pb.write_fixed_field(3, 1.5_f32);
+        pb.write_packed_varint::<u32>(4, &[1, 2, 3]);
+
+        let bytes = pb.take();
+        let mut pb = Protobuf::from_input(bytes);
+
+        let mut tags = vec![];
+        while let Some(field) = pb.next_field() {
+            tags.push(field.tag);
+            pb.skip(field.r#type);
+        }
+        assert_eq!(tags, vec![1, 2, 3, 4]);
+        assert_eq!(pb.next_field(), None);
+    }
+
+    #[test]
+    fn test_none_type() {
+        let mut pb = Protobuf::new();
+        pb.write_field(3, Type::None);
+        pb.write_varint_field(4, 1_u8);
+        let mut pb = Protobuf::from_input(pb.take());
+
+        assert_eq!(pb.read_field(), Field { tag: 3, r#type: Type::None });
+        assert_eq!(pb.get_pos(), 1);
+        pb.read_none();
+        assert_eq!(pb.get_pos(), 1);
+        pb.skip(Type::None);
+        assert_eq!(pb.get_pos(), 1);
+        assert_eq!(pb.read_field(), Field { tag: 4, r#type: Type::Varint });
+    }
+
+    #[test]
+    fn clear() {
+        let mut pb = Protobuf::with_capacity(64);
+        pb.write_string_field(1, "first message");
+        pb.set_pos(3);
+        let capacity = pb.buf.capacity();
+
+        pb.clear();
+        assert!(pb.is_empty());
+        assert_eq!(pb.get_pos(), 0);
+        assert_eq!(pb.buf.capacity(), capacity);
+
+        pb.write_varint_field(2, 5_u32);
+        assert_eq!(pb.take(), vec![16, 5]);
+    }
+
+    #[test]
+    fn test_read_bool() {
+        let mut pb = Protobuf::from_input(vec![0, 1, 2]);
+        assert_eq!(pb.try_read_bool(), Ok(false));
+        assert_eq!(pb.try_read_bool(), Ok(true));
+        assert_eq!(pb.try_read_bool(), Err(PbfError::InvalidBool(2)));
+        assert_eq!(pb.get_pos(), 2);
+        assert!(pb.read_bool());
+
+        let mut pb = Protobuf::from_input(vec![0, 1]);
+        assert!(!pb.read_bool());
+        assert!(pb.read_bool());
+        assert_eq!(pb.try_read_bool(), Err(PbfError::UnexpectedEof));
+    }
+
+    #[test]
+    fn test_seek_to_field() {
+        let mut pb = Protobuf::new();
+        pb.write_varint_field(1, 1_u32);
+        pb.write_string_field(2, "two");
+        pb.write_fixed_field(3, 3.0_f64);
+        pb.write_packed_varint(4, &[4_u32, 4]);
+        pb.write_s_varint_field(5, -5);
+        pb.write_fixed_field(6, 6_u32);
+        let mut pb = Protobuf::from_input(pb.take());
+
+        assert_eq!(pb.seek_to_field(5), Some(Field { tag: 5, r#type: Type::Varint }));
+        assert_eq!(pb.read_s_varint::<i32>(), -5);
+        // tags already passed are not found again
+        assert_eq!(pb.seek_to_field(1), None);
+        assert_eq!(pb.get_pos(), pb.len());
+    }
+
+    #[test]
+    fn test_field_histogram() {
+        let mut pb = Protobuf::new();
+        pb.write_string_field(3, "a");
+        pb.write_varint_field(1, 1_u32);
+        pb.write_fixed_field(3, 2.0_f32);
+        pb.write_varint_field(2, 2_u32);
+        pb.write_varint_field(1, 1_u32);
+        pb.write_s_varint_field(3, -3);
+        let mut pb = Protobuf::from_input(pb.take());
+        pb.set_pos(2);
+
+        assert_eq!(pb.field_histogram(), vec![(1, 2), (2, 1), (3, 3)]);
+        assert_eq!(pb.get_pos(), 2);
+    }
+
+    #[test]
+    fn test_append() {
+        let mut head = Protobuf::new();
+        head.write_varint_field(1, 7_u32);
+        head.write_string_field(2, "head");
+        let mut tail = Protobuf::new();
+        tail.write_s_varint_field(3, -7);
+        tail.write_string_field(2, "tail");
+
+        head.append(&mut tail);
+        assert!(tail.is_empty());
+
+        let mut pb = Protobuf::from_input(head.take());
+        assert_eq!(pb.read_field(), Field { tag: 1, r#type: Type::Varint });
+        assert_eq!(pb.read_varint::<u32>(), 7);
+        assert_eq!(pb.read_field(), Field { tag: 2, r#type: Type::Bytes });
+        assert_eq!(pb.read_string(), "head");
+        assert_eq!(pb.read_field(), Field { tag: 3, r#type: Type::Varint });
+        assert_eq!(pb.read_s_varint::<i32>(), -7);
+        assert_eq!(pb.read_field(), Field { tag: 2, r#type: Type::Bytes });
+        assert_eq!(pb.read_string(), "tail");
+        assert_eq!(pb.next_field(), None);
+    }
+
+    #[test]
+    fn test_fixed_byte_order() {
+        let mut pb = Protobuf::new();
+        pb.write_fixed_be(0x0102_0304_u32);
+        pb.write_fixed_le(0x0102_0304_u32);
+        pb.write_fixed_be(-2_i64);
+        let bytes = pb.take();
+        assert_eq!(
+            bytes,
+            vec![1, 2, 3, 4, 4, 3, 2, 1, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xfe]
+        );
+
+        let mut pb = Protobuf::from_input(bytes);
+        assert_eq!(pb.read_fixed_be::<u32>(), 0x0102_0304);
+        assert_eq!(pb.read_fixed_le::<u32>(), 0x0102_0304);
+        assert_eq!(pb.read_fixed_be::<i64>(), -2);
+        assert_eq!(pb.try_read_fixed_ordered(4, true), Err(PbfError::UnexpectedEof));
+    }
+
+    #[test]
+    fn test_read_fixed_float_canonical() {
+        // signaling NaNs with a payload, one of them negative
+        let snan32 = f32::from_bits(0x7f80_0001);
+        let snan64 = f64::from_bits(0xfff0_0000_0000_0001);
+        let mut pb = Protobuf::new();
+        pb.write_fixed(snan32);
+        pb.write_fixed(-1.5_f32);
+        pb.write_fixed(snan64);
+        pb.write_fixed(f64::INFINITY);
+        pb.write_fixed(f64::from_bits(1));
+
+        let mut pb = Protobuf::from_input(pb.take());
+        assert_eq!(pb.read_fixed_f32_canonical().to_bits(), f32::NAN.to_bits());
+        assert_eq!(pb.read_fixed_f32_canonical().to_bits(), (-1.5_f32).to_bits());
+        assert_eq!(pb.read_fixed_f64_canonical().to_bits(), f64::NAN.to_bits());
+        assert_eq!(pb.read_fixed_f64_canonical(), f64::INFINITY);
+        // subnormals pass through untouched
+        assert_eq!(pb.read_fixed_f64_canonical().to_bits(), 1);
+    }
+
+    #[test]
+    fn test_f16() {
+        assert_eq!(f32_to_f16_bits(0.0), 0);
+        assert_eq!(f32_to_f16_bits(-0.0), 0x8000);
+        assert_eq!(f32_to_f16_bits(1.0), 0x3c00);
+        assert_eq!(f32_to_f16_bits(-2.0), 0xc000);
+        assert_eq!(f32_to_f16_bits(65504.0), 0x7bff);
+        assert_eq!(f32_to_f16_bits(65520.0), 0x7c00);
+        assert_eq!(f32_to_f16_bits(f32::INFINITY), 0x7c00);
+        assert!(f16_bits_to_f32(f32_to_f16_bits(f32::NAN)).is_nan());
+        // ties round to even
+        assert_eq!(f32_to_f16_bits(1.0 + 2.0_f32.powi(-11)), 0x3c00);
+        assert_eq!(f32_to_f16_bits(1.0 + 3.0 * 2.0_f32.powi(-11)), 0x3c02);
+        // subnormals
+        let smallest = 2.0_f32.powi(-24);
+        assert_eq!(f32_to_f16_bits(smallest), 0x0001);
+        assert_eq!(f32_to_f16_bits(smallest / 4.0), 0);
+        assert_eq!(f32_to_f16_bits(2.0_f32.powi(-15)), 0x0200);
+
+        for val in [0.0, 1.0, -2.0, 65504.0, smallest, 2.0_f32.powi(-15), 0.333_251_95] {
+            assert_eq!(f16_bits_to_f32(f32_to_f16_bits(val)), val);
+        }
+
+        let mut pb = Protobuf::new();
+        pb.write_fixed16_field(1, f32_to_f16_bits(1.5));
+        let bytes = pb.take();
+        assert_eq!(bytes, vec![10, 2, 0x00, 0x3e]);
+
+        let mut pb = Protobuf::from_input(bytes);
+        assert_eq!(pb.read_field(), Field { tag: 1, r#type: Type::Bytes });
+        assert_eq!(f16_bits_to_f32(pb.read_fixed16()), 1.5);
+    }
+
+    #[test]
+    fn test_read_packed_with() {
+        #[derive(Debug, PartialEq)]
+        enum Color {
+            Red = 1,
+            Green = 2,
+            Unknown = 0,
+        }
+
+        let mut pb = Protobuf::new();
+        pb.write_packed_varint(1, &[1_u32, 7, 2, 300]);
+        let mut pb = Protobuf::from_input(pb.take());
+        assert_eq!(pb.read_field(), Field { tag: 1, r#type: Type::Bytes });
+        let colors = pb.read_packed_with(|val| match val {
+            1 => Some(Color::Red),
+            2 => Some(Color::Green),
+            _ => Some(Color::Unknown),
+        });
+        assert_eq!(colors, vec![Color::Red, Color::Unknown, Color::Green, Color::Unknown]);
+
+        pb.set_pos(0);
+        pb.read_field();
+        let known: Vec<u8> = pb.read_packed_with(|val| u8::try_from(val).ok().filter(|&v| v < 3));
+        assert_eq!(known, vec![1, 2]);
+        assert_eq!(pb.next_field(), None);
+    }
+
+    #[test]
+    fn test_read_fields_tracked() {
+        #[derive(Default)]
+        struct Header {
+            id: u32,
+            flags: Vec<u32>,
+        }
+        impl ProtoRead for Header {
+            fn read(&mut self, tag: u64, pb: &mut Protobuf) {
+                match tag {
+                    1 => self.id = pb.read_varint(),
+                    3 => self.flags.push(pb.read_varint()),
+                    _ => {}
+                }
+            }
+        }
+
+        let mut pb = Protobuf::new();
+        pb.write_varint_field(3, 1_u32);
+        pb.write_varint_field(1, 7_u32);
+        pb.write_varint_field(3, 2_u32);
+        pb.write_string_field(9, "unknown");
+        let mut pb = Protobuf::from_input(pb.take());
+
+        let mut header = Header::default();
+        let seen = pb.read_fields_tracked(&mut header, None);
+        assert_eq!(seen, vec![3, 1, 9]);
+        assert_eq!(header.id, 7);
+        assert_eq!(header.flags, vec![1, 2]);
+
+        let required = RequiredFields::new().require(1).require(2).require(3).require(2);
+        assert_eq!(required.missing(&seen), vec![2]);
+        assert!(RequiredFields::new().require(1).missing(&seen).is_empty());
+    }
+
+    #[test]
+    fn test_debug_dump() {
+        let mut pb = Protobuf::new();
+        pb.write_varint_field(1, 150_u32);
+        pb.write_fixed_field(2, 42_u32);
+        pb.write_fixed_field(3, -1_i64);
+        pb.write_string_field(4, "hello");
+        pb.write_bytes_field(5, &[0xab; 20]);
+        pb.write_bytes_field(6, &[]);
+        let mut pb = Protobuf::from_input(pb.take());
+        pb.set_pos(3);
+
+        assert_eq!(
+            pb.debug_dump(),
+            "tag=1 type=Varint value=150\n\
+             tag=2 type=Fixed32 value=0x0000002a\n\
+             tag=3 type=Fixed64 value=0xffffffffffffffff\n\
+             tag=4 type=Bytes value=len=5 68656c6c6f\n\
+             tag=5 type=Bytes value=len=20 abababababababababababababababab..\n\
+             tag=6 type=Bytes value=len=0\n"
+        );
+        assert_eq!(pb.get_pos(), 3);
+
+        let mut pb = Protobuf::from_input(vec![8, 1, 18, 5, 1]);
+        assert_eq!(pb.debug_dump(), "tag=1 type=Varint value=1\nerror=unexpected EOF\n");
+
+        // a group that is never closed, and one closed after a truncated value
+        let mut pb = Protobuf::from_input(vec![8, 1, 11, 16, 2]);
+        assert_eq!(pb.debug_dump(), "tag=1 type=Varint value=1\nerror=unexpected EOF\n");
+        let mut pb = Protobuf::from_input(vec![11, 16, 2, 12, 11, 17, 1, 12]);
+        assert_eq!(pb.debug_dump(), "tag=1 type=StartGroup value=len=3\nerror=unexpected EOF\n");
+    }
+
+    #[test]
+    fn test_try_read_varint() {
+        let mut pb = Protobuf::new();
+        pb.write_varint('x');
+        pb.write_varint(0xd800_u32);
+        let mut pb = Protobuf::from_input(pb.take());
+
+        assert_eq!(pb.try_read_varint::<char>(), Ok('x'));
+        assert_eq!(pb.try_read_varint::<char>(), Err(PbfError::InvalidValue(0xd800)));
+        assert_eq!(pb.get_pos(), 1);
+        assert_eq!(pb.read_varint::<u32>(), 0xd800);
+        assert_eq!(pb.try_read_varint::<char>(), Err(PbfError::UnexpectedEof));
+    }
+
+    #[test]
+    fn test_read_packed_into() {
+        let mut pb = Protobuf::new();
+        pb.write_packed_varint(1, &[1_u16, 300, 3]);
+        pb.write_varint_field(2, 9_u32);
+        let bytes = pb.take();
+
+        let mut pb = Protobuf::from_input(bytes.clone());
+        pb.read_field();
+        let mut out = [0_u16; 8];
+        assert_eq!(pb.read_packed_into(&mut out), 3);
+        assert_eq!(out, [1, 300, 3, 0, 0, 0, 0, 0]);
+        assert_eq!(pb.read_field(), Field { tag: 2, r#type: Type::Varint });
+
+        let mut pb = Protobuf::from_input(bytes);
+        pb.read_field();
+        let mut out = [0_u16; 2];
+        assert_eq!(pb.read_packed_into(&mut out), 2);
+        assert_eq!(out, [1, 300]);
+        assert_eq!(pb.read_field(), Field { tag: 2, r#type: Type::Varint });
+        assert_eq!(pb.read_varint::<u32>(), 9);
+    }
+
+    #[test]
+    fn test_from_bytes() {
+        let mut pb = Protobuf::new();
+        pb.write_string_field(1, "a");
+        let bytes = pb.take();
+
+        let mut from_input = Protobuf::from_input(bytes.clone());
+        let mut from_vec = Protobuf::from_bytes(bytes.clone());
+        let mut from_slice = Protobuf::from_bytes(&bytes[..]);
+        let mut from_str = Protobuf::from_bytes("\n\u{1}a");
+        for pb in [&mut from_input, &mut from_vec, &mut from_slice, &mut from_str] {
+            assert_eq!(pb.read_field(), Field { tag: 1, r#type: Type::Bytes });
+            assert_eq!(pb.read_string(), "a");
+        }
+    }
+
+    #[test]
+    fn test_write_length_delimited() {
+        #[derive(Debug, Default, PartialEq)]
+        struct Point {
+            x: i32,
+            y: i32,
+        }
+        impl ProtoWrite for Point {
+            fn write(&self, pb: &mut Protobuf) {
+                pb.write_s_varint_field(1, self.x);
+                pb.write_s_varint_field(2, self.y);
+            }
+        }
+        impl ProtoRead for Point {
+            fn read(&mut self, tag: u64, pb: &mut Protobuf) {
+                match tag {
+                    1 => self.x = pb.read_s_varint(),
+                    2 => self.y = pb.read_s_varint(),
+                    _ => {}
+                }
+            }
+        }
+
+        let points = vec![Point { x: 1, y: -1 }, Point { x: 200, y: 0 }, Point { x: -3, y: 4 }];
+        let mut pb = Protobuf::new();
+        for point in &points {
+            pb.write_length_delimited(point);
+        }
+        assert_eq!(Protobuf::encoded_len(&points[1]), 5);
+        let bytes = pb.take();
+        assert_eq!(bytes[..6], [4, 8, 2, 16, 1, 5]);
+
+        let mut pb = Protobuf::from_input(bytes);
+        let mut result = Vec::new();
+        while pb.remaining() > 0 {
+            let end = pb.read_varint::<usize>() + pb.get_pos();
+            let mut point = Point::default();
+            pb.read_fields(&mut point, Some(end));
+            result.push(point);
+        }
+        assert_eq!(result, points);
+    }
+
+    #[test]
+    fn test_read_s_varint_out_of_range() {
+        let mut pb = Protobuf::new();
+        pb.write_s_varint(300);
+        pb.write_s_varint(-300);
+        pb.write_s_varint(-5);
+        let mut pb = Protobuf::from_input(pb.take());
+
+        assert_eq!(pb.try_read_s_varint::<i8>(), Err(PbfError::InvalidValue(600)));
+        assert_eq!(pb.get_pos(), 0);
+        assert_eq!(pb.read_s_varint_saturating::<i8>(), 127);
+        assert_eq!(pb.read_s_varint_saturating::<i8>(), -128);
+        assert_eq!(pb.try_read_s_varint::<i8>(), Ok(-5));
+        assert_eq!(pb.try_read_s_varint::<i8>(), Err(PbfError::UnexpectedEof));
+
+        let mut pb = Protobuf::from_input(vec![3]);
+        assert_eq!(pb.read_s_varint_saturating::<u32>(), 0);
+    }
+
+    #[test]
+    fn test_checkpoint() {
+        let mut pb = Protobuf::new();
+        pb.write_varint_field(1, 0xd800_u32);
+        pb.write_string_field(2, "ok");
+        let mut pb = Protobuf::from_input(pb.take());
+
+        let cp = pb.checkpoint();
+        pb.read_field();
+        pb.read_varint::<u32>();
+        pb.restore(cp);
+        assert_eq!(pb.get_pos(), 0);
+
+        // the first interpretation (a char) fails and rewinds the field key as well
+        let as_char = pb.with_checkpoint(|pb| {
+            pb.read_field();
+            pb.try_read_varint::<char>().ok()
+        });
+        assert_eq!(as_char, None);
+        assert_eq!(pb.get_pos(), 0);
+
+        let as_u32 = pb.with_checkpoint(|pb| {
+            pb.read_field();
+            Some(pb.read_varint::<u32>())
+        });
+        assert_eq!(as_u32, Some(0xd800));
+        assert_eq!(pb.read_field(), Field { tag: 2, r#type: Type::Bytes });
+        assert_eq!(pb.read_string(), "ok");
+    }
+
+    #[test]
+    fn test_empty_packed() {
+        let mut pb = Protobuf::new();
+        pb.write_packed_varint::<u32>(1, &[]);
+        pb.write_packed_s_varint::<i32>(2, &[]);
+        pb.write_packed_fixed::<f32>(3, &[]);
+        pb.write_packed_s_fixed::<i64>(4, &[]);
+        pb.write_varint_field(5, 1_u32);
+        let bytes = pb.take();
+        assert_eq!(bytes, vec![10, 0, 18, 0, 26, 0, 34, 0, 40, 1]);
+
+        let mut pb = Protobuf::from_input(bytes);
+        pb.read_field();
+        assert_eq!(pb.read_packed::<u32>(), Vec::<u32>::new());
+        assert_eq!(pb.get_pos(), 2);
+        pb.read_field();
+        assert_eq!(pb.read_s_packed::<i32>(), Vec::<i32>::new());
+        assert_eq!(pb.get_pos(), 4);
+        pb.read_field();
+        assert_eq!(pb.read_packed_fixed::<f32>(), Vec::<f32>::new());
+        assert_eq!(pb.get_pos(), 6);
+        pb.read_field();
+        assert_eq!(pb.read_s_packed_fixed::<i64>(), Vec::<i64>::new());
+        assert_eq!(pb.get_pos(), 8);
+        assert_eq!(pb.read_field(), Field { tag: 5, r#type: Type::Varint });
+        assert_eq!(pb.read_varint::<u32>(), 1);
+    }
+
+    #[test]
+    fn test_group() {
+        #[derive(Debug, Default, PartialEq)]
+        struct Pair {
+            a: u32,
+            b: String,
+        }
+        impl ProtoWrite for Pair {
+            fn write(&self, pb: &mut Protobuf) {
+                pb.write_varint_field(1, self.a);
+                pb.write_string_field(2, &self.b);
+            }
+        }
+        impl ProtoRead for Pair {
+            fn read(&mut self, tag: u64, pb: &mut Protobuf) {
+                match tag {
+                    1 => self.a = pb.read_varint(),
+                    2 => self.b = pb.read_string(),
+                    _ => {}
+                }
+            }
+        }
+
+        let pair = Pair { a: 3, b: "b".into() };
+        let mut pb = Protobuf::new();
+        pb.write_group(5, &pair);
+        pb.write_varint_field(6, 1_u32);
+        let bytes = pb.take();
+        assert_eq!(bytes, vec![43, 8, 3, 18, 1, 98, 44, 48, 1]);
+
+        let mut pb = Protobuf::from_input(bytes);
+        assert_eq!(pb.read_field(), Field { tag: 5, r#type: Type::StartGroup });
+        let mut result = Pair::default();
+        pb.read_group(&mut result);
+        assert_eq!(result, pair);
+        assert_eq!(pb.read_field(), Field { tag: 6, r#type: Type::Varint });
+    }
+
+    #[test]
+    fn test_read_fixed_bytes() {
+        let mut pb = Protobuf::new();
+        pb.write_fixed(0x0102_0304_u32);
+        pb.write_fixed(-2.5_f64);
+        let bytes = pb.take();
+
+        let mut pb = Protobuf::from_input(bytes.clone());
+        assert_eq!(pb.read_fixed32_bytes(), [4, 3, 2, 1]);
+        assert_eq!(pb.read_fixed64_bytes(), (-2.5_f64).to_le_bytes());
+        assert_eq!(pb.try_read_raw::<4>(), Err(PbfError::UnexpectedEof));
+
+        let mut pb = Protobuf::from_input(bytes[..7].to_vec());
+        pb.set_pos(4);
+        assert_eq!(pb.try_read_raw::<8>(), Err(PbfError::UnexpectedEof));
+        assert_eq!(pb.get_pos(), 4);
+    }
+
+    #[test]
+    fn test_varint_decoder() {
+        let mut decoder = VarintDecoder::new();
+        assert_eq!(decoder.push(0xac), Ok(None));
+        assert!(decoder.is_pending());
+        assert_eq!(decoder.push(0x02), Ok(Some(300)));
+        assert!(!decoder.is_pending());
+        assert_eq!(decoder.push(0x05), Ok(Some(5)));
+
+        let mut pb = Protobuf::new();
+        pb.write_varint(u64::MAX);
+        let bytes = pb.take();
+        for &b in &bytes[..9] {
+            assert_eq!(decoder.push(b), Ok(None));
+        }
+        assert_eq!(decoder.push(bytes[9]), Ok(Some(u64::MAX)));
+
+        for _ in 0..9 {
+            assert_eq!(decoder.push(0xff), Ok(None));
+        }
+        assert_eq!(decoder.push(0x02), Err(PbfError::VarintOverflow));
+        assert!(!decoder.is_pending());
+        assert_eq!(decoder.push(0x01), Ok(Some(1)));
+    }
+
+    #[test]
+    fn test_read_string_into() {
+        let mut pb = Protobuf::new();
+        pb.write_string("héllo");
+        pb.write_string("too long for it");
+        pb.write_bytes_field(1, &[0xff, 0xfe]);
+        let mut pb = Protobuf::from_input(pb.take());
+
+        let mut out = [0_u8; 8];
+        assert_eq!(pb.read_string_into(&mut out), Ok(6));
+        assert_eq!(core::str::from_utf8(&out[..6]), Ok("héllo"));
+
+        let pos = pb.get_pos();
+        assert_eq!(
+            pb.read_string_into(&mut out),
+            Err(PbfError::LengthMismatch { expected: 8, found: 15 })
+        );
+        assert_eq!(pb.get_pos(), pos);
+        let mut out = [0_u8; 16];
+        assert_eq!(pb.read_string_into(&mut out), Ok(15));
+
+        pb.read_field();
+        let pos = pb.get_pos();
+        assert_eq!(pb.read_string_into(&mut out), Err(PbfError::InvalidUtf8));
+        assert_eq!(pb.get_pos(), pos);
+
+        let mut pb = Protobuf::from_input(vec![5, b'a']);
+        assert_eq!(pb.read_string_into(&mut out), Err(PbfError::UnexpectedEof));
+        assert_eq!(pb.get_pos(), 0);
+    }
+
+    #[test]
+    fn test_read_fields_len() {
+        #[derive(Default)]
+        struct Tags(Vec<u64>);
+        impl ProtoRead for Tags {
+            fn read(&mut self, tag: u64, _pb: &mut Protobuf) {
+                self.0.push(tag);
+            }
+        }
+
+        let mut pb = Protobuf::new();
+        pb.write_varint_field(1, 1_u32);
+        pb.write_varint_field(2, 2_u32);
+        pb.write_string_field(3, "ab");
+        pb.write_varint_field(4, 4_u32);
+        let mut pb = Protobuf::from_input(pb.take());
+
+        pb.set_pos(2);
+        let mut tags = Tags::default();
+        pb.read_fields_len(&mut tags, 6);
+        assert_eq!(tags.0, vec![2, 3]);
+        assert_eq!(pb.get_pos(), 8);
+        assert_eq!(pb.read_field(), Field { tag: 4, r#type: Type::Varint });
+    }
+
+    #[test]
+    fn test_try_read_fields() {
+        #[derive(Default)]
+        struct Lenient {
+            id: u64,
+            name: String,
+        }
+        impl ProtoRead for Lenient {
+            fn read(&mut self, tag: u64, pb: &mut Protobuf) {
+                if tag == 1
+                    && let Ok(id) = pb.try_decode_varint()
+                {
+                    self.id = id;
+                } else if tag == 2
+                    && let Ok(name) = pb.try_read_string()
+                {
+                    self.name = name;
+                }
+            }
+        }
+
+        let mut pb = Protobuf::new();
+        pb.write_varint_field(1, 300_u32);
+        pb.write_string_field(2, "skipped");
+        pb.write_field(3, Type::StartGroup);
+        pb.write_fixed_field(4, 1.0_f64);
+        pb.write_field(3, Type::EndGroup);
+        let bytes = pb.take();
+
+        let mut msg = Lenient::default();
+        assert_eq!(Protobuf::from_input(bytes.clone()).try_read_fields(&mut msg, None), Ok(()));
+        assert_eq!(msg.id, 300);
+
+        // every truncation of a valid message fails cleanly
+        for len in [1, 2, 4, 8, 13, bytes.len() - 1] {
+            let mut pb = Protobuf::from_input(bytes[..len].to_vec());
+            let res = pb.try_read_fields(&mut Lenient::default(), None);
+            assert_eq!(res, Err(PbfError::UnexpectedEof), "truncated at {}", len);
+        }
+        // a well framed value its reader rejects is dropped
+        let mut pb = Protobuf::new();
+        pb.write_bytes_field(2, &[0xff]);
+        pb.write_varint_field(1, 7_u32);
+        let mut msg = Lenient::default();
+        assert_eq!(Protobuf::from_input(pb.take()).try_read_fields(&mut msg, None), Ok(()));
+        assert_eq!((msg.id, msg.name.as_str()), (7, ""));
+        let mut pb = Protobuf::from_input(vec![0xff; 11]);
+        assert_eq!(pb.try_read_fields(&mut msg, None), Err(PbfError::VarintOverflow));
+        let mut pb = Protobuf::from_input(vec![8, 1]);
+        assert_eq!(pb.try_read_fields(&mut msg, Some(3)), Err(PbfError::UnexpectedEof));
+
+        // arbitrary input never panics
+        let mut seed: u64 = 0x9e37_79b9_7f4a_7c15;
+        for _ in 0..2000 {
+            let len = (seed % 24) as usize;
+            let input: Vec<u8> = (0..len)
+                .map(|_| {
+                    seed ^= seed << 13;
+                    seed ^= seed >> 7;
+                    seed ^= seed << 17;
+                    seed as u8
+                })
+                .collect();
+            let _ = Protobuf::from_input(input).try_read_fields(&mut Lenient::default(), None);
+        }
+    }
+
+    #[test]
+    fn test_skip_deeply_nested_groups() {
+        #[derive(Default)]
+        struct Empty;
+        impl ProtoRead for Empty {
+            fn read(&mut self, _tag: u64, _pb: &mut Protobuf) {}
+        }
 
-        let bytes = pb.take();
+        // two million unclosed StartGroup keys
+        let open = vec![0x0b; 2_000_000];
+        let mut pb = Protobuf::from_input(open.clone());
+        assert_eq!(pb.try_read_fields(&mut Empty, None), Err(PbfError::UnexpectedEof));
+        let mut pb = Protobuf::from_input(open.clone());
+        pb.read_field();
+        assert_eq!(pb.try_skip(Type::StartGroup), Err(PbfError::UnexpectedEof));
+        assert_eq!(pb.get_pos(), 1);
+
+        // the same depth, closed, is skipped in full
+        let mut bytes = open;
+        bytes.extend(core::iter::repeat_n(0x0c, 2_000_000));
+        let mut pb = Protobuf::from_input(bytes.clone());
+        assert_eq!(pb.try_read_fields(&mut Empty, None), Ok(()));
+        let mut pb = Protobuf::from_input(bytes.clone());
+        pb.read_field();
+        pb.skip(Type::StartGroup);
+        assert_eq!(pb.remaining(), 0);
         let mut pb = Protobuf::from_input(bytes);
-        pb.read_varint::<u64>();
-        pb.read_varint::<u64>();
-        pb.read_varint::<u64>();
+        pb.read_field();
+        assert_eq!(pb.read_field_raw(Type::StartGroup).len(), 3_999_999);
     }
 
     #[test]
-    fn test_varint() {
+    fn test_nonzero_varint() {
+        use core::num::{NonZeroI32, NonZeroU32};
+
         let mut pb = Protobuf::new();
-        pb.write_varint(1);
-        pb.write_varint(300);
-        pb.write_varint(0x7fffffffffffffff_u64);
+        pb.write_varint_field(1, NonZeroU32::new(5).unwrap());
+        pb.write_varint_field(2, NonZeroI32::new(-3).unwrap());
+        pb.write_varint_field(3, 0_u32);
+
+        let mut pb = Protobuf::from_input(pb.take());
+        pb.read_field();
+        assert_eq!(pb.read_varint::<NonZeroU32>(), NonZeroU32::new(5).unwrap());
+        pb.read_field();
+        assert_eq!(pb.read_varint::<NonZeroI32>(), NonZeroI32::new(-3).unwrap());
+        pb.read_field();
+        let pos = pb.pos;
+        assert_eq!(pb.try_read_varint::<NonZeroU32>(), Err(PbfError::InvalidValue(0)));
+        assert_eq!(pb.pos, pos);
+    }
 
-        let bytes = pb.take();
-        assert_eq!(bytes, &[1, 172, 2, 255, 255, 255, 255, 255, 255, 255, 255, 127]);
+    #[test]
+    #[should_panic(expected = "Invalid NonZeroU32 value: 0")]
+    fn test_nonzero_varint_zero() {
+        let mut pb = Protobuf::from_input(vec![0]);
+        pb.read_varint::<core::num::NonZeroU32>();
+    }
 
-        let mut pb = Protobuf::from_input(bytes);
-        assert_eq!(pb.read_varint::<u64>(), 1);
-        assert_eq!(pb.read_varint::<u64>(), 300);
-        assert_eq!(pb.read_varint::<u64>(), 0x7fffffffffffffff);
+    #[test]
+    fn test_zigzag32() {
+        assert_eq!(zigzag32(-1), 1);
+        assert_eq!(zigzag(-1), 1);
+        for val in [0, 1, -1, 2, -2, 63, -64, i32::MAX, i32::MIN] {
+            assert_eq!(zigzag32(val) as u64, zigzag(val as i64));
+            assert_eq!(zagzig32(zigzag32(val)), val);
+        }
+        assert_eq!(zigzag32(i32::MIN), u32::MAX);
+
+        // same bytes as the 64-bit path
+        let mut pb = Protobuf::new();
+        pb.write_s_varint_field(1, i32::MIN);
+        let bytes = pb.take();
+        pb.write_field(1, Type::Varint);
+        pb.write_varint(zigzag32(i32::MIN));
+        assert_eq!(pb.take(), bytes);
     }
 
     #[test]
-    fn test_varint_field() {
+    fn test_read_packed_append() {
         let mut pb = Protobuf::new();
-        // unsigned
-        pb.write_varint_field(0, 5_u8);
-        pb.write_varint_field(1, 1_u16);
-        pb.write_varint_field(2, 300_u32);
-        pb.write_varint_field(3, 0x7fffffffffffffff_u64);
-        // signed
-        pb.write_varint_field(4, -5_i8);
-        pb.write_varint_field(5, -1_i16);
-        pb.write_varint_field(6, -300_i32);
-        pb.write_varint_field(7, -94949494949_i64);
-        // bool
-        pb.write_varint_field(8, true);
-        pb.write_varint_field(9, false);
-        // enum
-        #[derive(Debug, PartialEq)]
-        enum TestEnum {
-            A = 1,
-            B = 2,
-            C = 3,
+        pb.write_packed_varint(1, &[1_u32, 2, 3]);
+        pb.write_packed_varint(1, &[4_u32]);
+
+        let mut pb = Protobuf::from_input(pb.take());
+        let mut out = vec![0_u32];
+        while pb.remaining() > 0 {
+            assert_eq!(pb.read_field().tag, 1);
+            pb.read_packed_append(&mut out);
         }
-        impl BitCast for TestEnum {
-            fn from_u64(val: u64) -> Self {
-                match val {
-                    1 => TestEnum::A,
-                    2 => TestEnum::B,
-                    3 => TestEnum::C,
-                    _ => panic!("Invalid enum value"),
-                }
-            }
-            fn to_u64(&self) -> u64 {
-                match self {
-                    TestEnum::A => 1,
-                    TestEnum::B => 2,
-                    TestEnum::C => 3,
+        assert_eq!(out, vec![0, 1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn test_read_fields_strict() {
+        #[derive(Default)]
+        struct Named {
+            id: u64,
+            name: String,
+        }
+        impl ProtoRead for Named {
+            fn read(&mut self, tag: u64, pb: &mut Protobuf) {
+                match tag {
+                    0 => self.id = pb.read_varint(),
+                    1 => self.name = pb.read_string(),
+                    _ => {}
                 }
             }
         }
-        pb.write_varint_field(10, TestEnum::B);
-        pb.write_varint_field(11, TestEnum::A);
-        pb.write_varint_field(12, TestEnum::C);
-        // float
-        pb.write_varint_field(13, core::f32::consts::PI);
-        pb.write_varint_field(14, -core::f64::consts::PI);
 
+        let mut pb = Protobuf::new();
+        pb.write_varint_field(0, 300_u64);
+        pb.write_string_field(1, "name");
+        pb.write_fixed_field(2, 1.5_f64);
         let bytes = pb.take();
-        let mut pb = Protobuf::from_input(bytes);
+        let mut named = Named::default();
+        assert_eq!(Protobuf::from_input(bytes).read_fields_strict(&mut named, None), Ok(()));
+        assert_eq!((named.id, named.name.as_str()), (300, "name"));
 
-        // unsigned
-        // tag 0
-        assert_eq!(pb.read_field(), Field { tag: 0, r#type: Type::Varint });
-        assert_eq!(pb.read_varint::<u8>(), 5);
-        // tag 1
-        assert_eq!(pb.read_field(), Field { tag: 1, r#type: Type::Varint });
-        assert_eq!(pb.read_varint::<u16>(), 1);
-        // tag 2
-        assert_eq!(pb.read_field(), Field { tag: 2, r#type: Type::Varint });
-        assert_eq!(pb.read_varint::<u32>(), 300);
-        // tag 3
-        assert_eq!(pb.read_field(), Field { tag: 3, r#type: Type::Varint });
-        assert_eq!(pb.read_varint::<u64>(), 0x7fffffffffffffff);
+        // tag 1 holds a varint, which `read_string` takes as a length prefix
+        let mut pb = Protobuf::new();
+        pb.write_varint_field(0, 1_u64);
+        pb.write_varint_field(1, 2_u64);
+        pb.write_varint_field(0, 3_u64);
+        let mut pb = Protobuf::from_input(pb.take());
+        let res = pb.read_fields_strict(&mut Named::default(), None);
+        assert_eq!(res, Err(PbfError::WireTypeMismatch { tag: 1, wire_type: 0 }));
+        assert_eq!(pb.get_pos(), 2);
+    }
 
-        // signed
-        // tag 4
-        assert_eq!(pb.read_field(), Field { tag: 4, r#type: Type::Varint });
-        assert_eq!(pb.read_varint::<i8>(), -5);
-        // tag 5
-        assert_eq!(pb.read_field(), Field { tag: 5, r#type: Type::Varint });
-        assert_eq!(pb.read_varint::<i16>(), -1);
-        // tag 6
-        assert_eq!(pb.read_field(), Field { tag: 6, r#type: Type::Varint });
-        assert_eq!(pb.read_varint::<i32>(), -300);
-        // tag 7
-        assert_eq!(pb.read_field(), Field { tag: 7, r#type: Type::Varint });
-        assert_eq!(pb.read_varint::<i64>(), -94949494949);
+    #[test]
+    fn test_sfixed_newtypes() {
+        let mut pb = Protobuf::new();
+        pb.write_fixed_field(1, SFixed32(-12345));
+        pb.write_fixed_field(2, SFixed64(i64::MIN));
+        let bytes = pb.take();
+        assert_eq!(bytes.len(), 1 + 4 + 1 + 8);
+        assert_eq!(&bytes[..5], &[(1 << 3) | 5, 0xc7, 0xcf, 0xff, 0xff]);
 
-        // bool
-        // tag 8
-        assert_eq!(pb.read_field(), Field { tag: 8, r#type: Type::Varint });
-        assert!(pb.read_varint::<bool>());
-        // tag 9
-        assert_eq!(pb.read_field(), Field { tag: 9, r#type: Type::Varint });
-        assert!(!pb.read_varint::<bool>());
+        let mut pb = Protobuf::from_input(bytes);
+        assert_eq!(pb.read_field().r#type, Type::Fixed32);
+        assert_eq!(pb.read_fixed::<SFixed32>(), SFixed32(-12345));
+        assert_eq!(pb.read_field().r#type, Type::Fixed64);
+        assert_eq!(pb.read_fixed::<SFixed64>(), SFixed64(i64::MIN));
+    }
 
-        // enum
-        // tag 10
-        assert_eq!(pb.read_field(), Field { tag: 10, r#type: Type::Varint });
-        assert_eq!(pb.read_varint::<TestEnum>(), TestEnum::B);
-        // tag 11
-        assert_eq!(pb.read_field(), Field { tag: 11, r#type: Type::Varint });
-        assert_eq!(pb.read_varint::<TestEnum>(), TestEnum::A);
-        // tag 12
-        assert_eq!(pb.read_field(), Field { tag: 12, r#type: Type::Varint });
-        assert_eq!(pb.read_varint::<TestEnum>(), TestEnum::C);
+    #[test]
+    fn test_read_field_raw() {
+        let mut pb = Protobuf::new();
+        pb.write_varint_field(1, 300_u32);
+        pb.write_fixed_field(2, 1.5_f32);
+        pb.write_fixed_field(3, -2_i64);
+        pb.write_string_field(4, "raw");
+        pb.write_field(5, Type::StartGroup);
+        pb.write_varint_field(1, 1_u32);
+        pb.write_field(5, Type::EndGroup);
+        let bytes = pb.take();
 
-        // float
-        // tag 13
-        assert_eq!(pb.read_field(), Field { tag: 13, r#type: Type::Varint });
-        assert_eq!(pb.read_varint::<f32>(), core::f32::consts::PI);
-        // tag 14
-        assert_eq!(pb.read_field(), Field { tag: 14, r#type: Type::Varint });
-        assert_eq!(pb.read_varint::<f64>(), -core::f64::consts::PI);
+        let mut pb = Protobuf::from_input(bytes.clone());
+        let mut copy = Protobuf::new();
+        let mut raws = Vec::new();
+        while pb.remaining() > 0 {
+            let field = pb.read_field();
+            let raw = pb.read_field_raw(field.r#type);
+            copy.write_field(field.tag, field.r#type);
+            copy.write_raw(&raw);
+            raws.push(raw);
+        }
+        assert_eq!(copy.take(), bytes);
+        assert_eq!(raws[0], vec![0xac, 0x02]);
+        assert_eq!(raws[1], 1.5_f32.to_le_bytes());
+        assert_eq!(raws[2], (-2_i64).to_le_bytes());
+        assert_eq!(raws[3], vec![3, b'r', b'a', b'w']);
+        assert_eq!(raws[4], vec![1 << 3, 1, (5 << 3) | 4]);
     }
 
     #[test]
-    fn test_varint_field_2() {
+    fn test_packed_iter() {
+        let values: Vec<u32> = (0..1000).map(|i| i * 7).collect();
         let mut pb = Protobuf::new();
-        pb.write_varint_field(1, 5_u8);
-        pb.write_varint_field(2, 5_u16);
-        pb.write_varint_field(3, 5_u32);
-        pb.write_varint_field(4, 5_u64);
-        pb.write_varint_field(5, 5_usize);
-        pb.write_varint_field(6, -5_isize);
+        pb.write_packed_varint(1, &values);
+        pb.write_varint_field(2, 9_u32);
 
-        let bytes = pb.take();
-        let mut pb: Protobuf = bytes.into();
+        let mut pb = Protobuf::from_input(pb.take());
+        pb.read_field();
+        let sum: u64 = pb.packed_iter::<u32>().map(u64::from).sum();
+        assert_eq!(sum, values.iter().map(|&v| v as u64).sum::<u64>());
+        assert_eq!(pb.read_field().tag, 2);
 
-        assert_eq!(pb.read_field(), Field { tag: 1, r#type: Type::Varint });
-        assert_eq!(pb.read_varint::<u8>(), 5);
-        assert_eq!(pb.read_field(), Field { tag: 2, r#type: Type::Varint });
-        assert_eq!(pb.read_varint::<u16>(), 5);
-        assert_eq!(pb.read_field(), Field { tag: 3, r#type: Type::Varint });
-        assert_eq!(pb.read_varint::<u32>(), 5);
-        assert_eq!(pb.read_field(), Field { tag: 4, r#type: Type::Varint });
-        assert_eq!(pb.read_varint::<u64>(), 5);
-        assert_eq!(pb.read_field(), Field { tag: 5, r#type: Type::Varint });
-        assert_eq!(pb.read_varint::<usize>(), 5);
-        assert_eq!(pb.read_field(), Field { tag: 6, r#type: Type::Varint });
-        assert_eq!(pb.read_varint::<isize>(), -5);
+        // a partially consumed iterator leaves the position after the last element it read
+        let mut pb = Protobuf::new();
+        pb.write_packed_varint(1, &[1_u32, 300, 2]);
+        let mut pb = Protobuf::from_input(pb.take());
+        pb.read_field();
+        let mut iter = pb.packed_iter::<u32>();
+        assert_eq!(iter.next(), Some(1));
+        assert_eq!(iter.next(), Some(300));
+        assert_eq!(pb.get_pos(), 5);
     }
 
     #[test]
-    fn test_s_varint_field() {
+    fn test_split_messages() {
         let mut pb = Protobuf::new();
-        pb.write_s_varint_field(1, 5_i8);
-        pb.write_s_varint_field(2, 5_i16);
-        pb.write_s_varint_field(3, 5_i32);
-        pb.write_s_varint_field(4, 5_i64);
-        pb.write_s_varint_field(5, -5_i8);
-        pb.write_s_varint_field(6, -5_i16);
-        pb.write_s_varint_field(7, -5_i32);
-        pb.write_s_varint_field(8, -5_i64);
+        pb.write_packed_varint(1, &[3_u64, 200, 0]);
+        let mut pb = Protobuf::from_input(pb.take());
+        pb.read_field();
+        assert_eq!(pb.read_packed_lengths(), vec![3, 200, 0]);
 
-        let bytes = pb.take();
-        let mut pb = Protobuf::from_input(bytes);
+        let mut pb = Protobuf::new();
+        for name in ["a", "", &"x".repeat(130)] {
+            let mut msg = Protobuf::new();
+            if !name.is_empty() {
+                msg.write_string_field(1, name);
+            }
+            pb.write_varint(msg.len());
+            pb.write_raw(&msg.take());
+        }
 
-        assert_eq!(pb.read_field(), Field { tag: 1, r#type: Type::Varint });
-        assert_eq!(pb.read_s_varint::<i8>(), 5);
-        assert_eq!(pb.read_field(), Field { tag: 2, r#type: Type::Varint });
-        assert_eq!(pb.read_s_varint::<i16>(), 5);
-        assert_eq!(pb.read_field(), Field { tag: 3, r#type: Type::Varint });
-        assert_eq!(pb.read_s_varint::<i32>(), 5);
-        assert_eq!(pb.read_field(), Field { tag: 4, r#type: Type::Varint });
-        assert_eq!(pb.read_s_varint::<i64>(), 5);
-        assert_eq!(pb.read_field(), Field { tag: 5, r#type: Type::Varint });
-        assert_eq!(pb.read_s_varint::<i8>(), -5);
-        assert_eq!(pb.read_field(), Field { tag: 6, r#type: Type::Varint });
-        assert_eq!(pb.read_s_varint::<i16>(), -5);
-        assert_eq!(pb.read_field(), Field { tag: 7, r#type: Type::Varint });
-        assert_eq!(pb.read_s_varint::<i32>(), -5);
-        assert_eq!(pb.read_field(), Field { tag: 8, r#type: Type::Varint });
-        assert_eq!(pb.read_s_varint::<i64>(), -5);
+        let mut pb = Protobuf::from_input(pb.take());
+        let parts = pb.split_messages();
+        // 1 byte prefix + 3 bytes, 1 byte prefix + 0 bytes, 2 byte prefix + 133 bytes
+        assert_eq!(parts, vec![(1, 3), (5, 0), (7, 133)]);
+        assert_eq!(pb.remaining(), 0);
+
+        pb.set_pos(parts[2].0);
+        pb.read_field();
+        assert_eq!(pb.read_string().len(), 130);
+        assert_eq!(pb.get_pos(), parts[2].0 + parts[2].1);
+    }
+
+    #[derive(Default)]
+    struct Chain {
+        depth: usize,
+        error: Option<PbfError>,
+    }
+    impl ProtoRead for Chain {
+        fn read(&mut self, tag: u64, pb: &mut Protobuf) {
+            if tag == 1 {
+                let mut child = Chain::default();
+                let res = pb.try_read_message(&mut child);
+                self.error = res.err().or(child.error);
+                self.depth = child.depth + 1;
+            }
+        }
+    }
+
+    fn nested_chain(depth: usize) -> Vec<u8> {
+        let mut msg = Vec::new();
+        for _ in 0..depth {
+            let mut pb = Protobuf::new();
+            pb.write_bytes_field(1, &msg);
+            msg = pb.take();
+        }
+        msg
     }
 
     #[test]
-    fn test_fixed() {
-        let mut pb = Protobuf::new();
-        pb.write_fixed_field(1, 5_u32);
-        pb.write_fixed_field(2, -5_i32);
-        pb.write_fixed_field(3, 5.5_f32);
-        pb.write_fixed_field(4, 5_u64);
-        pb.write_fixed_field(5, -5_i64);
-        pb.write_fixed_field(6, 5.5_f64);
+    fn test_max_depth() {
+        let mut pb = Protobuf::from_input(nested_chain(200));
+        assert_eq!(pb.max_depth(), DEFAULT_MAX_DEPTH);
+        let mut chain = Chain::default();
+        pb.read_fields(&mut chain, None);
+        assert_eq!(chain.error, Some(PbfError::RecursionLimitExceeded));
+
+        let mut pb = Protobuf::from_input(nested_chain(200));
+        pb.set_max_depth(200);
+        let mut chain = Chain::default();
+        pb.read_fields(&mut chain, None);
+        assert_eq!((chain.depth, chain.error), (200, None));
+    }
 
-        let bytes = pb.take();
-        let mut pb = Protobuf::from_input(bytes);
+    #[test]
+    #[should_panic(expected = "recursion limit exceeded")]
+    fn test_max_depth_read_message() {
+        #[derive(Default)]
+        struct Node;
+        impl ProtoRead for Node {
+            fn read(&mut self, tag: u64, pb: &mut Protobuf) {
+                if tag == 1 {
+                    pb.read_message(&mut Node);
+                }
+            }
+        }
 
-        assert_eq!(pb.read_field(), Field { tag: 1, r#type: Type::Fixed32 });
-        assert_eq!(pb.read_fixed::<u32>(), 5);
-        assert_eq!(pb.read_field(), Field { tag: 2, r#type: Type::Fixed32 });
-        assert_eq!(pb.read_fixed::<i32>(), -5);
-        assert_eq!(pb.read_field(), Field { tag: 3, r#type: Type::Fixed32 });
-        assert_eq!(pb.read_fixed::<f32>(), 5.5);
-        assert_eq!(pb.read_field(), Field { tag: 4, r#type: Type::Fixed64 });
-        assert_eq!(pb.read_fixed::<u64>(), 5);
-        assert_eq!(pb.read_field(), Field { tag: 5, r#type: Type::Fixed64 });
-        assert_eq!(pb.read_fixed::<i64>(), -5);
-        assert_eq!(pb.read_field(), Field { tag: 6, r#type: Type::Fixed64 });
-        assert_eq!(pb.read_fixed::<f64>(), 5.5);
+        let mut pb = Protobuf::from_input(nested_chain(200));
+        pb.read_fields(&mut Node, None);
     }
 
     #[test]
-    #[should_panic(expected = "Invalid fixed type")]
-    fn test_fixed_panic() {
+    fn test_field_key() {
         let mut pb = Protobuf::new();
-        pb.write_fixed_field(1, 1_u8);
+        pb.write_varint_field(1, 1_u32);
+        pb.write_string_field(300, "x");
+        pb.write_fixed_field(2, 1_u32);
+        let bytes = pb.take();
+
+        let mut pb = Protobuf::from_input(bytes.clone());
+        let mut keys = Protobuf::new();
+        let mut original = Vec::new();
+        let mut values = Vec::new();
+        while pb.remaining() > 0 {
+            let key_pos = pb.get_pos();
+            let field = pb.read_field();
+            original.extend_from_slice(&bytes[key_pos..pb.get_pos()]);
+            field.write_key(&mut keys);
+            values.push(field.key());
+            pb.skip(field.r#type);
+        }
+        assert_eq!(values, vec![8, (300 << 3) | 2, (2 << 3) | 5]);
+        assert_eq!(keys.take(), original);
     }
 
     #[test]
-    fn test_string() {
+    fn test_read_string_lossy() {
         let mut pb = Protobuf::new();
-        pb.write_string_field(1, "hello");
-        pb.write_string_field(2, "world");
+        pb.write_string_field(1, "héllo");
+        pb.write_bytes_field(2, &[b'a', 0xff, b'b']);
+        pb.write_bytes_field(3, b"abc");
+        let mut bytes = pb.take();
+
+        let mut pb = Protobuf::from_input(bytes.clone());
+        pb.read_field();
+        assert_eq!(pb.try_read_string(), Ok("héllo".to_string()));
+        pb.read_field();
+        let pos = pb.get_pos();
+        assert_eq!(pb.try_read_string(), Err(PbfError::InvalidUtf8));
+        assert_eq!(pb.get_pos(), pos);
+        assert_eq!(pb.read_string_lossy(), "a\u{fffd}b");
+
+        // truncated in the middle of the last string
+        bytes.pop();
+        let mut pb = Protobuf::from_input(bytes);
+        pb.set_pos(pb.len() - 3);
+        assert_eq!(pb.try_read_string(), Err(PbfError::UnexpectedEof));
+        assert_eq!(pb.get_pos(), pb.len() - 3);
+    }
+
+    #[test]
+    fn test_well_known_timestamp() {
+        use well_known::{Duration, Timestamp};
 
+        let before_epoch = Timestamp::new(-62_135_596_800, 500_000_000);
+        let mut pb = Protobuf::new();
+        pb.write_message(1, &before_epoch);
+        pb.write_message(2, &Duration::new(-3, -250));
+        pb.write_message(3, &Timestamp::default());
         let bytes = pb.take();
-        let mut pb = Protobuf::from_input(bytes);
+        // negative int64 varints take 10 bytes, zero fields are omitted
+        assert_eq!(bytes[1], 1 + 10 + 1 + 5);
+        assert_eq!(&bytes[bytes.len() - 2..], &[(3 << 3) | 2, 0]);
 
-        assert_eq!(pb.read_field(), Field { tag: 1, r#type: Type::Bytes });
-        assert_eq!(pb.read_string(), "hello");
-        assert_eq!(pb.read_field(), Field { tag: 2, r#type: Type::Bytes });
-        assert_eq!(pb.read_string(), "world");
+        let mut pb = Protobuf::from_input(bytes);
+        let mut ts = Timestamp::default();
+        let mut duration = Duration::default();
+        let mut empty = Timestamp::default();
+        pb.read_field();
+        pb.read_message(&mut ts);
+        pb.read_field();
+        pb.read_message(&mut duration);
+        pb.read_field();
+        pb.read_message(&mut empty);
+        assert_eq!(ts, before_epoch);
+        assert_eq!(duration, Duration::new(-3, -250));
+        assert_eq!(empty, Timestamp::new(0, 0));
     }
 
     #[test]
-    fn test_bytes() {
+    fn test_read_all() {
         let mut pb = Protobuf::new();
-        pb.write_bytes_field(1, &[1, 2, 3]);
-        pb.write_bytes_field(2, &[4, 5, 6]);
+        pb.write_varint_field(1, 150_u32);
+        pb.write_fixed_field(2, 1.0_f32);
+        pb.write_fixed_field(3, -1_i64);
+        pb.write_string_field(4, "hi");
+        pb.write_field(5, Type::StartGroup);
+        pb.write_s_varint_field(1, -2);
+        pb.write_field(5, Type::EndGroup);
+        pb.write_varint_field(1, 7_u32);
+
+        let mut pb = Protobuf::from_input(pb.take());
+        pb.set_pos(3);
+        assert_eq!(
+            pb.read_all(),
+            vec![
+                (1, WireValue::Varint(150)),
+                (2, WireValue::Fixed32(1.0_f32.to_le_bytes())),
+                (3, WireValue::Fixed64([0xff; 8])),
+                (4, WireValue::Bytes(b"hi".to_vec())),
+                (5, WireValue::Group(vec![(1, WireValue::Varint(3))])),
+                (1, WireValue::Varint(7)),
+            ]
+        );
+        assert_eq!(pb.get_pos(), 3);
+    }
 
+    #[test]
+    fn test_len_delimited_mark() {
+        let mut pb = Protobuf::new();
+        let outer = pb.begin_len_delimited(1);
+        pb.write_varint_field(1, 1_u32);
+        let inner = pb.begin_len_delimited(2);
+        pb.write_string_field(1, &"x".repeat(200));
+        pb.end_len_delimited(inner);
+        pb.write_varint_field(3, 2_u32);
+        pb.end_len_delimited(outer);
+
+        // the same message through a temporary buffer per level
+        let mut expected = Protobuf::new();
+        expected.write_message_closure(1, |pb| {
+            pb.write_varint_field(1, 1_u32);
+            pb.write_message_closure(2, |pb| pb.write_string_field(1, &"x".repeat(200)));
+            pb.write_varint_field(3, 2_u32);
+        });
         let bytes = pb.take();
-        let mut pb = Protobuf::from_input(bytes);
+        assert_eq!(bytes, expected.take());
 
+        let mut pb = Protobuf::from_input(bytes);
         assert_eq!(pb.read_field(), Field { tag: 1, r#type: Type::Bytes });
-        assert_eq!(pb.read_bytes(), &[1, 2, 3]);
-        assert_eq!(pb.read_field(), Field { tag: 2, r#type: Type::Bytes });
-        assert_eq!(pb.read_bytes(), &[4, 5, 6]);
+        // 2 + (key + 2 byte length + 203 byte body) + 2
+        assert_eq!(pb.decode_varint(), 2 + 3 + 203 + 2);
+        pb.read_field();
+        pb.decode_varint();
+        pb.read_field();
+        assert_eq!(pb.decode_varint(), 203);
+
+        let mut counter = Protobuf { count_only: true, ..Default::default() };
+        let mark = counter.begin_len_delimited(1);
+        counter.write_string_field(1, &"x".repeat(200));
+        counter.end_len_delimited(mark);
+        assert_eq!(counter.counted, 1 + 2 + 203);
+
+        // several levels deep, each with a small sibling message and a body over 127 bytes,
+        // after a field already in the buffer
+        fn marks(pb: &mut Protobuf, depth: u32) {
+            pb.write_varint_field(1, depth);
+            let small = pb.begin_len_delimited(2);
+            pb.write_varint_field(1, depth);
+            pb.end_len_delimited(small);
+            if depth == 0 {
+                pb.write_string_field(3, &"y".repeat(300));
+                return;
+            }
+            let child = pb.begin_len_delimited(4);
+            marks(pb, depth - 1);
+            pb.end_len_delimited(child);
+        }
+        fn closures(pb: &mut Protobuf, depth: u32) {
+            pb.write_varint_field(1, depth);
+            pb.write_message_closure(2, |pb| pb.write_varint_field(1, depth));
+            if depth == 0 {
+                pb.write_string_field(3, &"y".repeat(300));
+                return;
+            }
+            pb.write_message_closure(4, |pb| closures(pb, depth - 1));
+        }
+        for depth in [1, 5, 40] {
+            let mut pb = Protobuf::new();
+            pb.write_string_field(9, "prefix");
+            marks(&mut pb, depth);
+            let mut expected = Protobuf::new();
+            expected.write_string_field(9, "prefix");
+            closures(&mut expected, depth);
+            assert_eq!(pb.take(), expected.take(), "depth {}", depth);
+        }
+
+        // clear drops an unfinished mark, the next message is compacted as usual
+        let mut pb = Protobuf::new();
+        let _ = pb.begin_len_delimited(1);
+        pb.clear();
+        let mark = pb.begin_len_delimited(2);
+        pb.write_varint_field(1, 7_u32);
+        pb.end_len_delimited(mark);
+        assert_eq!(pb.take(), vec![18, 2, 8, 7]);
     }
 
     #[test]
-    fn test_write_field() {
+    #[should_panic(expected = "end_len_delimited called without an open mark")]
+    fn test_len_delimited_mark_not_open() {
         let mut pb = Protobuf::new();
-        pb.write_field(1, Type::Varint);
-        pb.write_field(2, Type::None);
+        let mark = pb.begin_len_delimited(1);
+        pb.clear();
+        pb.end_len_delimited(mark);
+    }
 
+    #[test]
+    fn test_write_repeated_varint() {
+        let mut pb = Protobuf::new();
+        pb.write_repeated_varint(3, &[1_u32, 300, 0]);
+        pb.write_repeated_varint::<bool>(4, &[]);
         let bytes = pb.take();
-        let mut pb = Protobuf::from_input(bytes);
+        assert_eq!(bytes, vec![24, 1, 24, 172, 2, 24, 0]);
 
-        assert_eq!(pb.read_field(), Field { tag: 1, r#type: Type::Varint });
-        assert_eq!(pb.read_field(), Field { tag: 2, r#type: Type::None });
+        let mut pb = Protobuf::from_input(bytes);
+        let mut values: Vec<u32> = vec![];
+        while pb.remaining() > 0 {
+            assert_eq!(pb.read_field(), Field { tag: 3, r#type: Type::Varint });
+            values.push(pb.read_varint());
+        }
+        assert_eq!(values, vec![1, 300, 0]);
     }
 
     #[test]
@@ -1033,6 +4501,115 @@ mod tests {
         assert_eq!(pb.read_field(), Field { tag: 6, r#type: Type::Varint });
     }
 
+    #[test]
+    fn test_skip_message() {
+        let mut pb = Protobuf::new();
+        pb.write_bytes_field(1, &[8, 1, 16, 2]);
+        pb.write_bytes_field(2, &[8, 3]);
+        let mut pb = Protobuf::from_input(pb.take());
+
+        assert_eq!(pb.read_field(), Field { tag: 1, r#type: Type::Bytes });
+        pb.skip_message();
+        assert_eq!(pb.read_field(), Field { tag: 2, r#type: Type::Bytes });
+        let mut sub = pb.read_message_slice();
+        assert_eq!(sub.read_field(), Field { tag: 1, r#type: Type::Varint });
+        assert_eq!(sub.read_varint::<u32>(), 3);
+        assert!(!pb.has_remaining());
+
+        // length prefix of 5 with only 2 bytes left
+        let mut pb = Protobuf::from_input(vec![5, 8, 1]);
+        assert_eq!(pb.try_skip_message(), Err(PbfError::UnexpectedEof));
+        assert_eq!(pb.get_pos(), 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "unexpected EOF")]
+    fn test_skip_message_panic() {
+        let mut pb = Protobuf::from_input(vec![5, 8, 1]);
+        pb.skip_message();
+    }
+
+    #[test]
+    fn test_skip_group() {
+        let mut pb = Protobuf::new();
+        pb.write_field(1, Type::StartGroup);
+        pb.write_varint_field(2, 300);
+        pb.write_field(3, Type::StartGroup);
+        pb.write_string_field(4, "nested");
+        pb.write_fixed_field(5, 1.5_f64);
+        pb.write_field(3, Type::EndGroup);
+        pb.write_fixed_field(6, 7_u32);
+        pb.write_field(1, Type::EndGroup);
+        pb.write_varint_field(7, 1);
+
+        let bytes = pb.take();
+        let mut pb = Protobuf::from_input(bytes);
+
+        let field = pb.read_field();
+        assert_eq!(field, Field { tag: 1, r#type: Type::StartGroup });
+        pb.skip(field.r#type);
+        assert_eq!(pb.read_field(), Field { tag: 7, r#type: Type::Varint });
+        assert_eq!(pb.read_varint::<u8>(), 1);
+        assert_eq!(pb.get_pos(), pb.len());
+    }
+
+    #[test]
+    fn test_skip_group_mismatch() {
+        // group 5 closed by an EndGroup of field 6
+        let mut pb = Protobuf::new();
+        pb.write_field(5, Type::StartGroup);
+        pb.write_varint_field(1, 1_u32);
+        pb.write_field(6, Type::EndGroup);
+        let bytes = pb.take();
+        let mismatch = PbfError::GroupMismatch { expected: 5, found: 6 };
+
+        let mut pb = Protobuf::from_input(bytes.clone());
+        let field = pb.read_field();
+        assert_eq!(pb.try_skip(field.r#type), Err(mismatch));
+        assert_eq!(pb.get_pos(), 1);
+        assert_eq!(
+            Protobuf::from_input(bytes).debug_dump().lines().last(),
+            Some("error=group 5 was closed by an EndGroup with tag 6")
+        );
+
+        // a nested group closed by its parent's tag
+        let mut pb = Protobuf::new();
+        pb.write_field(5, Type::StartGroup);
+        pb.write_field(7, Type::StartGroup);
+        pb.write_field(5, Type::EndGroup);
+        pb.write_field(5, Type::EndGroup);
+        let mut pb = Protobuf::from_input(pb.take());
+        let field = pb.read_field();
+        assert_eq!(
+            pb.try_skip(field.r#type),
+            Err(PbfError::GroupMismatch { expected: 7, found: 5 })
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "group 5 was closed by an EndGroup with tag 6")]
+    fn test_read_group_mismatch() {
+        #[derive(Debug, Default)]
+        struct Inner {
+            a: u32,
+        }
+        impl ProtoRead for Inner {
+            fn read(&mut self, tag: u64, pb: &mut Protobuf) {
+                if tag == 1 {
+                    self.a = pb.read_varint();
+                }
+            }
+        }
+
+        let mut pb = Protobuf::new();
+        pb.write_field(5, Type::StartGroup);
+        pb.write_varint_field(1, 1_u32);
+        pb.write_field(6, Type::EndGroup);
+        let mut pb = Protobuf::from_input(pb.take());
+        pb.read_field();
+        pb.read_group(&mut Inner::default());
+    }
+
     #[test]
     fn test_packed_and_s_packed() {
         let mut pb = Protobuf::new();
@@ -1051,6 +4628,406 @@ mod tests {
         assert_eq!(pb.read_s_packed::<i32>(), vec![-1, -2, -3]);
     }
 
+    #[test]
+    fn test_packed_n() {
+        let mut pb = Protobuf::new();
+        pb.write_varint_field(1, 3_u32);
+        for v in [1_u32, 300, 70_000] {
+            pb.write_varint(v);
+        }
+        for v in [-1_i64, 0, i64::MIN] {
+            pb.write_s_varint(v);
+        }
+        pb.write_varint_field(2, 1_u32);
+
+        let mut pb = Protobuf::from_input(pb.take());
+        assert_eq!(pb.read_field(), Field { tag: 1, r#type: Type::Varint });
+        let count: usize = pb.read_varint();
+        assert_eq!(pb.read_packed_n::<u32>(count), vec![1, 300, 70_000]);
+        assert_eq!(pb.read_s_packed_n::<i64>(count), vec![-1, 0, i64::MIN]);
+        assert!(pb.read_packed_n::<u32>(0).is_empty());
+        assert_eq!(pb.read_field(), Field { tag: 2, r#type: Type::Varint });
+    }
+
+    #[test]
+    fn test_packed_fixed() {
+        let mut pb = Protobuf::new();
+        pb.write_packed_fixed::<f64>(1, &[1.1, -2.2, 3.3]);
+        pb.write_packed_fixed::<u32>(2, &[1, 2, 3, 4]);
+
+        let bytes = pb.take();
+        assert_eq!(bytes.len(), (2 + 3 * 8) + (2 + 4 * 4));
+        let mut pb = Protobuf::from_input(bytes);
+
+        assert_eq!(pb.read_field(), Field { tag: 1, r#type: Type::Bytes });
+        assert_eq!(pb.read_packed_fixed::<f64>(), vec![1.1, -2.2, 3.3]);
+        assert_eq!(pb.read_field(), Field { tag: 2, r#type: Type::Bytes });
+        assert_eq!(pb.read_packed_fixed::<u32>(), vec![1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn test_packed_s_fixed() {
+        let mut pb = Protobuf::new();
+        pb.write_packed_s_fixed::<i32>(1, &[-1, 0, 1, i32::MIN]);
+        pb.write_packed_s_fixed::<i64>(2, &[-1, i64::MAX, i64::MIN]);
+
+        let bytes = pb.take();
+        assert_eq!(bytes.len(), (2 + 4 * 4) + (2 + 3 * 8));
+        assert_eq!(&bytes[2..6], &[1, 0, 0, 0]);
+        let mut pb = Protobuf::from_input(bytes);
+
+        assert_eq!(pb.read_field(), Field { tag: 1, r#type: Type::Bytes });
+        assert_eq!(pb.read_s_packed_fixed::<i32>(), vec![-1, 0, 1, i32::MIN]);
+        assert_eq!(pb.read_field(), Field { tag: 2, r#type: Type::Bytes });
+        assert_eq!(pb.read_s_packed_fixed::<i64>(), vec![-1, i64::MAX, i64::MIN]);
+    }
+
+    #[test]
+    fn test_read_message_slice() {
+        let mut nested = Protobuf::new();
+        nested.write_varint_field(1, 42_u32);
+        nested.write_string_field(2, "nested");
+        let nested = nested.take();
+
+        let mut pb = Protobuf::new();
+        pb.write_bytes_field(1, &nested);
+        pb.write_varint_field(2, 7_u32);
+        let mut pb = Protobuf::from_input(pb.take());
+
+        fn parse(mut pb: Protobuf) -> (u32, String) {
+            let mut res = (0, String::new());
+            while let Some(field) = pb.next_field() {
+                match field.tag {
+                    1 => res.0 = pb.read_varint(),
+                    2 => res.1 = pb.read_string(),
+                    _ => pb.skip(field.r#type),
+                }
+            }
+            res
+        }
+
+        assert_eq!(pb.read_field(), Field { tag: 1, r#type: Type::Bytes });
+        let sub = pb.read_message_slice();
+        assert_eq!(sub.get_pos(), 0);
+        assert_eq!(sub.len(), nested.len());
+        assert_eq!(parse(sub), (42, "nested".into()));
+        // the parent cursor moved past the whole sub-message
+        assert_eq!(pb.get_pos(), 2 + nested.len());
+        assert_eq!(pb.read_field(), Field { tag: 2, r#type: Type::Varint });
+        assert_eq!(pb.read_varint::<u32>(), 7);
+    }
+
+    #[test]
+    fn test_map_field() {
+        let entries: Vec<(u32, u64)> = vec![(1, 10), (2, u64::MAX), (300, 0)];
+        let mut pb = Protobuf::new();
+        pb.write_map_field(4, entries.iter().copied());
+
+        let bytes = pb.take();
+        // first entry: tag 4 bytes, len 4, [1: 1, 2: 10]
+        assert_eq!(&bytes[..6], &[34, 4, 8, 1, 16, 10]);
+
+        let mut pb = Protobuf::from_input(bytes);
+        let mut res: Vec<(u32, u64)> = vec![];
+        while let Some(field) = pb.next_field() {
+            assert_eq!(field.tag, 4);
+            res.push(pb.read_map_entry());
+        }
+        assert_eq!(res, entries);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_flush_to() {
+        let mut sink: Vec<u8> = Vec::new();
+        let mut pb = Protobuf::new();
+        let mut flushed = 0;
+        for i in 0..10_000_u64 {
+            pb.write_varint_field(1, i);
+            if i % 1000 == 999 {
+                flushed += pb.flush_to(&mut sink).unwrap();
+                assert!(pb.is_empty());
+            }
+        }
+        flushed += pb.flush_to(&mut sink).unwrap();
+        assert_eq!(flushed, sink.len());
+
+        let mut pb = Protobuf::from_input(sink);
+        let mut i = 0;
+        while let Some(field) = pb.next_field() {
+            assert_eq!(field, Field { tag: 1, r#type: Type::Varint });
+            assert_eq!(pb.read_varint::<u64>(), i);
+            i += 1;
+        }
+        assert_eq!(i, 10_000);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_read_length_delimited() {
+        use std::io::{Cursor, ErrorKind};
+
+        #[derive(Debug, PartialEq, Default)]
+        struct Blob {
+            id: u32,
+            data: String,
+        }
+        impl ProtoWrite for Blob {
+            fn write(&self, pb: &mut Protobuf) {
+                pb.write_varint_field(1, self.id);
+                pb.write_string_field(2, &self.data);
+            }
+        }
+        impl ProtoRead for Blob {
+            fn read(&mut self, tag: u64, pb: &mut Protobuf) {
+                match tag {
+                    1 => self.id = pb.read_varint(),
+                    2 => self.data = pb.read_string(),
+                    _ => panic!("unknown tag {}", tag),
+                }
+            }
+        }
+
+        let blobs = [
+            Blob { id: 1, data: "first".into() },
+            Blob { id: 2, data: "x".repeat(300) },
+            Blob { id: 3, data: String::new() },
+        ];
+        let mut stream = Protobuf::new();
+        for blob in &blobs {
+            stream.write_varint(Protobuf::encoded_len(blob));
+            stream.write_fields(blob);
+        }
+        let mut cursor = Cursor::new(stream.take());
+
+        for blob in &blobs {
+            let mut res = Blob::default();
+            assert!(read_length_delimited(&mut cursor, &mut res).unwrap());
+            assert_eq!(&res, blob);
+        }
+        assert!(!read_length_delimited(&mut cursor, &mut Blob::default()).unwrap());
+
+        // truncated length prefix
+        let mut cursor = Cursor::new(vec![0x80]);
+        let err = read_length_delimited(&mut cursor, &mut Blob::default()).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+        // truncated body
+        let mut cursor = Cursor::new(vec![5, 8, 1]);
+        let err = read_length_delimited(&mut cursor, &mut Blob::default()).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+        // a length prefix far beyond the stream is not allocated up front
+        let mut cursor = Cursor::new(vec![0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x7f]);
+        let err = read_length_delimited(&mut cursor, &mut Blob::default()).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+        // a length prefix that never terminates
+        let mut cursor = Cursor::new(vec![0xff; 11]);
+        let err = read_length_delimited(&mut cursor, &mut Blob::default()).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_write_raw() {
+        let mut header = Protobuf::new();
+        header.write_string_field(1, "header");
+        header.write_varint_field(2, 7_u32);
+        let header = header.take();
+
+        let mut pb = Protobuf::new();
+        pb.write_raw(&header);
+        pb.write_varint_field(3, 9_u32);
+        pb.write_raw(&[]);
+        let bytes = pb.take();
+        assert_eq!(&bytes[..header.len()], &header[..]);
+        let mut copy = Protobuf::new();
+        UnknownFields { buf: header.clone() }.write_to(&mut copy);
+        assert_eq!(copy.take(), header);
+
+        let mut pb = Protobuf::from_input(bytes);
+        assert_eq!(pb.read_field(), Field { tag: 1, r#type: Type::Bytes });
+        assert_eq!(pb.read_string(), "header");
+        assert_eq!(pb.read_field(), Field { tag: 2, r#type: Type::Varint });
+        assert_eq!(pb.read_varint::<u32>(), 7);
+        assert_eq!(pb.read_field(), Field { tag: 3, r#type: Type::Varint });
+        assert_eq!(pb.read_varint::<u32>(), 9);
+        assert!(!pb.has_remaining());
+    }
+
+    #[test]
+    fn test_as_slice_and_clone_bytes() {
+        let mut pb = Protobuf::new();
+        pb.write_varint_field(1, 150_u32);
+        assert_eq!(pb.as_slice(), &[8, 150, 1]);
+        let first = pb.clone_bytes();
+        pb.write_string_field(2, "more");
+        assert_eq!(pb.as_slice().len(), 9);
+
+        let bytes = pb.take();
+        assert!(bytes.starts_with(&first));
+        assert_eq!(bytes.len(), first.len() + 6);
+        assert!(pb.as_slice().is_empty());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde_bridge() {
+        use serde::{Deserialize, Serialize};
+
+        #[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+        struct Nested {
+            name: String,
+            weight: f32,
+        }
+
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        enum Kind {
+            Empty,
+            Named(String),
+        }
+
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        struct TestStruct {
+            a: i32,
+            b: String,
+            c: Vec<u32>,
+            d: Vec<String>,
+            e: Option<u64>,
+            f: Nested,
+            g: Vec<Nested>,
+            h: Kind,
+            i: Kind,
+            j: Vec<f64>,
+            k: bool,
+        }
+
+        let a = TestStruct {
+            a: -5,
+            b: "hello".into(),
+            c: vec![1, 300, u32::MAX],
+            d: vec!["x".into(), "".into(), "z".into()],
+            e: Some(0),
+            f: Nested { name: "nested".into(), weight: 1.5 },
+            g: vec![Nested::default(), Nested { name: "g".into(), weight: -2.0 }],
+            h: Kind::Empty,
+            i: Kind::Named("named".into()),
+            j: vec![0.5, -1.25],
+            k: true,
+        };
+        let bytes = to_pbf_bytes(&a);
+        // a: zigzag(-5) = 9 at tag 0, b: "hello" at tag 1, c: packed at tag 2
+        assert_eq!(&bytes[..11], &[0, 9, 10, 5, 104, 101, 108, 108, 111, 18, 8]);
+        let b: TestStruct = from_pbf_bytes(&bytes).unwrap();
+        assert_eq!(a, b);
+
+        // fields and sequences absent from the buffer take their default value
+        let b: TestStruct = from_pbf_bytes(&[]).unwrap();
+        assert_eq!(b.a, 0);
+        assert!(b.c.is_empty() && b.d.is_empty() && b.g.is_empty());
+        assert_eq!((b.e, b.f, b.h), (None, Nested::default(), Kind::Empty));
+
+        // the same wire format as a hand written implementation with sequential tags
+        #[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+        struct Pair(u32, String);
+        let mut pb = Protobuf::new();
+        pb.write_varint_field(0, 7_u32);
+        pb.write_string_field(1, "seven");
+        pb.write_varint_field(5, 1_u32);
+        let bytes = pb.take();
+        assert_eq!(from_pbf_bytes::<Pair>(&bytes), Ok(Pair(7, "seven".into())));
+        assert_eq!(to_pbf_bytes(&Pair(7, "seven".into())), &bytes[..9]);
+
+        assert_eq!(
+            try_to_pbf_bytes(&5_u32),
+            Err(PbfError::Custom(
+                "unsupported serde type: top level value must be a struct".into()
+            ))
+        );
+        // a string claiming 5 bytes with only 1 left
+        assert_eq!(from_pbf_bytes::<Pair>(&[10, 5, 1]), Err(PbfError::UnexpectedEof));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde_bridge_truncated() {
+        use serde::Deserialize;
+
+        #[derive(Debug, Deserialize)]
+        #[allow(dead_code)]
+        struct P {
+            x: u32,
+            y: u32,
+        }
+        #[derive(Debug, Deserialize)]
+        #[allow(dead_code)]
+        struct Outer {
+            p: P,
+        }
+
+        // unknown fields cut short: a group, a bytes field and a fixed64
+        for bytes in [&[0x1b][..], &[0x1a, 0x7f], &[0x19, 1]] {
+            let res = from_pbf_bytes::<P>(bytes);
+            assert_eq!(res.unwrap_err(), PbfError::UnexpectedEof, "{:?}", bytes);
+        }
+        // an unknown fixed64 running past the end of its nested message
+        let mut bytes = vec![2, 2, 0x19, 1];
+        bytes.extend([0; 8]);
+        assert_eq!(from_pbf_bytes::<Outer>(&bytes).unwrap_err(), PbfError::UnexpectedEof);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde_bridge_sequences() {
+        use serde::{Deserialize, Serialize};
+
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        enum Color {
+            Red,
+            Green,
+            Blue,
+        }
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        enum Kind {
+            Named(String),
+        }
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        struct A {
+            colors: Vec<Color>,
+            n: u32,
+        }
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        struct B {
+            v: Vec<Vec<u32>>,
+        }
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        struct C {
+            kinds: Vec<Kind>,
+        }
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        struct D {
+            v: Option<Vec<u32>>,
+        }
+
+        // unit variants are packed like any other number
+        let a = A { colors: vec![Color::Green, Color::Blue], n: 3 };
+        let bytes = to_pbf_bytes(&a);
+        assert_eq!(bytes, vec![2, 2, 1, 2, 8, 3]);
+        assert_eq!(from_pbf_bytes::<A>(&bytes), Ok(a));
+        let a = A { colors: vec![Color::Red], n: 0 };
+        assert_eq!(from_pbf_bytes::<A>(&to_pbf_bytes(&a)), Ok(a));
+
+        let nested = PbfError::Custom("unsupported serde type: nested sequence".into());
+        assert_eq!(try_to_pbf_bytes(&B { v: vec![vec![1, 2], vec![3]] }).unwrap_err(), nested);
+        assert_eq!(from_pbf_bytes::<B>(&[2, 2, 1, 2, 2, 1, 3]).unwrap_err(), nested);
+        assert_eq!(
+            try_to_pbf_bytes(&C { kinds: vec![Kind::Named("a".into())] }),
+            Err(PbfError::Custom(
+                "unsupported serde type: newtype variant inside a sequence".into()
+            ))
+        );
+
+        // an empty sequence is not written, so it reads back as absent
+        assert_eq!(from_pbf_bytes::<D>(&to_pbf_bytes(&D { v: Some(vec![]) })), Ok(D { v: None }));
+    }
+
     #[test]
     fn test_message() {
         #[derive(Debug, PartialEq, Default)]
@@ -1096,6 +5073,121 @@ mod tests {
         assert_eq!(msg.b, "hello");
     }
 
+    #[test]
+    fn test_message_inplace() {
+        #[derive(Debug, PartialEq, Default)]
+        struct Level {
+            depth: u32,
+            data: Vec<u8>,
+            child: Option<alloc::boxed::Box<Level>>,
+        }
+        impl Level {
+            fn new(depth: u32, data_len: usize) -> Self {
+                let child = if depth < 5 {
+                    Some(alloc::boxed::Box::new(Level::new(depth + 1, data_len)))
+                } else {
+                    None
+                };
+                Level { depth, data: vec![depth as u8; data_len], child }
+            }
+        }
+        struct Inplace<'a>(&'a Level);
+        impl ProtoWrite for Level {
+            fn write(&self, pb: &mut Protobuf) {
+                pb.write_varint_field(1, self.depth);
+                pb.write_bytes_field(2, &self.data);
+                if let Some(child) = &self.child {
+                    pb.write_message(3, child.as_ref());
+                }
+            }
+        }
+        impl ProtoWrite for Inplace<'_> {
+            fn write(&self, pb: &mut Protobuf) {
+                pb.write_varint_field(1, self.0.depth);
+                pb.write_bytes_field(2, &self.0.data);
+                if let Some(child) = &self.0.child {
+                    pb.write_message_inplace(3, &Inplace(child));
+                }
+            }
+        }
+
+        // small bodies fit a 1 byte length, large ones need the length to grow
+        for data_len in [1, 100, 20_000] {
+            let level = Level::new(1, data_len);
+            let mut pb = Protobuf::new();
+            pb.write_message(1, &level);
+            let expected = pb.take();
+
+            let mut pb = Protobuf::new();
+            pb.write_message_inplace(1, &Inplace(&level));
+            assert_eq!(pb.take(), expected);
+        }
+    }
+
+    #[test]
+    fn test_encoded_len() {
+        #[derive(Default)]
+        struct Nested {
+            a: u64,
+            b: String,
+        }
+        impl ProtoWrite for Nested {
+            fn write(&self, pb: &mut Protobuf) {
+                pb.write_varint_field(1, self.a);
+                pb.write_string_field(2, &self.b);
+            }
+        }
+        #[derive(Default)]
+        struct TestMessage {
+            a: i32,
+            b: Vec<u32>,
+            c: Vec<i64>,
+            d: Vec<f32>,
+            e: Nested,
+            f: Vec<u8>,
+        }
+        impl ProtoWrite for TestMessage {
+            fn write(&self, pb: &mut Protobuf) {
+                pb.write_s_varint_field(1, self.a);
+                pb.write_fixed_field(2, 1.5_f64);
+                pb.write_packed_varint(3, &self.b);
+                pb.write_packed_s_varint(4, &self.c);
+                pb.write_packed_fixed(5, &self.d);
+                pb.write_packed_s_fixed(6, &self.c);
+                pb.write_message(7, &self.e);
+                pb.write_message_inplace(8, &self.e);
+                pb.write_bytes_field(9, &self.f);
+            }
+        }
+
+        let messages = [
+            TestMessage::default(),
+            TestMessage {
+                a: -300,
+                b: vec![1, 300, u32::MAX],
+                c: vec![-1, i64::MIN],
+                d: vec![1.5; 40],
+                e: Nested { a: u64::MAX, b: "hello".into() },
+                f: vec![],
+            },
+            TestMessage {
+                e: Nested { a: 1, b: "x".repeat(200) },
+                f: vec![7; 20_000],
+                ..Default::default()
+            },
+        ];
+        for msg in messages {
+            let mut pb = Protobuf::new();
+            pb.write_fields(&msg);
+            assert_eq!(Protobuf::encoded_len(&msg), pb.take().len());
+
+            let mut pb = Protobuf::new();
+            pb.write_message(1, &msg);
+            let len = Protobuf::encoded_len(&msg);
+            assert_eq!(1 + varint_len(len as u64) + len, pb.take().len());
+        }
+    }
+
     #[test]
     fn test_message_with_skip() {
         #[derive(Debug, PartialEq, Default)]
@@ -1152,6 +5244,53 @@ mod tests {
         assert_eq!(msg.b, "world");
     }
 
+    #[test]
+    fn test_read_fields_collecting() {
+        #[derive(Debug, PartialEq, Default)]
+        struct TestMessage {
+            b: String,
+        }
+        impl ProtoWrite for TestMessage {
+            fn write(&self, pb: &mut Protobuf) {
+                pb.write_string_field(2, &self.b);
+            }
+        }
+        impl ProtoRead for TestMessage {
+            fn read(&mut self, tag: u64, pb: &mut Protobuf) {
+                if tag == 2 {
+                    self.b = pb.read_string()
+                }
+            }
+        }
+
+        let mut pb = Protobuf::new();
+        pb.write_varint_field(1, 300);
+        pb.write_string_field(2, "hello");
+        pb.write_fixed_field(3, 5.5_f64);
+
+        let bytes = pb.take();
+        let mut pb = Protobuf::from_input(bytes);
+        let mut msg = TestMessage::default();
+        let unknown = pb.read_fields_collecting(&mut msg, None);
+        assert_eq!(msg.b, "hello");
+        assert!(!unknown.is_empty());
+        assert_eq!(unknown.as_bytes().len(), 3 + 9);
+
+        msg.b = "world".into();
+        let mut pb = Protobuf::new();
+        pb.write_fields(&msg);
+        unknown.write_to(&mut pb);
+
+        let bytes = pb.take();
+        let mut pb = Protobuf::from_input(bytes);
+        assert_eq!(pb.read_field(), Field { tag: 2, r#type: Type::Bytes });
+        assert_eq!(pb.read_string(), "world");
+        assert_eq!(pb.read_field(), Field { tag: 1, r#type: Type::Varint });
+        assert_eq!(pb.read_varint::<u32>(), 300);
+        assert_eq!(pb.read_field(), Field { tag: 3, r#type: Type::Fixed64 });
+        assert_eq!(pb.read_fixed::<f64>(), 5.5);
+    }
+
     #[test]
     fn unicode_string() {
         let mut pb = Protobuf::new();
@@ -1201,6 +5340,29 @@ mod tests {
         assert_eq!(pb.get_pos(), 5);
     }
 
+    #[test]
+    fn test_remaining() {
+        let mut pb = Protobuf::new();
+        pb.write_varint_field(1, 300);
+        pb.write_string_field(2, "hi");
+
+        let bytes = pb.take();
+        let mut pb = Protobuf::from_input(bytes);
+        assert_eq!(pb.remaining(), 7);
+        assert!(pb.has_remaining());
+
+        pb.read_field();
+        pb.read_varint::<u32>();
+        assert_eq!(pb.pos(), 3);
+        assert_eq!(pb.remaining(), 4);
+
+        pb.read_field();
+        pb.read_string();
+        assert_eq!(pb.pos(), 7);
+        assert_eq!(pb.remaining(), 0);
+        assert!(!pb.has_remaining());
+    }
+
     #[test]
     fn bug_test() {
         let mut pb = Protobuf::new();
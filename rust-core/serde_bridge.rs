@@ -0,0 +1,999 @@
+use crate::{Field, PbfError, Protobuf, Type, zagzig};
+use alloc::{
+    string::{String, ToString},
+    vec,
+    vec::Vec,
+};
+use core::fmt::Display;
+use serde::{
+    de::{
+        self, DeserializeOwned, DeserializeSeed, EnumAccess, IntoDeserializer, MapAccess,
+        SeqAccess, VariantAccess, Visitor, value::SeqDeserializer,
+    },
+    ser::{self, Impossible, Serialize},
+};
+
+impl ser::Error for PbfError {
+    fn custom<T: Display>(msg: T) -> Self {
+        PbfError::Custom(msg.to_string())
+    }
+}
+impl de::Error for PbfError {
+    fn custom<T: Display>(msg: T) -> Self {
+        PbfError::Custom(msg.to_string())
+    }
+}
+
+/// Serialize a value into protobuf bytes through serde's data model.
+///
+/// The value must be a struct (or tuple struct). Its fields are assigned sequential tags
+/// starting at 0, the same way the `ProtoWrite` derive assigns them (unless the struct is marked
+/// `#[pbf(strict_tags)]`):
+/// - `bool`, unsigned integers and `char` become varints
+/// - signed integers become zigzag encoded varints ("sint")
+/// - `f32` becomes a fixed32 and `f64` a fixed64
+/// - strings, bytes and nested structs become length-delimited fields
+/// - sequences of numbers are packed, sequences of anything else are repeated fields
+/// - `None` is not written at all, and neither is an empty sequence, so `Some(vec![])` reads
+///   back as `None`
+/// - unit enum variants are written as their variant index (packed inside a sequence), newtype
+///   variants as a nested message holding the value at the variant index
+///
+/// # Panics
+/// Panics if the value uses a part of serde's data model that has no protobuf mapping
+/// (maps, tuple and struct variants, nested sequences, newtype variants inside a sequence,
+/// 128-bit integers or a non struct top level value).
+///
+/// ```rust
+/// use pbf_core::{from_pbf_bytes, to_pbf_bytes};
+/// use serde::{Deserialize, Serialize};
+///
+/// #[derive(Debug, PartialEq, Serialize, Deserialize)]
+/// struct Point {
+///     x: i32,
+///     y: i32,
+/// }
+///
+/// let bytes = to_pbf_bytes(&Point { x: 1, y: -1 });
+/// assert_eq!(bytes, vec![0, 2, 8, 1]);
+/// assert_eq!(from_pbf_bytes::<Point>(&bytes), Ok(Point { x: 1, y: -1 }));
+/// ```
+pub fn to_pbf_bytes<T: Serialize + ?Sized>(value: &T) -> Vec<u8> {
+    try_to_pbf_bytes(value).unwrap_or_else(|err| panic!("{}", err))
+}
+
+/// Serialize a value into protobuf bytes through serde's data model.
+/// See `to_pbf_bytes` for how values are mapped onto the wire.
+/// Returns an error instead of panicking if the value has no protobuf mapping.
+pub fn try_to_pbf_bytes<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>, PbfError> {
+    let mut pbf = Protobuf::new();
+    value.serialize(Serializer { pbf: &mut pbf, tag: None, packed: None })?;
+
+    Ok(pbf.take())
+}
+
+/// Deserialize a value from protobuf bytes through serde's data model.
+///
+/// This is the inverse of `to_pbf_bytes`. Tags map back to fields by position, unknown tags
+/// are skipped and fields absent from the buffer take their protobuf default value
+/// (`0`, `false`, `""`, an empty sequence, `None`, or a struct of defaults).
+/// Because of this, `#[serde(default)]` values are not consulted.
+pub fn from_pbf_bytes<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, PbfError> {
+    let mut pbf = Protobuf::from_slice(bytes);
+    let end = pbf.len();
+
+    T::deserialize(MessageDeserializer { pbf: &mut pbf, end })
+}
+
+/// The error returned for the parts of serde's data model that have no protobuf mapping.
+fn unsupported(what: &str) -> PbfError {
+    PbfError::Custom(["unsupported serde type: ", what].concat())
+}
+
+/// Fail if the last value read ran past the end of the message holding it.
+fn check_end(pbf: &Protobuf, end: usize) -> Result<(), PbfError> {
+    if pbf.pos() > end {
+        return Err(PbfError::UnexpectedEof);
+    }
+
+    Ok(())
+}
+
+// === SERIALIZING =================================================================
+
+/// Writes a single value. Without a `tag` the value is the top level message,
+/// with a `packed` buffer the value is an element of a sequence.
+struct Serializer<'a> {
+    pbf: &'a mut Protobuf,
+    tag: Option<u64>,
+    packed: Option<&'a mut Protobuf>,
+}
+impl Serializer<'_> {
+    /// write a number either into the packed buffer or as a field of its own.
+    fn write_scalar(self, r#type: Type, write: impl FnOnce(&mut Protobuf)) -> Result<(), PbfError> {
+        match (self.packed, self.tag) {
+            (Some(packed), _) => write(packed),
+            (None, Some(tag)) => {
+                self.pbf.write_field(tag, r#type);
+                write(self.pbf);
+            }
+            (None, None) => return Err(unsupported("top level value must be a struct")),
+        }
+
+        Ok(())
+    }
+
+    /// The tag of a length-delimited value, which is never packed.
+    fn field_tag(&self) -> Result<u64, PbfError> {
+        self.tag.ok_or_else(|| unsupported("top level value must be a struct"))
+    }
+}
+
+impl<'a> ser::Serializer for Serializer<'a> {
+    type Ok = ();
+    type Error = PbfError;
+    type SerializeSeq = SeqSerializer<'a>;
+    type SerializeTuple = SeqSerializer<'a>;
+    type SerializeTupleStruct = StructSerializer<'a>;
+    type SerializeTupleVariant = Impossible<(), PbfError>;
+    type SerializeMap = Impossible<(), PbfError>;
+    type SerializeStruct = StructSerializer<'a>;
+    type SerializeStructVariant = Impossible<(), PbfError>;
+
+    fn serialize_bool(self, v: bool) -> Result<(), PbfError> {
+        self.write_scalar(Type::Varint, |pbf| pbf.write_varint(v))
+    }
+    fn serialize_i8(self, v: i8) -> Result<(), PbfError> {
+        self.serialize_i64(v.into())
+    }
+    fn serialize_i16(self, v: i16) -> Result<(), PbfError> {
+        self.serialize_i64(v.into())
+    }
+    fn serialize_i32(self, v: i32) -> Result<(), PbfError> {
+        self.serialize_i64(v.into())
+    }
+    fn serialize_i64(self, v: i64) -> Result<(), PbfError> {
+        self.write_scalar(Type::Varint, |pbf| pbf.write_s_varint(v))
+    }
+    fn serialize_u8(self, v: u8) -> Result<(), PbfError> {
+        self.serialize_u64(v.into())
+    }
+    fn serialize_u16(self, v: u16) -> Result<(), PbfError> {
+        self.serialize_u64(v.into())
+    }
+    fn serialize_u32(self, v: u32) -> Result<(), PbfError> {
+        self.serialize_u64(v.into())
+    }
+    fn serialize_u64(self, v: u64) -> Result<(), PbfError> {
+        self.write_scalar(Type::Varint, |pbf| pbf.write_varint(v))
+    }
+    fn serialize_f32(self, v: f32) -> Result<(), PbfError> {
+        self.write_scalar(Type::Fixed32, |pbf| pbf.write_fixed(v))
+    }
+    fn serialize_f64(self, v: f64) -> Result<(), PbfError> {
+        self.write_scalar(Type::Fixed64, |pbf| pbf.write_fixed(v))
+    }
+    fn serialize_char(self, v: char) -> Result<(), PbfError> {
+        self.write_scalar(Type::Varint, |pbf| pbf.write_varint(v))
+    }
+    fn serialize_str(self, v: &str) -> Result<(), PbfError> {
+        let tag = self.field_tag()?;
+        self.pbf.write_string_field(tag, v);
+        Ok(())
+    }
+    fn serialize_bytes(self, v: &[u8]) -> Result<(), PbfError> {
+        let tag = self.field_tag()?;
+        self.pbf.write_bytes_field(tag, v);
+        Ok(())
+    }
+    fn serialize_none(self) -> Result<(), PbfError> {
+        Ok(())
+    }
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<(), PbfError> {
+        value.serialize(self)
+    }
+    fn serialize_unit(self) -> Result<(), PbfError> {
+        Ok(())
+    }
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<(), PbfError> {
+        Ok(())
+    }
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        variant_index: u32,
+        _variant: &'static str,
+    ) -> Result<(), PbfError> {
+        self.serialize_u32(variant_index)
+    }
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<(), PbfError> {
+        value.serialize(self)
+    }
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        variant_index: u32,
+        _variant: &'static str,
+        value: &T,
+    ) -> Result<(), PbfError> {
+        // the elements of a sequence are told apart from packed unit variants by position alone
+        if self.packed.is_some() {
+            return Err(unsupported("newtype variant inside a sequence"));
+        }
+        let variant_tag = Some(variant_index.into());
+        match self.tag {
+            None => value.serialize(Serializer { pbf: self.pbf, tag: variant_tag, packed: None }),
+            Some(tag) => {
+                let mut body = self.pbf.scratch();
+                value.serialize(Serializer { pbf: &mut body, tag: variant_tag, packed: None })?;
+                self.pbf.write_scratch_field(tag, body);
+                Ok(())
+            }
+        }
+    }
+    fn serialize_seq(self, _len: Option<usize>) -> Result<SeqSerializer<'a>, PbfError> {
+        // the inner elements would share the outer tag and read back as a single sequence
+        if self.packed.is_some() {
+            return Err(unsupported("nested sequence"));
+        }
+        let tag = self.field_tag()?;
+        let packed = self.pbf.scratch();
+        Ok(SeqSerializer { pbf: self.pbf, tag, packed })
+    }
+    fn serialize_tuple(self, len: usize) -> Result<SeqSerializer<'a>, PbfError> {
+        self.serialize_seq(Some(len))
+    }
+    fn serialize_tuple_struct(
+        self,
+        name: &'static str,
+        len: usize,
+    ) -> Result<StructSerializer<'a>, PbfError> {
+        self.serialize_struct(name, len)
+    }
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleVariant, PbfError> {
+        Err(unsupported("tuple variant"))
+    }
+    fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap, PbfError> {
+        Err(unsupported("map"))
+    }
+    fn serialize_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<StructSerializer<'a>, PbfError> {
+        let body = self.tag.map(|tag| (tag, self.pbf.scratch()));
+        Ok(StructSerializer { pbf: self.pbf, body, index: 0 })
+    }
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStructVariant, PbfError> {
+        Err(unsupported("struct variant"))
+    }
+}
+
+/// Writes the fields of a struct, either inline (top level) or into a nested message body.
+struct StructSerializer<'a> {
+    pbf: &'a mut Protobuf,
+    body: Option<(u64, Protobuf)>,
+    index: u64,
+}
+impl StructSerializer<'_> {
+    fn field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), PbfError> {
+        let tag = self.index;
+        self.index += 1;
+        let pbf = match &mut self.body {
+            Some((_, body)) => body,
+            None => &mut *self.pbf,
+        };
+
+        value.serialize(Serializer { pbf, tag: Some(tag), packed: None })
+    }
+
+    fn finish(self) -> Result<(), PbfError> {
+        if let Some((tag, body)) = self.body {
+            self.pbf.write_scratch_field(tag, body);
+        }
+
+        Ok(())
+    }
+}
+impl ser::SerializeStruct for StructSerializer<'_> {
+    type Ok = ();
+    type Error = PbfError;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        _key: &'static str,
+        value: &T,
+    ) -> Result<(), PbfError> {
+        self.field(value)
+    }
+    fn skip_field(&mut self, _key: &'static str) -> Result<(), PbfError> {
+        // keep the tags of the following fields stable
+        self.index += 1;
+        Ok(())
+    }
+    fn end(self) -> Result<(), PbfError> {
+        self.finish()
+    }
+}
+impl ser::SerializeTupleStruct for StructSerializer<'_> {
+    type Ok = ();
+    type Error = PbfError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), PbfError> {
+        self.field(value)
+    }
+    fn end(self) -> Result<(), PbfError> {
+        self.finish()
+    }
+}
+
+/// Writes the elements of a sequence. Numbers are gathered into a single packed field,
+/// everything else is written as a repeated field.
+struct SeqSerializer<'a> {
+    pbf: &'a mut Protobuf,
+    tag: u64,
+    packed: Protobuf,
+}
+impl SeqSerializer<'_> {
+    fn element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), PbfError> {
+        value.serialize(Serializer {
+            pbf: self.pbf,
+            tag: Some(self.tag),
+            packed: Some(&mut self.packed),
+        })
+    }
+
+    fn finish(self) -> Result<(), PbfError> {
+        if self.packed.written() != 0 {
+            self.pbf.write_scratch_field(self.tag, self.packed);
+        }
+
+        Ok(())
+    }
+}
+impl ser::SerializeSeq for SeqSerializer<'_> {
+    type Ok = ();
+    type Error = PbfError;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), PbfError> {
+        self.element(value)
+    }
+    fn end(self) -> Result<(), PbfError> {
+        self.finish()
+    }
+}
+impl ser::SerializeTuple for SeqSerializer<'_> {
+    type Ok = ();
+    type Error = PbfError;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), PbfError> {
+        self.element(value)
+    }
+    fn end(self) -> Result<(), PbfError> {
+        self.finish()
+    }
+}
+
+// === DESERIALIZING ===============================================================
+
+/// Reads the top level message, which must be a struct (or tuple struct).
+struct MessageDeserializer<'a> {
+    pbf: &'a mut Protobuf,
+    end: usize,
+}
+impl<'de> de::Deserializer<'de> for MessageDeserializer<'_> {
+    type Error = PbfError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, PbfError> {
+        Err(unsupported("top level value must be a struct"))
+    }
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, PbfError> {
+        visitor.visit_newtype_struct(self)
+    }
+    fn deserialize_tuple_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        len: usize,
+        visitor: V,
+    ) -> Result<V::Value, PbfError> {
+        visitor.visit_seq(TupleAccess { pbf: self.pbf, end: self.end, index: 0, len })
+    }
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, PbfError> {
+        visitor.visit_map(FieldsAccess::new(self.pbf, self.end, fields.len()))
+    }
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, PbfError> {
+        let field = self.pbf.try_read_field()?;
+        visitor.visit_enum(VariantDeserializer { pbf: self.pbf, end: self.end, field })
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string bytes byte_buf
+        option unit unit_struct seq tuple map identifier ignored_any
+    }
+}
+
+/// Yields the fields of a message, followed by every field that never appeared on the wire.
+struct FieldsAccess<'a> {
+    pbf: &'a mut Protobuf,
+    end: usize,
+    seen: Vec<bool>,
+    missing: usize,
+    current: Option<Field>,
+}
+impl<'a> FieldsAccess<'a> {
+    fn new(pbf: &'a mut Protobuf, end: usize, len: usize) -> Self {
+        FieldsAccess { pbf, end, seen: vec![false; len], missing: 0, current: None }
+    }
+}
+impl<'de> MapAccess<'de> for FieldsAccess<'_> {
+    type Error = PbfError;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, PbfError> {
+        if self.pbf.pos() < self.end {
+            let field = self.pbf.try_read_field()?;
+            let tag = field.tag;
+            if let Some(seen) = self.seen.get_mut(tag as usize) {
+                *seen = true;
+            }
+            self.current = Some(field);
+            return seed.deserialize(tag.into_deserializer()).map(Some);
+        }
+        check_end(self.pbf, self.end)?;
+        // fields absent from the buffer take their default value
+        while let Some(&seen) = self.seen.get(self.missing) {
+            let index = self.missing as u64;
+            self.missing += 1;
+            if !seen {
+                self.current = None;
+                return seed.deserialize(index.into_deserializer()).map(Some);
+            }
+        }
+
+        Ok(None)
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value, PbfError> {
+        match self.current.take() {
+            Some(Field { tag, r#type }) => seed.deserialize(ValueDeserializer {
+                pbf: self.pbf,
+                end: self.end,
+                tag,
+                wire: r#type,
+                packed: None,
+            }),
+            None => seed.deserialize(DefaultDeserializer),
+        }
+    }
+}
+
+/// Yields the fields of a tuple struct in tag order. Tags are expected in increasing order,
+/// a field missing from the buffer takes its default value.
+struct TupleAccess<'a> {
+    pbf: &'a mut Protobuf,
+    end: usize,
+    index: usize,
+    len: usize,
+}
+impl<'de> SeqAccess<'de> for TupleAccess<'_> {
+    type Error = PbfError;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(
+        &mut self,
+        seed: T,
+    ) -> Result<Option<T::Value>, PbfError> {
+        if self.index >= self.len {
+            // skip any trailing unknown fields
+            while self.pbf.pos() < self.end {
+                let field = self.pbf.try_read_field()?;
+                self.pbf.try_skip(field.r#type)?;
+            }
+            check_end(self.pbf, self.end)?;
+            return Ok(None);
+        }
+        let index = self.index as u64;
+        self.index += 1;
+        while self.pbf.pos() < self.end {
+            let pos = self.pbf.pos();
+            let field = self.pbf.try_read_field()?;
+            if field.tag < index {
+                self.pbf.try_skip(field.r#type)?;
+                continue;
+            }
+            if field.tag > index {
+                self.pbf.set_pos(pos);
+                break;
+            }
+            return seed
+                .deserialize(ValueDeserializer {
+                    pbf: self.pbf,
+                    end: self.end,
+                    tag: field.tag,
+                    wire: field.r#type,
+                    packed: None,
+                })
+                .map(Some);
+        }
+        check_end(self.pbf, self.end)?;
+
+        seed.deserialize(DefaultDeserializer).map(Some)
+    }
+}
+
+/// Reads a single value whose field key has already been consumed.
+/// `packed` is set for the elements of a sequence, so a length-delimited field holding
+/// numbers can be read as packed.
+struct ValueDeserializer<'a> {
+    pbf: &'a mut Protobuf,
+    end: usize,
+    tag: u64,
+    wire: Type,
+    packed: Option<&'a mut Option<usize>>,
+}
+impl ValueDeserializer<'_> {
+    /// Prepare to read a number, entering the packed body if the field is length-delimited.
+    fn start_scalar(&mut self) -> Result<(), PbfError> {
+        if self.wire != Type::Bytes {
+            return Ok(());
+        }
+        let Some(packed) = self.packed.take() else {
+            return Err(PbfError::Custom(
+                "expected a number, found a length-delimited field".into(),
+            ));
+        };
+        let len = self.read_len()?;
+        if len == 0 {
+            return Err(PbfError::Custom("empty packed field".into()));
+        }
+        *packed = Some(self.pbf.pos() + len);
+
+        Ok(())
+    }
+
+    fn varint(&mut self) -> Result<u64, PbfError> {
+        self.start_scalar()?;
+        self.pbf.try_decode_varint()
+    }
+
+    /// Read a length prefix that is guaranteed to fit in the remaining buffer.
+    fn read_len(&mut self) -> Result<usize, PbfError> {
+        let len = self.pbf.try_decode_varint()? as usize;
+        if len > self.pbf.remaining() {
+            return Err(PbfError::UnexpectedEof);
+        }
+
+        Ok(len)
+    }
+
+    /// Read the length prefix of a nested message, returning where its body ends.
+    fn message_end(&mut self) -> Result<usize, PbfError> {
+        if self.wire != Type::Bytes {
+            return Err(PbfError::Custom("expected a length-delimited field".into()));
+        }
+
+        Ok(self.read_len()? + self.pbf.pos())
+    }
+
+    fn bytes(&mut self) -> Result<&[u8], PbfError> {
+        if self.wire != Type::Bytes {
+            return Err(PbfError::Custom("expected a length-delimited field".into()));
+        }
+        let len = self.read_len()?;
+        let start = self.pbf.pos();
+        self.pbf.set_pos(start + len);
+
+        Ok(&self.pbf.buf[start..start + len])
+    }
+}
+impl<'de> de::Deserializer<'de> for ValueDeserializer<'_> {
+    type Error = PbfError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, PbfError> {
+        Err(unsupported("deserialize_any (protobuf is not self-describing)"))
+    }
+    fn deserialize_bool<V: Visitor<'de>>(mut self, visitor: V) -> Result<V::Value, PbfError> {
+        visitor.visit_bool(self.varint()? != 0)
+    }
+    fn deserialize_i8<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, PbfError> {
+        self.deserialize_i64(visitor)
+    }
+    fn deserialize_i16<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, PbfError> {
+        self.deserialize_i64(visitor)
+    }
+    fn deserialize_i32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, PbfError> {
+        self.deserialize_i64(visitor)
+    }
+    fn deserialize_i64<V: Visitor<'de>>(mut self, visitor: V) -> Result<V::Value, PbfError> {
+        visitor.visit_i64(zagzig(self.varint()?))
+    }
+    fn deserialize_u8<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, PbfError> {
+        self.deserialize_u64(visitor)
+    }
+    fn deserialize_u16<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, PbfError> {
+        self.deserialize_u64(visitor)
+    }
+    fn deserialize_u32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, PbfError> {
+        self.deserialize_u64(visitor)
+    }
+    fn deserialize_u64<V: Visitor<'de>>(mut self, visitor: V) -> Result<V::Value, PbfError> {
+        visitor.visit_u64(self.varint()?)
+    }
+    fn deserialize_f32<V: Visitor<'de>>(mut self, visitor: V) -> Result<V::Value, PbfError> {
+        self.start_scalar()?;
+        visitor.visit_f32(self.pbf.try_read_fixed()?)
+    }
+    fn deserialize_f64<V: Visitor<'de>>(mut self, visitor: V) -> Result<V::Value, PbfError> {
+        self.start_scalar()?;
+        visitor.visit_f64(self.pbf.try_read_fixed()?)
+    }
+    fn deserialize_char<V: Visitor<'de>>(mut self, visitor: V) -> Result<V::Value, PbfError> {
+        let val = self.varint()?;
+        let c = u32::try_from(val).ok().and_then(char::from_u32);
+        visitor.visit_char(c.ok_or_else(|| PbfError::Custom("invalid char value".into()))?)
+    }
+    fn deserialize_str<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, PbfError> {
+        self.deserialize_string(visitor)
+    }
+    fn deserialize_string<V: Visitor<'de>>(mut self, visitor: V) -> Result<V::Value, PbfError> {
+        let s = String::from_utf8(self.bytes()?.to_vec()).map_err(|_| PbfError::InvalidUtf8)?;
+        visitor.visit_string(s)
+    }
+    fn deserialize_bytes<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, PbfError> {
+        self.deserialize_byte_buf(visitor)
+    }
+    fn deserialize_byte_buf<V: Visitor<'de>>(mut self, visitor: V) -> Result<V::Value, PbfError> {
+        visitor.visit_byte_buf(self.bytes()?.to_vec())
+    }
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, PbfError> {
+        // a field is only written when the value is present
+        visitor.visit_some(self)
+    }
+    fn deserialize_unit<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, PbfError> {
+        self.pbf.try_skip(self.wire)?;
+        visitor.visit_unit()
+    }
+    fn deserialize_unit_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, PbfError> {
+        self.deserialize_unit(visitor)
+    }
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, PbfError> {
+        visitor.visit_newtype_struct(self)
+    }
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, PbfError> {
+        if self.packed.is_some() {
+            return Err(unsupported("nested sequence"));
+        }
+        visitor.visit_seq(RepeatedAccess {
+            pbf: self.pbf,
+            end: self.end,
+            tag: self.tag,
+            first: Some(self.wire),
+            packed_end: None,
+        })
+    }
+    fn deserialize_tuple<V: Visitor<'de>>(
+        self,
+        _len: usize,
+        visitor: V,
+    ) -> Result<V::Value, PbfError> {
+        self.deserialize_seq(visitor)
+    }
+    fn deserialize_tuple_struct<V: Visitor<'de>>(
+        mut self,
+        _name: &'static str,
+        len: usize,
+        visitor: V,
+    ) -> Result<V::Value, PbfError> {
+        let end = self.message_end()?;
+        visitor.visit_seq(TupleAccess { pbf: self.pbf, end, index: 0, len })
+    }
+    fn deserialize_map<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, PbfError> {
+        Err(unsupported("map"))
+    }
+    fn deserialize_struct<V: Visitor<'de>>(
+        mut self,
+        _name: &'static str,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, PbfError> {
+        let end = self.message_end()?;
+        visitor.visit_map(FieldsAccess::new(self.pbf, end, fields.len()))
+    }
+    fn deserialize_enum<V: Visitor<'de>>(
+        mut self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, PbfError> {
+        // inside a sequence a length-delimited field is the packed body of unit variants
+        if self.wire != Type::Bytes || self.packed.is_some() {
+            let index = self.varint()?;
+            let index = u32::try_from(index)
+                .map_err(|_| PbfError::Custom("invalid enum variant index".into()))?;
+            return visitor.visit_enum(index.into_deserializer());
+        }
+        let end = self.read_len()? + self.pbf.pos();
+        let field = self.pbf.try_read_field()?;
+        visitor.visit_enum(VariantDeserializer { pbf: self.pbf, end, field })
+    }
+    fn deserialize_identifier<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, PbfError> {
+        self.deserialize_any(visitor)
+    }
+    fn deserialize_ignored_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, PbfError> {
+        self.pbf.try_skip(self.wire)?;
+        visitor.visit_unit()
+    }
+}
+
+/// Yields the elements of a packed or repeated field.
+struct RepeatedAccess<'a> {
+    pbf: &'a mut Protobuf,
+    end: usize,
+    tag: u64,
+    first: Option<Type>,
+    packed_end: Option<usize>,
+}
+impl<'de> SeqAccess<'de> for RepeatedAccess<'_> {
+    type Error = PbfError;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(
+        &mut self,
+        seed: T,
+    ) -> Result<Option<T::Value>, PbfError> {
+        if let Some(end) = self.packed_end {
+            if self.pbf.pos() < end {
+                return seed
+                    .deserialize(ValueDeserializer {
+                        pbf: self.pbf,
+                        end: self.end,
+                        tag: self.tag,
+                        // packed elements carry no key, any non length-delimited type reads the raw value
+                        wire: Type::Varint,
+                        packed: None,
+                    })
+                    .map(Some);
+            }
+            check_end(self.pbf, end)?;
+            self.packed_end = None;
+        }
+        let wire = match self.first.take() {
+            Some(wire) => wire,
+            None => {
+                // repeated values continue for as long as the same tag follows
+                if self.pbf.pos() >= self.end {
+                    check_end(self.pbf, self.end)?;
+                    return Ok(None);
+                }
+                let pos = self.pbf.pos();
+                let field = self.pbf.try_read_field()?;
+                if field.tag != self.tag {
+                    self.pbf.set_pos(pos);
+                    return Ok(None);
+                }
+                field.r#type
+            }
+        };
+
+        seed.deserialize(ValueDeserializer {
+            pbf: self.pbf,
+            end: self.end,
+            tag: self.tag,
+            wire,
+            packed: Some(&mut self.packed_end),
+        })
+        .map(Some)
+    }
+}
+
+/// Reads a newtype enum variant: a message holding a single field tagged with the variant index.
+struct VariantDeserializer<'a> {
+    pbf: &'a mut Protobuf,
+    end: usize,
+    field: Field,
+}
+impl<'de, 'a> EnumAccess<'de> for VariantDeserializer<'a> {
+    type Error = PbfError;
+    type Variant = Self;
+
+    fn variant_seed<V: DeserializeSeed<'de>>(self, seed: V) -> Result<(V::Value, Self), PbfError> {
+        let variant = seed.deserialize(self.field.tag.into_deserializer())?;
+        Ok((variant, self))
+    }
+}
+impl<'de> VariantAccess<'de> for VariantDeserializer<'_> {
+    type Error = PbfError;
+
+    fn unit_variant(self) -> Result<(), PbfError> {
+        self.pbf.try_skip(self.field.r#type)?;
+        self.pbf.set_pos(self.end);
+        Ok(())
+    }
+    fn newtype_variant_seed<T: DeserializeSeed<'de>>(self, seed: T) -> Result<T::Value, PbfError> {
+        let value = seed.deserialize(ValueDeserializer {
+            pbf: self.pbf,
+            end: self.end,
+            tag: self.field.tag,
+            wire: self.field.r#type,
+            packed: None,
+        })?;
+        // ignore anything following the variant's value
+        self.pbf.set_pos(self.end);
+        Ok(value)
+    }
+    fn tuple_variant<V: Visitor<'de>>(
+        self,
+        _len: usize,
+        _visitor: V,
+    ) -> Result<V::Value, PbfError> {
+        Err(unsupported("tuple variant"))
+    }
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        _fields: &'static [&'static str],
+        _visitor: V,
+    ) -> Result<V::Value, PbfError> {
+        Err(unsupported("struct variant"))
+    }
+}
+
+/// Produces the protobuf default value of a field that is absent from the buffer.
+struct DefaultDeserializer;
+impl<'de> de::Deserializer<'de> for DefaultDeserializer {
+    type Error = PbfError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, PbfError> {
+        Err(unsupported("deserialize_any (protobuf is not self-describing)"))
+    }
+    fn deserialize_bool<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, PbfError> {
+        visitor.visit_bool(false)
+    }
+    fn deserialize_i8<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, PbfError> {
+        visitor.visit_i64(0)
+    }
+    fn deserialize_i16<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, PbfError> {
+        visitor.visit_i64(0)
+    }
+    fn deserialize_i32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, PbfError> {
+        visitor.visit_i64(0)
+    }
+    fn deserialize_i64<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, PbfError> {
+        visitor.visit_i64(0)
+    }
+    fn deserialize_u8<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, PbfError> {
+        visitor.visit_u64(0)
+    }
+    fn deserialize_u16<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, PbfError> {
+        visitor.visit_u64(0)
+    }
+    fn deserialize_u32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, PbfError> {
+        visitor.visit_u64(0)
+    }
+    fn deserialize_u64<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, PbfError> {
+        visitor.visit_u64(0)
+    }
+    fn deserialize_f32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, PbfError> {
+        visitor.visit_f32(0.0)
+    }
+    fn deserialize_f64<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, PbfError> {
+        visitor.visit_f64(0.0)
+    }
+    fn deserialize_char<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, PbfError> {
+        visitor.visit_char('\0')
+    }
+    fn deserialize_str<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, PbfError> {
+        visitor.visit_str("")
+    }
+    fn deserialize_string<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, PbfError> {
+        visitor.visit_string(String::new())
+    }
+    fn deserialize_bytes<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, PbfError> {
+        visitor.visit_bytes(&[])
+    }
+    fn deserialize_byte_buf<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, PbfError> {
+        visitor.visit_byte_buf(Vec::new())
+    }
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, PbfError> {
+        visitor.visit_none()
+    }
+    fn deserialize_unit<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, PbfError> {
+        visitor.visit_unit()
+    }
+    fn deserialize_unit_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, PbfError> {
+        visitor.visit_unit()
+    }
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, PbfError> {
+        visitor.visit_newtype_struct(self)
+    }
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, PbfError> {
+        visitor.visit_seq(SeqDeserializer::new(core::iter::empty::<u8>()))
+    }
+    fn deserialize_tuple<V: Visitor<'de>>(
+        self,
+        _len: usize,
+        visitor: V,
+    ) -> Result<V::Value, PbfError> {
+        self.deserialize_seq(visitor)
+    }
+    fn deserialize_tuple_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        len: usize,
+        visitor: V,
+    ) -> Result<V::Value, PbfError> {
+        let mut pbf = Protobuf::new();
+        visitor.visit_seq(TupleAccess { pbf: &mut pbf, end: 0, index: 0, len })
+    }
+    fn deserialize_map<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, PbfError> {
+        Err(unsupported("map"))
+    }
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, PbfError> {
+        let mut pbf = Protobuf::new();
+        visitor.visit_map(FieldsAccess::new(&mut pbf, 0, fields.len()))
+    }
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, PbfError> {
+        visitor.visit_enum(0_u32.into_deserializer())
+    }
+    fn deserialize_identifier<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, PbfError> {
+        self.deserialize_any(visitor)
+    }
+    fn deserialize_ignored_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, PbfError> {
+        visitor.visit_unit()
+    }
+}
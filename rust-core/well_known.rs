@@ -0,0 +1,73 @@
+use crate::{ProtoRead, ProtoWrite, Protobuf};
+
+/// The `Timestamp` struct mirrors `google.protobuf.Timestamp`: a point in time as seconds
+/// since the Unix epoch (field 1) and a non-negative count of nanoseconds (field 2).
+/// Zero fields are left out of the buffer, as the standard protobuf encoders do.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Timestamp {
+    /// Seconds since 1970-01-01T00:00:00Z, negative for earlier times
+    pub seconds: i64,
+    /// Nanoseconds of the second, from 0 to 999,999,999
+    pub nanos: i32,
+}
+impl Timestamp {
+    /// create a timestamp from seconds since the Unix epoch and nanoseconds
+    pub fn new(seconds: i64, nanos: i32) -> Self {
+        Timestamp { seconds, nanos }
+    }
+}
+impl ProtoWrite for Timestamp {
+    fn write(&self, pbf: &mut Protobuf) {
+        write_seconds_nanos(pbf, self.seconds, self.nanos);
+    }
+}
+impl ProtoRead for Timestamp {
+    fn read(&mut self, tag: u64, pb: &mut Protobuf) {
+        read_seconds_nanos(pb, tag, &mut self.seconds, &mut self.nanos);
+    }
+}
+
+/// The `Duration` struct mirrors `google.protobuf.Duration`: a signed span of time as
+/// seconds (field 1) and nanoseconds (field 2), both carrying the same sign.
+/// Zero fields are left out of the buffer, as the standard protobuf encoders do.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Duration {
+    /// Whole seconds of the span
+    pub seconds: i64,
+    /// Nanoseconds of the span, from -999,999,999 to 999,999,999
+    pub nanos: i32,
+}
+impl Duration {
+    /// create a duration from seconds and nanoseconds
+    pub fn new(seconds: i64, nanos: i32) -> Self {
+        Duration { seconds, nanos }
+    }
+}
+impl ProtoWrite for Duration {
+    fn write(&self, pbf: &mut Protobuf) {
+        write_seconds_nanos(pbf, self.seconds, self.nanos);
+    }
+}
+impl ProtoRead for Duration {
+    fn read(&mut self, tag: u64, pb: &mut Protobuf) {
+        read_seconds_nanos(pb, tag, &mut self.seconds, &mut self.nanos);
+    }
+}
+
+/// Both types are plain `int64 seconds = 1; int32 nanos = 2;` (not zigzag encoded).
+fn write_seconds_nanos(pbf: &mut Protobuf, seconds: i64, nanos: i32) {
+    if seconds != 0 {
+        pbf.write_varint_field(1, seconds);
+    }
+    if nanos != 0 {
+        pbf.write_varint_field(2, nanos);
+    }
+}
+
+fn read_seconds_nanos(pb: &mut Protobuf, tag: u64, seconds: &mut i64, nanos: &mut i32) {
+    match tag {
+        1 => *seconds = pb.read_varint(),
+        2 => *nanos = pb.read_varint(),
+        _ => {}
+    }
+}
@@ -22,6 +22,7 @@ pub fn expand_bitcast(input: TokenStream) -> TokenStream {
 
     // Extract variant names and discriminants
     let mut from_u64_arms = Vec::new();
+    let mut try_from_u64_arms = Vec::new();
     let mut to_u64_arms = Vec::new();
 
     for variant in &enum_data.variants {
@@ -38,6 +39,7 @@ pub fn expand_bitcast(input: TokenStream) -> TokenStream {
         };
 
         from_u64_arms.push(quote! { #expr => Self::#variant_name });
+        try_from_u64_arms.push(quote! { #expr => Some(Self::#variant_name) });
         to_u64_arms.push(quote! { Self::#variant_name => #expr });
     }
 
@@ -73,6 +75,16 @@ pub fn expand_bitcast(input: TokenStream) -> TokenStream {
                     }
                 }
             }
+
+            #[automatically_derived]
+            impl TryBitCast for #name {
+                fn try_from_u64(val: u64) -> Option<Self> {
+                    match val {
+                        #(#try_from_u64_arms,)*
+                        _ => None,
+                    }
+                }
+            }
         };
     };
 
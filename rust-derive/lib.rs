@@ -1,10 +1,14 @@
 #![forbid(unsafe_code)]
 
-use darling::{self, FromField, FromVariant};
+use darling::{self, FromDeriveInput, FromField, FromVariant};
 use proc_macro::TokenStream;
 use proc_macro_crate::{FoundCrate, crate_name};
-use proc_macro2::Span;
-use syn::{Data, DeriveInput, Ident, parse_macro_input};
+use proc_macro2::{Span, TokenTree};
+use quote::quote;
+use syn::{
+    Data, DeriveInput, GenericArgument, Generics, Ident, PathArguments, Type, TypePath,
+    parse_macro_input, parse_quote,
+};
 
 mod bitcast;
 mod read;
@@ -14,7 +18,7 @@ use bitcast::expand_bitcast;
 use read::{derive_proto_read_enum, derive_proto_read_struct};
 use write::{derive_proto_write_enum, derive_proto_write_struct};
 
-#[derive(Debug, FromField, FromVariant)]
+#[derive(Debug, Clone, Default, FromField, FromVariant)]
 #[darling(attributes(pbf))]
 struct FieldAttributes {
     tag: Option<u64>,
@@ -26,6 +30,226 @@ struct FieldAttributes {
     nested: bool,
     #[darling(default)]
     ignore: bool,
+    #[darling(default)]
+    oneof: bool,
+    default: Option<String>,
+    packed: Option<bool>,
+    #[darling(default)]
+    skip_if_default: bool,
+    #[darling(default)]
+    skip_if_empty: bool,
+    #[darling(default)]
+    group: bool,
+}
+
+/// Attributes on the struct or enum itself.
+#[derive(Debug, Clone, Default, FromDeriveInput)]
+#[darling(attributes(pbf))]
+struct ContainerAttributes {
+    #[darling(default)]
+    strict_tags: bool,
+}
+
+/// Check if a type is an `Option<T>`.
+fn is_option_type(field_type: &Type) -> bool {
+    matches!(
+        field_type,
+        Type::Path(TypePath { path, .. }) if path.segments.last().unwrap().ident == "Option"
+    )
+}
+
+/// Get the key and value types of a `BTreeMap<K, V>` or `HashMap<K, V>`.
+fn map_types(field_type: &Type) -> Option<(&Type, &Type)> {
+    let Type::Path(TypePath { path, .. }) = field_type else {
+        return None;
+    };
+    let segment = path.segments.last()?;
+    if segment.ident != "BTreeMap" && segment.ident != "HashMap" {
+        return None;
+    }
+    let PathArguments::AngleBracketed(args) = &segment.arguments else {
+        return None;
+    };
+    let mut types = args.args.iter().filter_map(|arg| match arg {
+        GenericArgument::Type(t) => Some(t),
+        _ => None,
+    });
+
+    Some((types.next()?, types.next()?))
+}
+
+/// The first tag given to a field of a named struct without an explicit `#[pbf(tag = N)]`.
+/// Protobuf field numbers start at 1, but the derives have always started at 0 so a struct opts
+/// into the valid numbering with `#[pbf(strict_tags)]`.
+fn first_named_tag(strict_tags: bool) -> u64 {
+    if strict_tags { 1 } else { 0 }
+}
+
+/// Record the tag a struct field resolves to. If the tag is not a valid protobuf field number
+/// (0 included with `strict_tags`), or an earlier field already claimed it, return an error
+/// spanning the offending field, for use as a `compile_error!`.
+fn claim_tag(
+    claimed: &mut Vec<(u64, String)>,
+    tag: u64,
+    field: &syn::Field,
+    name: String,
+    strict_tags: bool,
+) -> Result<(), syn::Error> {
+    let invalid = if strict_tags && tag == 0 {
+        Some("0 is not a valid protobuf field number, tags start at 1".to_string())
+    } else if (19000..=19999).contains(&tag) {
+        Some("field numbers 19000 to 19999 are reserved by protobuf".to_string())
+    } else if tag > 536_870_911 {
+        Some("the largest protobuf field number is 536870911".to_string())
+    } else {
+        None
+    };
+    if let Some(reason) = invalid {
+        return Err(syn::Error::new_spanned(
+            field,
+            format!("pbf tag {} of `{}` is invalid: {}", tag, name, reason),
+        ));
+    }
+    if let Some((_, other)) = claimed.iter().find(|(t, _)| *t == tag) {
+        return Err(syn::Error::new_spanned(
+            field,
+            format!(
+                "pbf tag {} is used by both `{}` and `{}`; give one of them an explicit #[pbf(tag = N)]",
+                tag, other, name
+            ),
+        ));
+    }
+    claimed.push((tag, name));
+
+    Ok(())
+}
+
+/// The error for a struct with more than one `pbf(oneof)` field, spanning the extra field, for
+/// use as a `compile_error!`.
+fn second_oneof_error(field: &syn::Field) -> syn::Error {
+    syn::Error::new_spanned(field, "only one #[pbf(oneof)] field is supported per struct")
+}
+
+/// Copy the input's generics, bounding every type parameter used by a `nested`, `group` or
+/// `oneof` field with `bound`, since those fields go through the parameter's own message impl.
+/// Other uses of a type parameter (such as a `BitCast` enum) keep the bounds the user wrote.
+fn message_bounds(input: &DeriveInput, bound: proc_macro2::TokenStream) -> Generics {
+    let mut message_types = Vec::new();
+    match &input.data {
+        Data::Struct(data_struct) => {
+            for field in &data_struct.fields {
+                let attr = FieldAttributes::from_field(field).unwrap();
+                if attr.nested || attr.group || attr.oneof {
+                    message_types.push(&field.ty);
+                }
+            }
+        }
+        Data::Enum(data_enum) => {
+            for variant in &data_enum.variants {
+                let attr = FieldAttributes::from_variant(variant).unwrap();
+                if attr.nested || attr.group {
+                    message_types.extend(variant.fields.iter().map(|field| &field.ty));
+                }
+            }
+        }
+        Data::Union(_) => {}
+    }
+
+    let mut generics = input.generics.clone();
+    let params: Vec<Ident> = generics.type_params().map(|param| param.ident.clone()).collect();
+    for param in params {
+        if message_types.iter().any(|ty| mentions_ident(quote! { #ty }, &param)) {
+            generics.make_where_clause().predicates.push(parse_quote! { #param: #bound });
+        }
+    }
+
+    generics
+}
+
+/// Check if `ident` appears anywhere in `tokens`.
+fn mentions_ident(tokens: proc_macro2::TokenStream, ident: &Ident) -> bool {
+    tokens.into_iter().any(|token| match token {
+        TokenTree::Ident(other) => other == *ident,
+        TokenTree::Group(group) => mentions_ident(group.stream(), ident),
+        _ => false,
+    })
+}
+
+/// Get the enum type of a `pbf(oneof)` field, looking through an `Option`.
+fn oneof_type(field_type: &Type) -> &Type {
+    if let Type::Path(TypePath { path, .. }) = field_type
+        && let Some(segment) = path.segments.last()
+        && segment.ident == "Option"
+        && let PathArguments::AngleBracketed(args) = &segment.arguments
+        && let Some(GenericArgument::Type(inner_type)) = args.args.first()
+    {
+        return inner_type;
+    }
+
+    field_type
+}
+
+/// The check that a `pbf(oneof)` field's tags do not collide with the struct's `fields`. A
+/// non-generic struct gets an item level `const`, always evaluated. A generic one gets an inline
+/// `const` block for its method body, evaluated once the impl is instantiated.
+fn oneof_tags_check(
+    oneof: &Type,
+    fields: &[(u64, String)],
+    generics: &Generics,
+    r#trait: proc_macro2::TokenStream,
+) -> (proc_macro2::TokenStream, proc_macro2::TokenStream) {
+    let tags = fields.iter().map(|(tag, _)| tag);
+    let check = quote! { assert_oneof_tags(<#oneof as #r#trait>::TAGS, &[#(#tags),*]) };
+    if generics.params.is_empty() {
+        (quote! { const _: () = #check; }, quote! {})
+    } else {
+        (quote! {}, quote! { const { #check }; })
+    }
+}
+
+/// Check if a type is a zero-sized marker (`()`, `PhantomData<T>` or `PhantomPinned`), which
+/// carries nothing to encode and is skipped as if it were `#[pbf(ignore)]`.
+fn is_marker_type(field_type: &Type) -> bool {
+    match field_type {
+        Type::Tuple(tuple) => tuple.elems.is_empty(),
+        Type::Path(TypePath { path, .. }) => path.segments.last().is_some_and(|segment| {
+            segment.ident == "PhantomData" || segment.ident == "PhantomPinned"
+        }),
+        _ => false,
+    }
+}
+
+/// Check if a type is a `Cow<str>`.
+fn is_cow_str(field_type: &Type) -> bool {
+    let Type::Path(TypePath { path, .. }) = field_type else {
+        return false;
+    };
+    let segment = path.segments.last().unwrap();
+    let PathArguments::AngleBracketed(args) = &segment.arguments else {
+        return false;
+    };
+    segment.ident == "Cow"
+        && args.args.iter().any(|arg| {
+            matches!(arg, GenericArgument::Type(Type::Path(TypePath { path, .. })) if path.is_ident("str"))
+        })
+}
+
+/// Get the pointee type of a `Box<T>`, `Rc<T>` or `Arc<T>`.
+fn pointer_inner_type(field_type: &Type) -> Option<&Type> {
+    let Type::Path(TypePath { path, .. }) = field_type else {
+        return None;
+    };
+    let segment = path.segments.last()?;
+    if segment.ident != "Box" && segment.ident != "Rc" && segment.ident != "Arc" {
+        return None;
+    }
+    let PathArguments::AngleBracketed(args) = &segment.arguments else {
+        return None;
+    };
+    match args.args.first()? {
+        GenericArgument::Type(t) => Some(t),
+        _ => None,
+    }
 }
 
 /// Derive the `BitCast` trait for an enum.
@@ -46,9 +270,22 @@ pub fn derive_proto_write(input: TokenStream) -> TokenStream {
     };
     let pbf_core = Ident::new(&crate_name, Span::call_site());
 
+    let generics = message_bounds(&input, quote! { ProtoWrite });
+    let container = match ContainerAttributes::from_derive_input(&input) {
+        Ok(container) => container,
+        Err(err) => return err.write_errors().into(),
+    };
+
     match &input.data {
-        Data::Struct(data_struct) => derive_proto_write_struct(data_struct, name, &pbf_core),
-        Data::Enum(data_enum) => derive_proto_write_enum(data_enum, name, &pbf_core),
+        Data::Struct(data_struct) => {
+            derive_proto_write_struct(data_struct, name, &generics, &pbf_core, &container)
+        }
+        Data::Enum(_) if container.strict_tags => {
+            syn::Error::new_spanned(name, "pbf(strict_tags) is only supported on structs")
+                .to_compile_error()
+                .into()
+        }
+        Data::Enum(data_enum) => derive_proto_write_enum(data_enum, name, &generics, &pbf_core),
         _ => panic!("ProtoWrite can only be derived for structs and enums"),
     }
 }
@@ -65,9 +302,22 @@ pub fn derive_proto_read(input: TokenStream) -> TokenStream {
     };
     let pbf_core = Ident::new(&crate_name, Span::call_site());
 
+    let generics = message_bounds(&input, quote! { ProtoRead + Default });
+    let container = match ContainerAttributes::from_derive_input(&input) {
+        Ok(container) => container,
+        Err(err) => return err.write_errors().into(),
+    };
+
     match &input.data {
-        Data::Struct(data_struct) => derive_proto_read_struct(data_struct, name, &pbf_core),
-        Data::Enum(data_enum) => derive_proto_read_enum(data_enum, name, &pbf_core),
+        Data::Struct(data_struct) => {
+            derive_proto_read_struct(data_struct, name, &generics, &pbf_core, &container)
+        }
+        Data::Enum(_) if container.strict_tags => {
+            syn::Error::new_spanned(name, "pbf(strict_tags) is only supported on structs")
+                .to_compile_error()
+                .into()
+        }
+        Data::Enum(data_enum) => derive_proto_read_enum(data_enum, name, &generics, &pbf_core),
         _ => panic!("ProtoRead can only be derived for structs and enums"),
     }
 }
@@ -1,49 +1,108 @@
-use crate::FieldAttributes;
+use crate::{
+    ContainerAttributes, FieldAttributes, claim_tag, first_named_tag, is_cow_str, is_marker_type,
+    is_option_type, map_types, oneof_tags_check, oneof_type, pointer_inner_type,
+    second_oneof_error,
+};
 use darling::{FromField, FromVariant};
 use proc_macro::TokenStream;
 use quote::quote;
-use syn::{DataEnum, DataStruct, Fields, GenericArgument, Ident, PathArguments, Type, TypePath};
+use syn::{
+    DataEnum, DataStruct, Fields, GenericArgument, Generics, Ident, Index, Member, PathArguments,
+    Type, TypeArray, TypePath,
+};
 
 pub fn derive_proto_read_struct(
     data_struct: &DataStruct,
     name: &Ident,
+    generics: &Generics,
     pbf_core: &Ident,
+    container: &ContainerAttributes,
 ) -> TokenStream {
     let mut write_statements = Vec::new();
-    let mut field_index: u64 = 0; // Default tag assignment
+    let mut oneof_field = None;
+    let mut init_statements = Vec::new();
+    // Default tag assignment (positional fields of tuple structs are 1-based)
+    let mut field_index: u64 = match &data_struct.fields {
+        Fields::Named(_) => first_named_tag(container.strict_tags),
+        Fields::Unnamed(_) => 1,
+        Fields::Unit => panic!("ProtoRead can only be derived for structs with fields"),
+    };
+    let mut claimed_tags = Vec::new();
 
-    if let Fields::Named(fields) = &data_struct.fields {
-        for field in &fields.named {
-            let field_name = field.ident.as_ref().unwrap();
-            let field_type = &field.ty;
-            let attr = FieldAttributes::from_field(field).unwrap();
-            // skip user defined "ignore"s
-            if attr.ignore {
-                continue;
+    for (idx, field) in data_struct.fields.iter().enumerate() {
+        let field_name = match &field.ident {
+            Some(ident) => Member::Named(ident.clone()),
+            None => Member::Unnamed(Index::from(idx)),
+        };
+        let field_type = &field.ty;
+        let attr = FieldAttributes::from_field(field).unwrap();
+        // skip user defined "ignore"s and zero-sized markers
+        if attr.ignore || is_marker_type(field_type) {
+            continue;
+        }
+        // user defined fallback for fields missing from the buffer
+        if let Some(default) = &attr.default {
+            let default: syn::Expr = syn::parse_str(default).unwrap_or_else(|err| {
+                panic!("Invalid pbf(default) expression {:?}: {}", default, err)
+            });
+            init_statements.push(quote! { self.#field_name = #default; });
+        }
+        // a oneof receives the tags of its variants, checked against the other fields below
+        if attr.oneof {
+            if oneof_field.is_some() {
+                return second_oneof_error(field).to_compile_error().into();
             }
+            oneof_field = Some((field_name, field_type));
+            continue;
+        }
 
-            let write_method =
-                field_type_to_read_method(field_type, field_name, field_index, &attr, false)
-                    .unwrap_or_else(|| {
-                        panic!(
-                            "Unsupported type in ProtoRead derive: {:#?}",
-                            quote! { #field_type }
-                        )
-                    });
+        let tag = attr.tag.unwrap_or(field_index);
+        if let Err(err) = claim_tag(
+            &mut claimed_tags,
+            tag,
+            field,
+            quote! { #field_name }.to_string(),
+            container.strict_tags,
+        ) {
+            return err.to_compile_error().into();
+        }
+        let target = quote! { self.#field_name };
+        let write_method =
+            field_type_to_read_method(field_type, &target, field_index, &attr, false)
+                .unwrap_or_else(|| {
+                    panic!("Unsupported type in ProtoRead derive: {:#?}", quote! { #field_type })
+                });
 
-            write_statements.push(write_method);
-            // increment field_index only if the user did not define an index for the field
-            if let Some(index) = attr.tag {
-                field_index = index + 1;
-            } else {
-                field_index += 1;
-            }
+        write_statements.push(write_method);
+        // increment field_index only if the user did not define an index for the field
+        if let Some(index) = attr.tag {
+            field_index = index + 1;
+        } else {
+            field_index += 1;
         }
-    } else {
-        panic!("ProtoRead can only be derived for structs with named fields");
+    }
+    let mut fallback_statement = quote! { _ => panic!("unknown tag {}", tag), };
+    let (mut oneof_item_check, mut oneof_body_check) = (quote! {}, quote! {});
+    if let Some((field_name, field_type)) = oneof_field {
+        let oneof = oneof_type(field_type);
+        (oneof_item_check, oneof_body_check) =
+            oneof_tags_check(oneof, &claimed_tags, generics, quote! { ProtoRead });
+        let target = if is_option_type(field_type) {
+            quote! { self.#field_name.get_or_insert_with(Default::default) }
+        } else {
+            quote! { &mut self.#field_name }
+        };
+        fallback_statement = quote! {
+            tag if {
+                let tags = <#oneof as ProtoRead>::TAGS;
+                tags.is_empty() || tags.contains(&tag)
+            } => ProtoRead::read(#target, tag, pb),
+            #fallback_statement
+        };
     }
 
     // Generate the trait implementation
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
     let expanded = quote! {
         #[doc(hidden)]
         #[allow(
@@ -60,14 +119,21 @@ pub fn derive_proto_read_struct(
 
             use _pbf_core::*;
 
+            #oneof_item_check
+
             #[automatically_derived]
-            impl ProtoRead for #name {
+            impl #impl_generics ProtoRead for #name #ty_generics #where_clause {
                 fn read(&mut self, tag: u64, pb: &mut Protobuf) {
+                    #oneof_body_check
                     match tag {
                         #(#write_statements)*
-                        _ => panic!("unknown tag {}", tag),
+                        #fallback_statement
                     }
                 }
+
+                fn init(&mut self) {
+                    #(#init_statements)*
+                }
             }
         };
     };
@@ -78,7 +144,7 @@ pub fn derive_proto_read_struct(
 /// Maps Rust types to the corresponding Protobuf read method.
 fn field_type_to_read_method(
     field_type: &syn::Type,
-    field_name: &Ident,
+    target: &proc_macro2::TokenStream,
     field_index: u64,
     attr: &FieldAttributes,
     is_option: bool,
@@ -111,79 +177,186 @@ fn field_type_to_read_method(
                 || path.is_ident("isize")
                 || path.is_ident("bool") =>
         {
-            let read_method = if attr.signed {
-                wrap_option(quote! { pb.read_s_varint() })
-            } else if attr.fixed {
+            // fixed wins over signed: the two's complement bits already keep the sign (sfixed)
+            let read_method = if attr.fixed {
                 wrap_option(quote! { pb.read_fixed() })
+            } else if attr.signed {
+                wrap_option(quote! { pb.read_s_varint() })
             } else {
                 wrap_option(quote! { pb.read_varint() })
             };
-            Some(quote! { #field_index => self.#field_name = #read_method, })
+            Some(quote! { #field_index => #target = #read_method, })
         }
 
         // Handling String fields
         Type::Path(TypePath { path, .. }) if path.is_ident("String") => {
             let read_string = wrap_option(quote! { pb.read_string() });
-            Some(quote! { #field_index => self.#field_name = #read_string, })
+            Some(quote! { #field_index => #target = #read_string, })
+        }
+
+        // Handling Cow<str> fields, always read as owned
+        Type::Path(TypePath { .. }) if is_cow_str(field_type) => {
+            let read_string = wrap_option(quote! { alloc::borrow::Cow::Owned(pb.read_string()) });
+            Some(quote! { #field_index => #target = #read_string, })
         }
 
         // Handling Vec<T>
         Type::Path(TypePath { path, .. }) if path.segments.last().unwrap().ident == "Vec" => {
             if let PathArguments::AngleBracketed(ref args) = path.segments.last().unwrap().arguments
+                && let Some(GenericArgument::Type(inner_type)) = args.args.first()
             {
-                if let Some(GenericArgument::Type(inner_type)) = args.args.first() {
-                    if let Type::Path(TypePath { path, .. }) = inner_type {
-                        if path.is_ident("u8") {
-                            let read_method = wrap_option(quote! { pb.read_bytes() });
-                            return Some(
-                                quote! { #field_index => self.#field_name = #read_method, },
-                            );
-                        }
-                    }
-                    let read_packed = if attr.signed {
-                        wrap_option(quote! { pb.read_s_packed() })
+                if let Type::Path(TypePath { path, .. }) = inner_type
+                    && path.is_ident("u8")
+                {
+                    let read_method = wrap_option(quote! { pb.read_bytes() });
+                    return Some(quote! { #field_index => #target = #read_method, });
+                }
+                // Unpacked (messages can never be packed), every occurrence of the tag holds one element
+                if attr.packed == Some(false) || attr.nested || attr.group {
+                    let inner_read = field_type_to_read_method(
+                        inner_type,
+                        &quote! { value },
+                        field_index,
+                        attr,
+                        false,
+                    )?;
+                    let vec = if is_option {
+                        quote! { #target.get_or_insert_with(Default::default) }
                     } else {
-                        wrap_option(quote! { pb.read_packed() })
+                        quote! { #target }
                     };
-                    return Some(quote! { #field_index => self.#field_name = #read_packed, });
+                    return Some(quote! {
+                        #field_index => {
+                            let mut value: #inner_type = Default::default();
+                            match tag {
+                                #inner_read
+                                _ => {}
+                            }
+                            #vec.push(value);
+                        }
+                    });
                 }
+                // Packed, append so split or merged occurrences accumulate
+                // fixed wins over signed here too, elements keep their two's complement bits
+                let vec = if is_option {
+                    quote! { #target.get_or_insert_with(Default::default) }
+                } else {
+                    quote! { (&mut #target) }
+                };
+                let read_packed = if attr.fixed {
+                    quote! { #vec.extend(pb.read_packed_fixed::<#inner_type>()) }
+                } else if attr.signed {
+                    quote! { #vec.extend(pb.read_s_packed::<#inner_type>()) }
+                } else {
+                    quote! { pb.read_packed_append::<#inner_type>(#vec) }
+                };
+                return Some(quote! { #field_index => #read_packed, });
             }
             None
         }
 
+        // Handling [T; N], the element count on the wire must be exactly N
+        Type::Array(array) => {
+            let read_method = wrap_option(array_read_expr(array, attr));
+            Some(quote! { #field_index => #target = #read_method, })
+        }
+
+        // Handling maps (BTreeMap<K, V> or HashMap<K, V>) as repeated key/value entries
+        Type::Path(TypePath { .. }) if map_types(field_type).is_some() => {
+            let (key_type, value_type) = map_types(field_type)?;
+            let key_attr = FieldAttributes { tag: Some(1), ..Default::default() };
+            let value_attr = FieldAttributes { tag: Some(2), ..attr.clone() };
+            let key_read =
+                field_type_to_read_method(key_type, &quote! { key }, 1, &key_attr, false)?;
+            let value_read =
+                field_type_to_read_method(value_type, &quote! { value }, 2, &value_attr, false)?;
+            let map = if is_option {
+                quote! { #target.get_or_insert_with(Default::default) }
+            } else {
+                quote! { #target }
+            };
+            Some(quote! {
+                #field_index => {
+                    let mut key: #key_type = Default::default();
+                    let mut value: #value_type = Default::default();
+                    let end = pb.decode_varint() as usize + pb.get_pos();
+                    while pb.get_pos() < end {
+                        let field = pb.read_field();
+                        // the entry tag shadows the map's tag, Box values match against it
+                        let tag = field.tag;
+                        match tag {
+                            #key_read
+                            #value_read
+                            _ => pb.skip(field.r#type),
+                        }
+                    }
+                    #map.insert(key, value);
+                }
+            })
+        }
+
+        // Handling Box<T>, Rc<T> and Arc<T> by reading the pointee and wrapping it
+        Type::Path(TypePath { .. }) if pointer_inner_type(field_type).is_some() => {
+            let inner_type = pointer_inner_type(field_type)?;
+            // a distinct name, `value` is the target of Vec and map elements
+            let inner_read = field_type_to_read_method(
+                inner_type,
+                &quote! { inner_value },
+                field_index,
+                attr,
+                false,
+            )?;
+            let read_method = wrap_option(quote! { <#field_type>::new(inner_value) });
+            Some(quote! {
+                #field_index => {
+                    let mut inner_value: #inner_type = Default::default();
+                    match tag {
+                        #inner_read
+                        _ => {}
+                    }
+                    #target = #read_method;
+                }
+            })
+        }
+
         // Handling Option<T>
         Type::Path(TypePath { path, .. }) if path.segments.last().unwrap().ident == "Option" => {
             if let PathArguments::AngleBracketed(ref args) = path.segments.last().unwrap().arguments
+                && let Some(GenericArgument::Type(inner_type)) = args.args.first()
             {
-                if let Some(GenericArgument::Type(inner_type)) = args.args.first() {
-                    return field_type_to_read_method(
-                        inner_type,
-                        field_name,
-                        field_index,
-                        attr,
-                        true,
-                    );
-                }
+                return field_type_to_read_method(inner_type, target, field_index, attr, true);
             }
             None
         }
 
-        // Handling nested messages
-        Type::Path(TypePath { .. }) if attr.nested => {
+        // Handling nested messages (length-delimited or as a legacy group)
+        Type::Path(TypePath { .. }) if attr.nested || attr.group => {
             let read_method = wrap_option(quote! { nested_value });
+            let read_nested = if attr.group {
+                quote! { pb.read_group(&mut nested_value); }
+            } else {
+                quote! { pb.read_message(&mut nested_value); }
+            };
             Some(quote! {
                 #field_index => {
                     let mut nested_value = #field_type::default();
-                    pb.read_message(&mut nested_value);
-                    self.#field_name = #read_method;
+                    #read_nested
+                    #target = #read_method;
                 }
             })
         }
 
+        // Handling Enums stored as a fixed32
+        Type::Path(TypePath { .. }) if attr.fixed => {
+            let read_enum =
+                wrap_option(quote! { BitCast::from_u64(pb.read_fixed::<u32>() as u64) });
+            Some(quote! { #field_index => #target = #read_enum, })
+        }
+
         // Handling Enums (assuming they are stored as integers)
         Type::Path(TypePath { .. }) => {
             let read_enum = wrap_option(quote! { pb.read_varint() });
-            Some(quote! { #field_index => self.#field_name = #read_enum, })
+            Some(quote! { #field_index => #target = #read_enum, })
         }
 
         // Other unsupported types
@@ -191,17 +364,29 @@ fn field_type_to_read_method(
     }
 }
 
-pub fn derive_proto_read_enum(data_enum: &DataEnum, name: &Ident, pbf_core: &Ident) -> TokenStream {
+pub fn derive_proto_read_enum(
+    data_enum: &DataEnum,
+    name: &Ident,
+    generics: &Generics,
+    pbf_core: &Ident,
+) -> TokenStream {
     let mut write_statements = Vec::new();
+    let mut tags = Vec::new();
     let mut field_index: u64 = 0; // Default tag assignment
 
     for variant in &data_enum.variants {
         let variant_name = &variant.ident;
         let attr = FieldAttributes::from_variant(variant).unwrap();
         field_index = attr.tag.unwrap_or(field_index);
+        if !attr.ignore {
+            tags.push(field_index);
+        }
         if variant.fields.is_empty() {
             write_statements.push(quote! {
-                #field_index => #name::#variant_name,
+                #field_index => {
+                    pb.read_none();
+                    #name::#variant_name
+                }
             });
         } else {
             for field in variant.fields.iter() {
@@ -235,6 +420,7 @@ pub fn derive_proto_read_enum(data_enum: &DataEnum, name: &Ident, pbf_core: &Ide
     }
 
     // Generate the trait implementation
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
     let expanded = quote! {
         #[doc(hidden)]
         #[allow(
@@ -252,13 +438,15 @@ pub fn derive_proto_read_enum(data_enum: &DataEnum, name: &Ident, pbf_core: &Ide
             use _pbf_core::*;
 
             #[automatically_derived]
-            impl ProtoRead for #name {
+            impl #impl_generics ProtoRead for #name #ty_generics #where_clause {
                 fn read(&mut self, tag: u64, pb: &mut Protobuf) {
                     *self = match tag {
                         #(#write_statements)*
                         _ => panic!("unknown tag {}", tag),
                     }
                 }
+
+                const TAGS: &'static [u64] = &[#(#tags),*];
             }
         };
     };
@@ -299,10 +487,11 @@ fn field_type_to_read_enum(
                 || path.is_ident("isize")
                 || path.is_ident("bool") =>
         {
-            let read_method = if attr.signed {
-                wrap_option(quote! { pb.read_s_varint() })
-            } else if attr.fixed {
+            // fixed wins over signed: the two's complement bits already keep the sign (sfixed)
+            let read_method = if attr.fixed {
                 wrap_option(quote! { pb.read_fixed() })
+            } else if attr.signed {
+                wrap_option(quote! { pb.read_s_varint() })
             } else {
                 wrap_option(quote! { pb.read_varint() })
             };
@@ -319,32 +508,38 @@ fn field_type_to_read_enum(
         // Handling Vec<T>
         Type::Path(TypePath { path, .. }) if path.segments.last().unwrap().ident == "Vec" => {
             if let PathArguments::AngleBracketed(ref args) = path.segments.last().unwrap().arguments
+                && let Some(GenericArgument::Type(inner_type)) = args.args.first()
             {
-                if let Some(GenericArgument::Type(inner_type)) = args.args.first() {
-                    if let Type::Path(TypePath { path, .. }) = inner_type {
-                        if path.is_ident("u8") {
-                            let read_method = wrap_option(quote! { pb.read_bytes() });
-                            return Some(quote! { #name::#variant_name(#read_method) });
-                        }
-                    }
-                    let read_packed = if attr.signed {
-                        wrap_option(quote! { pb.read_s_packed() })
-                    } else {
-                        wrap_option(quote! { pb.read_packed() })
-                    };
-                    return Some(quote! { #name::#variant_name(#read_packed) });
+                if let Type::Path(TypePath { path, .. }) = inner_type
+                    && path.is_ident("u8")
+                {
+                    let read_method = wrap_option(quote! { pb.read_bytes() });
+                    return Some(quote! { #name::#variant_name(#read_method) });
                 }
+                let read_packed = if attr.fixed {
+                    wrap_option(quote! { pb.read_packed_fixed() })
+                } else if attr.signed {
+                    wrap_option(quote! { pb.read_s_packed() })
+                } else {
+                    wrap_option(quote! { pb.read_packed() })
+                };
+                return Some(quote! { #name::#variant_name(#read_packed) });
             }
             None
         }
 
+        // Handling [T; N], the element count on the wire must be exactly N
+        Type::Array(array) => {
+            let read_method = wrap_option(array_read_expr(array, attr));
+            Some(quote! { #name::#variant_name(#read_method) })
+        }
+
         // Handling Option<T>
         Type::Path(TypePath { path, .. }) if path.segments.last().unwrap().ident == "Option" => {
             if let PathArguments::AngleBracketed(ref args) = path.segments.last().unwrap().arguments
+                && let Some(GenericArgument::Type(inner_type)) = args.args.first()
             {
-                if let Some(GenericArgument::Type(inner_type)) = args.args.first() {
-                    return field_type_to_read_enum(inner_type, name, variant_name, attr, true);
-                }
+                return field_type_to_read_enum(inner_type, name, variant_name, attr, true);
             }
             None
         }
@@ -359,6 +554,13 @@ fn field_type_to_read_enum(
             }})
         }
 
+        // Handling Enums stored as a fixed32
+        Type::Path(TypePath { .. }) if attr.fixed => {
+            let read_method =
+                wrap_option(quote! { BitCast::from_u64(pb.read_fixed::<u32>() as u64) });
+            Some(quote! { #name::#variant_name(#read_method) })
+        }
+
         // Handling Enums (assuming they are stored as integers)
         Type::Path(TypePath { .. }) => {
             let read_method = wrap_option(quote! { pb.read_varint() });
@@ -369,3 +571,29 @@ fn field_type_to_read_enum(
         _ => None,
     }
 }
+
+/// Build an expression reading a `[T; N]`: a bytes field for `[u8; N]`, otherwise a packed
+/// field whose element count must equal `N`.
+fn array_read_expr(array: &TypeArray, attr: &FieldAttributes) -> proc_macro2::TokenStream {
+    let TypeArray { elem, len, .. } = array;
+    if let Type::Path(TypePath { path, .. }) = &**elem
+        && path.is_ident("u8")
+    {
+        return quote! { pb.read_array() };
+    }
+    let read_packed = if attr.fixed {
+        quote! { pb.read_packed_fixed::<#elem>() }
+    } else if attr.signed {
+        quote! { pb.read_s_packed::<#elem>() }
+    } else {
+        quote! { pb.read_packed::<#elem>() }
+    };
+
+    quote! {{
+        let values = #read_packed;
+        let found = values.len();
+        values.try_into().unwrap_or_else(|_| {
+            panic!("Expected {} packed elements for a [_; {}], found {}", #len, #len, found)
+        })
+    }}
+}
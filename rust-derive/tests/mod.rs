@@ -38,6 +38,52 @@ mod tests {
         assert_eq!(c, c_back);
     }
 
+    #[test]
+    fn test_try_bit_cast_macro() {
+        use pbf_core::{PbfError, TryBitCast};
+
+        #[derive(Debug, PartialEq, BitCast)]
+        enum TestEnum {
+            A = 3,
+            B = 1,
+        }
+
+        assert_eq!(TestEnum::try_from_u64(3), Some(TestEnum::A));
+        assert_eq!(TestEnum::try_from_u64(1), Some(TestEnum::B));
+        assert_eq!(TestEnum::try_from_u64(2), None);
+
+        let mut pb = Protobuf::from_input(alloc::vec![1, 9]);
+        assert_eq!(pb.try_read_varint::<TestEnum>(), Ok(TestEnum::B));
+        assert_eq!(pb.try_read_varint::<TestEnum>(), Err(PbfError::InvalidValue(9)));
+    }
+
+    #[test]
+    fn test_enum_field() {
+        use pbf_core::{Field, Type};
+
+        #[derive(Debug, PartialEq, BitCast)]
+        enum Shape {
+            Unknown = 0,
+            Circle = 1,
+            Square = 2,
+        }
+
+        let mut pb = Protobuf::new();
+        pb.write_enum_field(1, &Shape::Square);
+        pb.write_varint_field(2, 7_u32); // a discriminant added by a newer schema
+        let bytes = pb.take();
+        assert_eq!(bytes, alloc::vec![8, 2, 16, 7]);
+
+        let mut pb = Protobuf::from_input(bytes);
+        assert_eq!(pb.read_field(), Field { tag: 1, r#type: Type::Varint });
+        assert_eq!(pb.read_enum::<Shape>(), Shape::Square);
+        assert_eq!(pb.read_field(), Field { tag: 2, r#type: Type::Varint });
+        assert_eq!(pb.read_enum_or(Shape::Unknown), Shape::Unknown);
+
+        let mut pb = Protobuf::from_input(alloc::vec![1]);
+        assert_eq!(pb.read_enum_or(Shape::Unknown), Shape::Circle);
+    }
+
     #[test]
     fn test_proto_write_struct_macro() {
         #[derive(Debug, Default, PartialEq, Copy, Clone, BitCast)]
@@ -153,4 +199,1052 @@ mod tests {
         pb.read_fields(&mut b, None);
         assert_eq!(a, b);
     }
+
+    #[test]
+    fn test_proto_oneof_macro() {
+        #[derive(Debug, Clone, Default, PartialEq, ProtoRead, ProtoWrite)]
+        struct NestedStruct {
+            a: usize,
+            b: String,
+        }
+
+        #[derive(Debug, Clone, Default, PartialEq, ProtoRead, ProtoWrite)]
+        pub enum Value {
+            /// String value
+            #[pbf(tag = 10)]
+            String(String),
+            /// Signed integer 64-bit value
+            #[pbf(signed)]
+            SInt(i64),
+            /// 64-bit Floating point value
+            #[pbf(fixed)]
+            Double(f64),
+            /// Nested struct
+            #[pbf(nested)]
+            Nested(NestedStruct),
+            /// Null value
+            #[default]
+            Null,
+        }
+
+        #[derive(Debug, Default, PartialEq, ProtoRead, ProtoWrite)]
+        struct TestStruct {
+            #[pbf(tag = 1)]
+            name: String,
+            #[pbf(oneof)]
+            value: Value,
+            id: u32,
+        }
+
+        #[derive(Debug, Default, PartialEq, ProtoRead, ProtoWrite)]
+        struct OptionStruct {
+            #[pbf(oneof)]
+            value: Option<Value>,
+        }
+
+        let nested = NestedStruct { a: 1, b: "nested".into() };
+        for value in [
+            Value::String("test".into()),
+            Value::SInt(-5),
+            Value::Double(1.5),
+            Value::Nested(nested),
+            Value::Null,
+        ] {
+            let a = TestStruct { name: "name".into(), value: value.clone(), id: 7 };
+            let mut pb = Protobuf::new();
+            pb.write_fields(&a);
+
+            let bytes = pb.take();
+            let mut pb = Protobuf::from_input(bytes);
+            let mut b = TestStruct::default();
+            pb.read_fields(&mut b, None);
+            assert_eq!(a, b);
+
+            let a = OptionStruct { value: Some(value) };
+            let mut pb = Protobuf::new();
+            pb.write_fields(&a);
+
+            let bytes = pb.take();
+            let mut pb = Protobuf::from_input(bytes);
+            let mut b = OptionStruct::default();
+            pb.read_fields(&mut b, None);
+            assert_eq!(a, b);
+        }
+
+        let mut pb = Protobuf::new();
+        pb.write_fields(&OptionStruct::default());
+        assert!(pb.take().is_empty());
+
+        // only the variant tags are routed to the oneof
+        use pbf_core::{ProtoRead, ProtoWrite};
+        assert_eq!(<Value as ProtoRead>::TAGS, &[10, 11, 12, 13, 14]);
+        assert_eq!(<Value as ProtoWrite>::TAGS, &[10, 11, 12, 13, 14]);
+    }
+
+    #[test]
+    fn test_proto_map_macro() {
+        use alloc::collections::BTreeMap;
+
+        #[derive(Debug, Clone, Default, PartialEq, ProtoRead, ProtoWrite)]
+        struct NestedStruct {
+            a: usize,
+            b: String,
+        }
+
+        #[derive(Debug, Default, PartialEq, ProtoRead, ProtoWrite)]
+        struct TestStruct {
+            #[pbf(tag = 1)]
+            a: BTreeMap<u32, String>,
+            #[pbf(signed)]
+            b: BTreeMap<String, i64>,
+            #[pbf(nested)]
+            c: BTreeMap<u64, NestedStruct>,
+            d: Option<BTreeMap<bool, f32>>,
+        }
+
+        let mut a = TestStruct::default();
+        a.a.insert(1, "one".into());
+        a.a.insert(2, "two".into());
+        a.a.insert(300, "three hundred".into());
+        a.b.insert("neg".into(), -5);
+        a.c.insert(7, NestedStruct { a: 1, b: "nested".into() });
+        a.d = Some(BTreeMap::from([(true, 1.5)]));
+
+        let mut pb = Protobuf::new();
+        pb.write_fields(&a);
+
+        let bytes = pb.take();
+        // first entry: key 1, len 7, [key tag 1 = 1, value tag 2 = "one"]
+        assert_eq!(&bytes[..9], &[10, 7, 8, 1, 18, 3, 111, 110, 101]);
+        assert_eq!(Protobuf::encoded_len(&a), bytes.len());
+
+        let mut pb = Protobuf::from_input(bytes);
+        let mut b = TestStruct::default();
+        pb.read_fields(&mut b, None);
+        assert_eq!(a, b);
+    }
+
+    #[test]
+    fn test_proto_tuple_struct_macro() {
+        #[derive(Debug, Default, PartialEq, ProtoRead, ProtoWrite)]
+        struct Pair(#[pbf(signed)] i32, String);
+
+        let a = Pair(-3, "pair".into());
+        let mut pb = Protobuf::new();
+        pb.write_fields(&a);
+
+        let bytes = pb.take();
+        // tag 1: zigzag(-3) = 5, tag 2: "pair"
+        assert_eq!(bytes, vec![8, 5, 18, 4, 112, 97, 105, 114]);
+
+        let mut pb = Protobuf::from_input(bytes);
+        let mut b = Pair::default();
+        pb.read_fields(&mut b, None);
+        assert_eq!(a, b);
+    }
+
+    #[test]
+    fn test_proto_default_macro() {
+        #[derive(Debug, Default, PartialEq, ProtoRead, ProtoWrite)]
+        struct Versioned {
+            #[pbf(tag = 1, default = "7")]
+            version: u32,
+            name: String,
+            #[pbf(default = "Some(-1)", signed)]
+            offset: Option<i64>,
+        }
+
+        #[derive(Debug, Default, PartialEq, ProtoWrite)]
+        struct Missing {
+            #[pbf(tag = 2)]
+            name: String,
+        }
+
+        let mut pb = Protobuf::new();
+        pb.write_fields(&Missing { name: "v".into() });
+
+        let b: Versioned = pbf_core::decode(&pb.take());
+        assert_eq!(b, Versioned { version: 7, name: "v".into(), offset: Some(-1) });
+
+        // present tags still override the default
+        let a = Versioned { version: 2, name: "w".into(), offset: Some(3) };
+        let mut pb = Protobuf::new();
+        pb.write_fields(&a);
+        let mut pb = Protobuf::from_input(pb.take());
+        let mut b = Versioned::default();
+        pb.read_fields(&mut b, None);
+        assert_eq!(a, b);
+    }
+
+    #[test]
+    fn test_proto_option_presence_macro() {
+        #[derive(Debug, Default, PartialEq, ProtoRead, ProtoWrite)]
+        struct Presence {
+            #[pbf(tag = 1)]
+            a: Option<u32>,
+            b: Option<u32>,
+            c: Option<String>,
+        }
+
+        let a = Presence { a: Some(0), b: None, c: Some(String::new()) };
+        let mut pb = Protobuf::new();
+        pb.write_fields(&a);
+
+        let bytes = pb.take();
+        // tag 1 with value 0 and an empty string at tag 3 are written, tag 2 is absent
+        assert_eq!(bytes, vec![8, 0, 26, 0]);
+
+        let mut pb = Protobuf::from_input(bytes);
+        let mut b = Presence::default();
+        pb.read_fields(&mut b, None);
+        assert_eq!(b.a, Some(0));
+        assert_eq!(b.b, None);
+        assert_eq!(b.c, Some(String::new()));
+    }
+
+    #[test]
+    fn test_proto_fixed_enum_macro() {
+        #[derive(Debug, Default, Copy, Clone, PartialEq, BitCast)]
+        enum Kind {
+            #[default]
+            A = 1,
+            B = 2,
+        }
+
+        #[derive(Debug, Default, PartialEq, ProtoRead, ProtoWrite)]
+        struct TestStruct {
+            #[pbf(tag = 1, fixed)]
+            a: Kind,
+            #[pbf(fixed)]
+            b: Option<Kind>,
+            c: Kind,
+        }
+
+        let a = TestStruct { a: Kind::B, b: Some(Kind::A), c: Kind::B };
+        let mut pb = Protobuf::new();
+        pb.write_fields(&a);
+
+        let bytes = pb.take();
+        // tag 1 fixed32 = 2, tag 2 fixed32 = 1, tag 3 varint = 2
+        assert_eq!(bytes, vec![13, 2, 0, 0, 0, 21, 1, 0, 0, 0, 24, 2]);
+
+        let mut pb = Protobuf::from_input(bytes);
+        let mut b = TestStruct::default();
+        pb.read_fields(&mut b, None);
+        assert_eq!(a, b);
+    }
+
+    #[test]
+    fn test_proto_unit_variant_macro() {
+        use pbf_core::{Field, ProtoRead, Type};
+
+        #[derive(Debug, Default, PartialEq, ProtoRead, ProtoWrite)]
+        enum Value {
+            Int(u64),
+            #[default]
+            Null,
+        }
+
+        let mut pb = Protobuf::new();
+        pb.write_fields(&Value::Null);
+        pb.write_fields(&Value::Int(5));
+
+        let bytes = pb.take();
+        assert_eq!(bytes, vec![15, 0, 5]);
+
+        let mut pb = Protobuf::from_input(bytes);
+        let mut value = Value::Int(0);
+        let field = pb.read_field();
+        assert_eq!(field, Field { tag: 1, r#type: Type::None });
+        value.read(field.tag, &mut pb);
+        assert_eq!(value, Value::Null);
+        // only the field key was consumed
+        assert_eq!(pb.get_pos(), 1);
+
+        let field = pb.read_field();
+        value.read(field.tag, &mut pb);
+        assert_eq!(value, Value::Int(5));
+    }
+
+    #[test]
+    fn test_proto_pointer_macro() {
+        use alloc::{boxed::Box, rc::Rc, sync::Arc};
+
+        #[derive(Debug, Default, PartialEq, ProtoRead, ProtoWrite)]
+        struct Node {
+            #[pbf(tag = 1, signed)]
+            value: i32,
+            #[pbf(nested)]
+            child: Option<Box<Node>>,
+        }
+
+        #[derive(Debug, Default, PartialEq, ProtoRead, ProtoWrite)]
+        struct Shared {
+            a: Box<u32>,
+            b: Rc<String>,
+            #[pbf(nested)]
+            c: Arc<Node>,
+        }
+
+        let a = Node {
+            value: 1,
+            child: Some(Box::new(Node {
+                value: -2,
+                child: Some(Box::new(Node { value: 3, child: None })),
+            })),
+        };
+        let mut pb = Protobuf::new();
+        pb.write_fields(&a);
+
+        let bytes = pb.take();
+        assert_eq!(bytes, vec![8, 2, 18, 6, 8, 3, 18, 2, 8, 6]);
+
+        let mut pb = Protobuf::from_input(bytes);
+        let mut b = Node::default();
+        pb.read_fields(&mut b, None);
+        assert_eq!(a, b);
+
+        let a = Shared { a: Box::new(300), b: Rc::new("rc".into()), c: Arc::new(a) };
+        let mut pb = Protobuf::new();
+        pb.write_fields(&a);
+        let mut pb = Protobuf::from_input(pb.take());
+        let mut b = Shared::default();
+        pb.read_fields(&mut b, None);
+        assert_eq!(a, b);
+    }
+
+    #[test]
+    fn test_proto_unpacked_macro() {
+        #[derive(Debug, Clone, Default, PartialEq, ProtoRead, ProtoWrite)]
+        struct NestedStruct {
+            a: u32,
+        }
+
+        #[derive(Debug, Default, PartialEq, ProtoRead, ProtoWrite)]
+        struct Packed {
+            #[pbf(tag = 1)]
+            a: Vec<u32>,
+        }
+
+        #[derive(Debug, Default, PartialEq, ProtoRead, ProtoWrite)]
+        struct Unpacked {
+            #[pbf(tag = 1, packed = false)]
+            a: Vec<u32>,
+            #[pbf(packed = false, signed)]
+            b: Option<Vec<i32>>,
+            #[pbf(packed = false)]
+            c: Vec<String>,
+            #[pbf(packed = false, nested)]
+            d: Vec<NestedStruct>,
+        }
+
+        let mut pb = Protobuf::new();
+        pb.write_fields(&Packed { a: vec![1, 300] });
+        assert_eq!(pb.take(), vec![10, 3, 1, 172, 2]);
+
+        let a = Unpacked {
+            a: vec![1, 300],
+            b: Some(vec![-1, 1]),
+            c: vec!["x".into(), "".into()],
+            d: vec![NestedStruct { a: 1 }, NestedStruct { a: 2 }],
+        };
+        let mut pb = Protobuf::new();
+        pb.write_fields(&a);
+
+        let bytes = pb.take();
+        assert_eq!(&bytes[..5], &[8, 1, 8, 172, 2]);
+        assert_eq!(&bytes[5..9], &[16, 1, 16, 2]);
+
+        let mut pb = Protobuf::from_input(bytes);
+        let mut b = Unpacked::default();
+        pb.read_fields(&mut b, None);
+        assert_eq!(a, b);
+    }
+
+    #[test]
+    fn test_proto_interleaved_tags_macro() {
+        #[derive(Debug, Default, PartialEq, ProtoRead, ProtoWrite)]
+        struct Interleaved {
+            a: u32, // tag 0
+            #[pbf(tag = 2)]
+            b: u32,
+            c: u32, // tag 3
+            #[pbf(tag = 1)]
+            d: u32,
+        }
+
+        let data = Interleaved { a: 1, b: 2, c: 3, d: 4 };
+        let mut pb = Protobuf::new();
+        pb.write_fields(&data);
+        let bytes = pb.take();
+        assert_eq!(bytes, vec![0, 1, 16, 2, 24, 3, 8, 4]);
+
+        let mut pb = Protobuf::from_input(bytes);
+        let mut result = Interleaved::default();
+        pb.read_fields(&mut result, None);
+        assert_eq!(result, data);
+    }
+
+    #[test]
+    fn test_proto_interleaved_tags_strict_macro() {
+        #[derive(Debug, Default, PartialEq, ProtoRead, ProtoWrite)]
+        #[pbf(strict_tags)]
+        struct Interleaved {
+            a: u32, // tag 1
+            #[pbf(tag = 3)]
+            b: u32,
+            c: u32, // tag 4
+            #[pbf(tag = 2)]
+            d: u32,
+        }
+
+        let data = Interleaved { a: 1, b: 2, c: 3, d: 4 };
+        let mut pb = Protobuf::new();
+        pb.write_fields(&data);
+        let bytes = pb.take();
+        assert_eq!(bytes, vec![8, 1, 24, 2, 32, 3, 16, 4]);
+
+        let mut pb = Protobuf::from_input(bytes);
+        let mut result = Interleaved::default();
+        pb.read_fields(&mut result, None);
+        assert_eq!(result, data);
+    }
+
+    #[test]
+    fn test_proto_repeated_nested_macro() {
+        #[derive(Debug, Default, PartialEq, ProtoRead, ProtoWrite)]
+        struct Point {
+            #[pbf(tag = 1)]
+            x: i64,
+            y: i64,
+        }
+        #[derive(Debug, Default, PartialEq, ProtoRead, ProtoWrite)]
+        struct Line {
+            #[pbf(tag = 1)]
+            name: String,
+            #[pbf(nested)]
+            points: Vec<Point>,
+        }
+
+        let line = Line {
+            name: "line".into(),
+            points: vec![Point { x: 1, y: 2 }, Point { x: 3, y: 4 }, Point { x: 5, y: 6 }],
+        };
+        let mut pb = Protobuf::new();
+        pb.write_fields(&line);
+        let bytes = pb.take();
+        assert_eq!(
+            bytes,
+            vec![
+                10, 4, 108, 105, 110, 101, 18, 4, 8, 1, 16, 2, 18, 4, 8, 3, 16, 4, 18, 4, 8, 5, 16,
+                6
+            ]
+        );
+
+        let mut pb = Protobuf::from_input(bytes);
+        let mut result = Line::default();
+        pb.read_fields(&mut result, None);
+        assert_eq!(result, line);
+    }
+
+    #[test]
+    fn test_proto_skip_if_default_macro() {
+        #[derive(Debug, Default, PartialEq, ProtoRead, ProtoWrite)]
+        struct Sparse {
+            #[pbf(tag = 1, skip_if_default)]
+            count: u32,
+            #[pbf(skip_if_default)]
+            name: String,
+            always: u32,
+        }
+
+        let zero = Sparse::default();
+        let mut pb = Protobuf::new();
+        pb.write_fields(&zero);
+        let bytes = pb.take();
+        assert_eq!(bytes, vec![24, 0]);
+
+        let mut pb = Protobuf::from_input(bytes);
+        let mut result = Sparse::default();
+        pb.read_fields(&mut result, None);
+        assert_eq!(result, zero);
+
+        let full = Sparse { count: 3, name: "a".into(), always: 0 };
+        let mut pb = Protobuf::new();
+        pb.write_fields(&full);
+        let bytes = pb.take();
+        assert_eq!(bytes, vec![8, 3, 18, 1, 97, 24, 0]);
+        let mut pb = Protobuf::from_input(bytes);
+        let mut result = Sparse::default();
+        pb.read_fields(&mut result, None);
+        assert_eq!(result, full);
+    }
+
+    #[test]
+    fn test_proto_signed_fixed_macro() {
+        #[derive(Debug, Default, PartialEq, ProtoRead, ProtoWrite)]
+        struct SFixed {
+            #[pbf(tag = 1, signed, fixed)]
+            a: i32,
+            #[pbf(signed, fixed)]
+            b: i64,
+        }
+
+        let data = SFixed { a: -5, b: -6 };
+        let mut pb = Protobuf::new();
+        pb.write_fields(&data);
+        let bytes = pb.take();
+        assert_eq!(bytes, vec![13, 251, 255, 255, 255, 17, 250, 255, 255, 255, 255, 255, 255, 255]);
+
+        let mut pb = Protobuf::from_input(bytes);
+        let mut result = SFixed::default();
+        pb.read_fields(&mut result, None);
+        assert_eq!(result, data);
+    }
+
+    #[test]
+    fn test_proto_group_macro() {
+        #[derive(Debug, Default, PartialEq, ProtoRead, ProtoWrite)]
+        struct Inner {
+            #[pbf(tag = 1)]
+            a: u32,
+            b: String,
+        }
+        #[derive(Debug, Default, PartialEq, ProtoRead, ProtoWrite)]
+        struct Outer {
+            #[pbf(tag = 1, group)]
+            inner: Inner,
+            #[pbf(group)]
+            maybe: Option<Inner>,
+            after: u32,
+        }
+
+        let data = Outer {
+            inner: Inner { a: 1, b: "x".into() },
+            maybe: Some(Inner { a: 2, b: "y".into() }),
+            after: 3,
+        };
+        let mut pb = Protobuf::new();
+        pb.write_fields(&data);
+        let bytes = pb.take();
+        assert_eq!(bytes, vec![11, 8, 1, 18, 1, 120, 12, 19, 8, 2, 18, 1, 121, 20, 24, 3]);
+
+        let mut pb = Protobuf::from_input(bytes);
+        let mut result = Outer::default();
+        pb.read_fields(&mut result, None);
+        assert_eq!(result, data);
+    }
+
+    #[test]
+    fn test_proto_borrowed_str_macro() {
+        use alloc::borrow::Cow;
+        use pbf_core::ProtoWrite;
+
+        #[derive(Debug, Default, PartialEq, ProtoRead, ProtoWrite)]
+        struct Owned {
+            a: String,
+            b: Option<String>,
+        }
+        #[derive(Debug, Default, PartialEq, ProtoRead, ProtoWrite)]
+        struct Cowed {
+            a: Cow<'static, str>,
+            b: Option<Cow<'static, str>>,
+        }
+        #[derive(ProtoWrite)]
+        struct Borrowed {
+            a: &'static str,
+            b: Option<&'static str>,
+        }
+
+        let mut owned = Protobuf::new();
+        Owned { a: "a".into(), b: Some("b".into()) }.write(&mut owned);
+        let mut borrowed = Protobuf::new();
+        Borrowed { a: "a", b: Some("b") }.write(&mut borrowed);
+        let cowed = Cowed { a: Cow::Borrowed("a"), b: Some(Cow::Owned("b".into())) };
+        let mut pb = Protobuf::new();
+        cowed.write(&mut pb);
+        let bytes = pb.take();
+        assert_eq!(bytes, owned.take());
+        assert_eq!(bytes, borrowed.take());
+
+        let mut pb = Protobuf::from_input(bytes);
+        let mut result = Cowed::default();
+        pb.read_fields(&mut result, None);
+        assert_eq!(result, cowed);
+        assert!(matches!(result.a, Cow::Owned(_)));
+    }
+
+    #[test]
+    fn test_proto_non_copy_enum_macro() {
+        // intentionally neither Copy nor Clone
+        #[derive(Debug, Default, PartialEq, BitCast)]
+        enum Level {
+            #[default]
+            Low = 0,
+            High = 2,
+        }
+        #[derive(Debug, Default, PartialEq, ProtoRead, ProtoWrite)]
+        struct Reading {
+            #[pbf(tag = 1)]
+            level: Level,
+            maybe: Option<Level>,
+            #[pbf(fixed)]
+            fixed: Level,
+        }
+
+        let data = Reading { level: Level::High, maybe: Some(Level::High), fixed: Level::High };
+        let mut pb = Protobuf::new();
+        pb.write_fields(&data);
+        let bytes = pb.take();
+        assert_eq!(bytes, vec![8, 2, 16, 2, 29, 2, 0, 0, 0]);
+
+        let mut pb = Protobuf::from_input(bytes);
+        let mut result = Reading::default();
+        pb.read_fields(&mut result, None);
+        assert_eq!(result, data);
+    }
+
+    #[test]
+    fn test_proto_merge_macro() {
+        #[derive(Debug, Default, PartialEq, ProtoRead, ProtoWrite)]
+        struct Partial {
+            #[pbf(tag = 1)]
+            id: u32,
+            name: String,
+            #[pbf(signed)]
+            values: Vec<i32>,
+            maybe: Option<Vec<u32>>,
+        }
+
+        let a = Partial { id: 1, name: "a".into(), values: vec![-1, 2], maybe: Some(vec![1]) };
+        let b = Partial { id: 2, values: vec![3], maybe: Some(vec![2]), ..Default::default() };
+        let mut pb = Protobuf::new();
+        pb.write_fields(&a);
+        let a_bytes = pb.take();
+        // only write the fields `b` sets
+        pb.write_varint_field(1, b.id);
+        pb.write_packed_s_varint(3, &b.values);
+        pb.write_packed_varint(4, b.maybe.as_ref().unwrap());
+        let b_bytes = pb.take();
+
+        let expected =
+            Partial { id: 2, name: "a".into(), values: vec![-1, 2, 3], maybe: Some(vec![1, 2]) };
+
+        let mut pb = Protobuf::from_input(Protobuf::merge_bytes(&a_bytes, &b_bytes));
+        let mut merged = Partial::default();
+        pb.read_fields(&mut merged, None);
+        assert_eq!(merged, expected);
+
+        let mut merged = Partial::default();
+        Protobuf::from_input(a_bytes).read_fields(&mut merged, None);
+        Protobuf::from_input(b_bytes).read_fields(&mut merged, None);
+        assert_eq!(merged, expected);
+    }
+
+    #[test]
+    fn test_proto_packed_sfixed_macro() {
+        #[derive(Debug, Default, PartialEq, ProtoRead, ProtoWrite)]
+        struct Deltas {
+            #[pbf(tag = 1, signed, fixed)]
+            wide: Vec<i64>,
+            #[pbf(fixed)]
+            narrow: Vec<i32>,
+        }
+
+        let data = Deltas { wide: vec![-1, 0, i64::MAX, i64::MIN], narrow: vec![-2, 7] };
+        let mut pb = Protobuf::new();
+        pb.write_fields(&data);
+        let bytes = pb.take();
+        // key + length + 4 * 8 bytes, key + length + 2 * 4 bytes
+        assert_eq!(bytes.len(), 2 + 32 + 2 + 8);
+        assert_eq!(&bytes[..2], &[10, 32]);
+        assert_eq!(&bytes[2..10], &[0xff; 8]);
+
+        let mut pb = Protobuf::from_input(bytes);
+        let mut result = Deltas::default();
+        pb.read_fields(&mut result, None);
+        assert_eq!(result, data);
+    }
+
+    #[test]
+    fn test_proto_array_macro() {
+        #[derive(Debug, Default, PartialEq, ProtoRead, ProtoWrite)]
+        struct Triangle {
+            #[pbf(tag = 1, signed)]
+            xs: [i32; 3],
+            ids: [u32; 2],
+            hash: [u8; 4],
+            #[pbf(fixed)]
+            weights: Option<[f32; 2]>,
+        }
+
+        let data = Triangle {
+            xs: [-1, 0, 300],
+            ids: [7, 8],
+            hash: [0xde, 0xad, 0xbe, 0xef],
+            weights: Some([0.5, 2.0]),
+        };
+        let mut pb = Protobuf::new();
+        pb.write_fields(&data);
+        let bytes = pb.take();
+        // key, length, then the zigzag varints 1, 0 and 600
+        assert_eq!(&bytes[..6], &[10, 4, 1, 0, 0xd8, 0x04]);
+
+        let mut pb = Protobuf::from_input(bytes);
+        let mut result = Triangle::default();
+        pb.read_fields(&mut result, None);
+        assert_eq!(result, data);
+    }
+
+    #[test]
+    #[should_panic(expected = "Expected 3 packed elements for a [_; 3], found 2")]
+    fn test_proto_array_length_mismatch_macro() {
+        #[derive(Debug, Default, PartialEq, ProtoRead)]
+        struct Triple {
+            #[pbf(tag = 1, signed)]
+            xs: [i32; 3],
+        }
+
+        let mut pb = Protobuf::new();
+        pb.write_packed_s_varint(1, &[1, 2]);
+        let mut pb = Protobuf::from_input(pb.take());
+        pb.read_fields(&mut Triple::default(), None);
+    }
+
+    #[test]
+    fn test_proto_tag_numbering_macro() {
+        #[derive(Debug, Default, PartialEq, ProtoRead, ProtoWrite)]
+        struct Implicit {
+            a: u32,
+            #[pbf(tag = 536_870_911)]
+            b: u32,
+        }
+
+        let data = Implicit { a: 1, b: 2 };
+        let mut pb = Protobuf::new();
+        pb.write_fields(&data);
+        let bytes = pb.take();
+        assert_eq!(bytes[0], 0);
+        assert_eq!(&bytes[2..7], &[0xf8, 0xff, 0xff, 0xff, 0x0f]);
+
+        let mut pb = Protobuf::from_input(bytes);
+        let mut result = Implicit::default();
+        pb.read_fields(&mut result, None);
+        assert_eq!(result, data);
+    }
+
+    #[test]
+    fn test_proto_optional_nested_macro() {
+        #[derive(Debug, Default, PartialEq, ProtoRead, ProtoWrite)]
+        struct Inner {
+            #[pbf(tag = 1)]
+            value: u32,
+        }
+        #[derive(Debug, Default, PartialEq, ProtoRead, ProtoWrite)]
+        struct Outer {
+            #[pbf(tag = 1, nested)]
+            inner: Option<Inner>,
+        }
+
+        // None writes nothing, not even the field key
+        let mut pb = Protobuf::new();
+        pb.write_fields(&Outer { inner: None });
+        let bytes = pb.take();
+        assert!(bytes.is_empty());
+        let mut result = Outer::default();
+        Protobuf::from_input(bytes).read_fields(&mut result, None);
+        assert_eq!(result, Outer { inner: None });
+
+        // Some writes exactly one message: key, length and the inner field
+        let data = Outer { inner: Some(Inner { value: 5 }) };
+        let mut pb = Protobuf::new();
+        pb.write_fields(&data);
+        let bytes = pb.take();
+        assert_eq!(bytes, vec![10, 2, 8, 5]);
+        let mut result = Outer::default();
+        Protobuf::from_input(bytes).read_fields(&mut result, None);
+        assert_eq!(result, data);
+
+        // an empty inner message is still present
+        let mut pb = Protobuf::new();
+        pb.write_fields(&Outer { inner: Some(Inner::default()) });
+        assert_eq!(pb.take(), vec![10, 2, 8, 0]);
+    }
+
+    #[test]
+    fn test_proto_skip_if_empty_macro() {
+        #[derive(Debug, Default, PartialEq, ProtoRead, ProtoWrite)]
+        struct Lists {
+            #[pbf(tag = 1, skip_if_empty)]
+            ids: Vec<u32>,
+            #[pbf(skip_if_empty, signed)]
+            deltas: Vec<i32>,
+            #[pbf(skip_if_empty)]
+            name: String,
+        }
+
+        let mut pb = Protobuf::new();
+        pb.write_fields(&Lists::default());
+        let bytes = pb.take();
+        assert!(bytes.is_empty());
+        let mut result = Lists::default();
+        Protobuf::from_input(bytes).read_fields(&mut result, None);
+        assert_eq!(result, Lists::default());
+
+        let data = Lists { ids: vec![], deltas: vec![-1], name: "a".into() };
+        let mut pb = Protobuf::new();
+        pb.write_fields(&data);
+        let bytes = pb.take();
+        assert_eq!(bytes, vec![(2 << 3) | 2, 1, 1, (3 << 3) | 2, 1, b'a']);
+        let mut result = Lists::default();
+        Protobuf::from_input(bytes).read_fields(&mut result, None);
+        assert_eq!(result, data);
+    }
+
+    #[test]
+    fn test_proto_fixed_enum_reference_macro() {
+        #[derive(Debug, Default, PartialEq, BitCast)]
+        enum Discriminator {
+            #[default]
+            Point = 1,
+            Line = 2,
+            Polygon = 3,
+        }
+        static LINE: Discriminator = Discriminator::Line;
+        static POLYGON: Discriminator = Discriminator::Polygon;
+
+        #[derive(ProtoWrite)]
+        struct Borrowed {
+            #[pbf(tag = 1, fixed)]
+            kind: &'static Discriminator,
+            #[pbf(fixed)]
+            other: Option<&'static Discriminator>,
+            count: &'static u32,
+        }
+        #[derive(Debug, Default, PartialEq, ProtoRead)]
+        struct Owned {
+            #[pbf(tag = 1, fixed)]
+            kind: Discriminator,
+            #[pbf(fixed)]
+            other: Option<Discriminator>,
+            count: u32,
+        }
+
+        let data = Borrowed { kind: &LINE, other: Some(&POLYGON), count: &7 };
+        let mut pb = Protobuf::new();
+        pb.write_fields(&data);
+        let bytes = pb.take();
+        // two fixed32 fields of 4 bytes each, then a varint
+        assert_eq!(bytes, vec![13, 2, 0, 0, 0, 21, 3, 0, 0, 0, 24, 7]);
+
+        let mut pb = Protobuf::from_input(bytes);
+        let mut result = Owned::default();
+        pb.read_fields(&mut result, None);
+        assert_eq!(
+            result,
+            Owned { kind: Discriminator::Line, other: Some(Discriminator::Polygon), count: 7 }
+        );
+    }
+
+    #[test]
+    fn test_proto_generic_struct_macro() {
+        use alloc::borrow::Cow;
+        use pbf_core::BitCast;
+
+        #[derive(Debug, Default, PartialEq, ProtoRead, ProtoWrite)]
+        struct NestedStruct {
+            a: u32,
+            b: String,
+        }
+        #[derive(Debug, Default, PartialEq, ProtoRead, ProtoWrite)]
+        struct Wrapper<T> {
+            id: u32,
+            #[pbf(nested)]
+            inner: T,
+            #[pbf(nested)]
+            extra: Vec<T>,
+        }
+        // bounds the user writes are kept, a BitCast parameter gets no message bound
+        #[derive(Debug, Default, PartialEq, ProtoRead, ProtoWrite)]
+        struct Tagged<'a, K: BitCast + Default + Copy>
+        where
+            K: PartialEq,
+        {
+            #[pbf(signed)]
+            n: i32,
+            kind: K,
+            note: Cow<'a, str>,
+        }
+
+        let data = Wrapper {
+            id: 1,
+            inner: NestedStruct { a: 2, b: "two".into() },
+            extra: vec![NestedStruct { a: 3, b: "three".into() }],
+        };
+        let mut pb = Protobuf::new();
+        pb.write_fields(&data);
+        let mut pb = Protobuf::from_input(pb.take());
+        let mut result = Wrapper::<NestedStruct>::default();
+        pb.read_fields(&mut result, None);
+        assert_eq!(result, data);
+
+        let data = Tagged { n: -2, kind: 7_u32, note: Cow::Borrowed("borrowed") };
+        let mut pb = Protobuf::new();
+        pb.write_fields(&data);
+        let mut pb = Protobuf::from_input(pb.take());
+        let mut result = Tagged::<u32>::default();
+        pb.read_fields(&mut result, None);
+        assert_eq!(result, data);
+    }
+
+    #[test]
+    fn test_proto_phantom_marker_macro() {
+        use core::marker::PhantomData;
+
+        #[derive(ProtoRead, ProtoWrite, Default, Debug, PartialEq)]
+        struct Marked<T> {
+            #[pbf(tag = 1)]
+            id: u32,
+            _kind: PhantomData<T>,
+            unit: (),
+            name: String,
+        }
+
+        let value = Marked::<u32> { id: 3, _kind: PhantomData, unit: (), name: "a".into() };
+        let mut pb = Protobuf::new();
+        pb.write_message(1, &value);
+        let bytes = pb.take();
+        // the markers take no tag: id is field 1 and name is field 2
+        assert_eq!(bytes, vec![10, 5, 8, 3, 18, 1, 97]);
+
+        let mut pb = Protobuf::from_input(bytes);
+        pb.read_field();
+        let mut decoded = Marked::<u32>::default();
+        pb.read_message(&mut decoded);
+        assert_eq!(decoded, value);
+    }
+
+    #[test]
+    fn test_proto_boxed_elements_macro() {
+        use alloc::{boxed::Box, collections::BTreeMap};
+
+        #[derive(Debug, Default, PartialEq, ProtoRead, ProtoWrite)]
+        struct Leaf {
+            id: u32,
+        }
+
+        #[derive(Debug, Default, PartialEq, ProtoRead, ProtoWrite)]
+        #[allow(clippy::vec_box)]
+        struct Node {
+            #[pbf(tag = 1)]
+            name: String,
+            #[pbf(nested)]
+            children: Vec<Box<Node>>,
+            #[pbf(nested)]
+            leaves: BTreeMap<u32, Box<Leaf>>,
+        }
+
+        let a = Node {
+            name: "root".into(),
+            children: vec![
+                Box::new(Node { name: "a".into(), ..Default::default() }),
+                Box::new(Node {
+                    name: "b".into(),
+                    leaves: BTreeMap::from([(1, Box::new(Leaf { id: 7 }))]),
+                    ..Default::default()
+                }),
+            ],
+            leaves: BTreeMap::from([(2, Box::new(Leaf { id: 8 })), (3, Box::new(Leaf { id: 9 }))]),
+        };
+        let mut pb = Protobuf::new();
+        pb.write_fields(&a);
+
+        let mut pb = Protobuf::from_input(pb.take());
+        let mut b = Node::default();
+        pb.read_fields(&mut b, None);
+        assert_eq!(a, b);
+    }
+
+    #[test]
+    fn test_proto_merge_defaults_macro() {
+        use pbf_core::ProtoRead;
+
+        #[derive(Debug, Default, PartialEq, ProtoRead, ProtoWrite)]
+        struct Versioned {
+            #[pbf(default = "7")]
+            version: u32,
+            #[pbf(skip_if_default)]
+            name: String,
+            ids: Vec<u32>,
+        }
+
+        let mut pb = Protobuf::new();
+        pb.write_fields(&Versioned { version: 3, name: String::new(), ids: vec![1] });
+        let a = pb.take();
+        let mut pb = Protobuf::new();
+        pb.write_fields(&Versioned { version: 7, name: "b".into(), ids: vec![2] });
+        // b without its version field
+        let b = pb.take()[2..].to_vec();
+
+        let mut sequential = Versioned::default();
+        sequential.init();
+        Protobuf::from_input(a.clone()).read_fields(&mut sequential, None);
+        Protobuf::from_input(b.clone()).read_fields(&mut sequential, None);
+        let merged: Versioned = pbf_core::decode(&Protobuf::merge_bytes(&a, &b));
+        assert_eq!(sequential, Versioned { version: 3, name: "b".into(), ids: vec![1, 2] });
+        assert_eq!(merged, sequential);
+    }
+
+    #[test]
+    #[should_panic(expected = "unknown tag 3")]
+    fn test_proto_oneof_unknown_tag_macro() {
+        #[derive(Debug, Default, PartialEq, ProtoRead)]
+        enum Choice {
+            #[default]
+            #[pbf(tag = 4)]
+            A,
+        }
+
+        #[derive(Debug, Default, PartialEq, ProtoRead)]
+        struct Holder {
+            id: u32,
+            #[pbf(oneof)]
+            choice: Choice,
+        }
+
+        // tag 3 belongs neither to a field nor to a variant of the oneof
+        let mut pb = Protobuf::from_input(vec![24, 1]);
+        pb.read_fields(&mut Holder::default(), None);
+    }
+
+    #[test]
+    fn test_proto_oneof_unit_variant_tag_macro() {
+        use pbf_core::ProtoWrite;
+
+        #[derive(Debug, Default, PartialEq, ProtoRead, ProtoWrite)]
+        enum Choice {
+            #[default]
+            #[pbf(tag = 4)]
+            A,
+        }
+
+        #[derive(Debug, Default, PartialEq, ProtoRead, ProtoWrite)]
+        struct Holder {
+            #[pbf(tag = 1)]
+            id: u32,
+            #[pbf(oneof)]
+            choice: Choice,
+        }
+
+        assert_eq!(<Choice as ProtoWrite>::TAGS, &[4]);
+        let mut pb = Protobuf::new();
+        pb.write_fields(&Holder { id: 7, choice: Choice::A });
+        let bytes = pb.take();
+        assert_eq!(bytes, vec![8, 7, 39]);
+
+        let mut pb = Protobuf::from_input(bytes);
+        let mut holder = Holder::default();
+        pb.read_fields(&mut holder, None);
+        assert_eq!(holder, Holder { id: 7, choice: Choice::A });
+    }
 }
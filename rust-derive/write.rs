@@ -1,49 +1,111 @@
-use crate::FieldAttributes;
+use crate::{
+    ContainerAttributes, FieldAttributes, claim_tag, first_named_tag, is_cow_str, is_marker_type,
+    is_option_type, map_types, oneof_tags_check, oneof_type, pointer_inner_type,
+    second_oneof_error,
+};
 use darling::{FromField, FromVariant};
 use proc_macro::TokenStream;
 use quote::{format_ident, quote};
-use syn::{DataEnum, DataStruct, Fields, GenericArgument, Ident, PathArguments, Type, TypePath};
+use syn::{
+    DataEnum, DataStruct, Fields, GenericArgument, Generics, Ident, Index, Member, PathArguments,
+    Type, TypeArray, TypePath, TypeReference,
+};
 
 pub fn derive_proto_write_struct(
     data_struct: &DataStruct,
     name: &Ident,
+    generics: &Generics,
     pbf_core: &Ident,
+    container: &ContainerAttributes,
 ) -> TokenStream {
     let mut write_statements = Vec::new();
-    let mut field_index: u64 = 0; // Default tag assignment
+    let mut oneof_field = None;
+    // Default tag assignment (positional fields of tuple structs are 1-based)
+    let mut field_index: u64 = match &data_struct.fields {
+        Fields::Named(_) => first_named_tag(container.strict_tags),
+        Fields::Unnamed(_) => 1,
+        Fields::Unit => panic!("ProtoWrite can only be derived for structs with fields"),
+    };
+    let mut claimed_tags = Vec::new();
 
-    if let Fields::Named(fields) = &data_struct.fields {
-        for field in &fields.named {
-            let field_name = field.ident.as_ref().unwrap();
-            let field_type = &field.ty;
-            let attr = FieldAttributes::from_field(field).unwrap();
-            // skip user defined "ignore"s
-            if attr.ignore {
-                continue;
+    for (idx, field) in data_struct.fields.iter().enumerate() {
+        let field_name = match &field.ident {
+            Some(ident) => Member::Named(ident.clone()),
+            None => Member::Unnamed(Index::from(idx)),
+        };
+        let field_type = &field.ty;
+        let attr = FieldAttributes::from_field(field).unwrap();
+        // skip user defined "ignore"s and zero-sized markers
+        if attr.ignore || is_marker_type(field_type) {
+            continue;
+        }
+        // a oneof writes its active variant using the variant's own tag
+        if attr.oneof {
+            if oneof_field.is_some() {
+                return second_oneof_error(field).to_compile_error().into();
             }
+            oneof_field = Some(oneof_type(field_type));
+            write_statements.push(if is_option_type(field_type) {
+                quote! {
+                    if let Some(value) = &self.#field_name {
+                        ProtoWrite::write(value, pbf);
+                    }
+                }
+            } else {
+                quote! { ProtoWrite::write(&self.#field_name, pbf); }
+            });
+            continue;
+        }
 
-            let write_method =
-                field_type_to_write_method(field_type, field_name, field_index, &attr, false)
-                    .unwrap_or_else(|| {
-                        panic!(
-                            "Unsupported type in ProtoWrite derive: {:#?}",
-                            quote! { #field_type }
-                        )
-                    });
+        let tag = attr.tag.unwrap_or(field_index);
+        if let Err(err) = claim_tag(
+            &mut claimed_tags,
+            tag,
+            field,
+            quote! { #field_name }.to_string(),
+            container.strict_tags,
+        ) {
+            return err.to_compile_error().into();
+        }
+        let target = quote! { self.#field_name };
+        let write_method =
+            field_type_to_write_method(field_type, &target, field_index, &attr, false)
+                .unwrap_or_else(|| {
+                    panic!("Unsupported type in ProtoWrite derive: {:#?}", quote! { #field_type })
+                });
 
+        // proto3 style, a field holding its default value is left out of the buffer
+        if attr.skip_if_default {
+            write_statements.push(quote! {
+                if self.#field_name != <#field_type as Default>::default() {
+                    #write_method
+                }
+            });
+        } else if attr.skip_if_empty {
+            // proto3 style for repeated fields, without requiring `PartialEq` on the elements
+            write_statements.push(quote! {
+                if !self.#field_name.is_empty() {
+                    #write_method
+                }
+            });
+        } else {
             write_statements.push(write_method);
-            // increment field_index only if the user did not define an index for the field
-            if let Some(index) = attr.tag {
-                field_index = index + 1;
-            } else {
-                field_index += 1;
-            }
         }
-    } else {
-        panic!("ProtoWrite can only be derived for structs with named fields");
+        // increment field_index only if the user did not define an index for the field
+        if let Some(index) = attr.tag {
+            field_index = index + 1;
+        } else {
+            field_index += 1;
+        }
     }
 
+    let (oneof_item_check, oneof_body_check) = match oneof_field {
+        Some(oneof) => oneof_tags_check(oneof, &claimed_tags, generics, quote! { ProtoWrite }),
+        None => (quote! {}, quote! {}),
+    };
+
     // Generate the trait implementation
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
     let expanded = quote! {
         #[doc(hidden)]
         #[allow(
@@ -60,9 +122,12 @@ pub fn derive_proto_write_struct(
 
             use _pbf_core::*;
 
+            #oneof_item_check
+
             #[automatically_derived]
-            impl ProtoWrite for #name {
+            impl #impl_generics ProtoWrite for #name #ty_generics #where_clause {
                 fn write(&self, pbf: &mut Protobuf) {
+                    #oneof_body_check
                     #(#write_statements)*
                 }
             }
@@ -75,14 +140,21 @@ pub fn derive_proto_write_struct(
 pub fn derive_proto_write_enum(
     data_enum: &DataEnum,
     name: &Ident,
+    generics: &Generics,
     pbf_core: &Ident,
 ) -> TokenStream {
     let mut write_statements = Vec::new();
+    let mut tags = Vec::new();
     let mut field_index: u64 = 0; // Default tag assignment
 
     for variant in &data_enum.variants {
         let variant_name = &variant.ident;
         let attr = FieldAttributes::from_variant(variant).unwrap();
+        // the same numbering as the read derive, unit variants honor an explicit tag too
+        field_index = attr.tag.unwrap_or(field_index);
+        if !attr.ignore {
+            tags.push(field_index);
+        }
         if variant.fields.is_empty() {
             write_statements.push(quote! {
                 #name::#variant_name => pbf.write_field(#field_index, Type::None),
@@ -96,14 +168,16 @@ pub fn derive_proto_write_enum(
                     continue;
                 }
 
-                let write_method =
-                    field_type_to_write_method(field_type, &field_name, field_index, &attr, true)
-                        .unwrap_or_else(|| {
-                            panic!(
-                                "Unsupported type in ProtoWrite derive: {:#?}",
-                                quote! { #field_type }
-                            )
-                        });
+                let write_method = field_type_to_write_method(
+                    field_type,
+                    &quote! { #field_name },
+                    field_index,
+                    &attr,
+                    true,
+                )
+                .unwrap_or_else(|| {
+                    panic!("Unsupported type in ProtoWrite derive: {:#?}", quote! { #field_type })
+                });
 
                 write_statements.push(quote! {
                     #name::#variant_name(#field_name) => {
@@ -121,6 +195,7 @@ pub fn derive_proto_write_enum(
     }
 
     // Generate the trait implementation
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
     let expanded = quote! {
         #[doc(hidden)]
         #[allow(
@@ -138,12 +213,14 @@ pub fn derive_proto_write_enum(
             use _pbf_core::*;
 
             #[automatically_derived]
-            impl ProtoWrite for #name {
+            impl #impl_generics ProtoWrite for #name #ty_generics #where_clause {
                 fn write(&self, pbf: &mut Protobuf) {
                     match self {
                         #(#write_statements)*
                     }
                 }
+
+                const TAGS: &'static [u64] = &[#(#tags),*];
             }
         };
     };
@@ -154,16 +231,11 @@ pub fn derive_proto_write_enum(
 /// Maps Rust types to the corresponding Protobuf write method.
 fn field_type_to_write_method(
     field_type: &syn::Type,
-    field_name: &Ident,
+    name: &proc_macro2::TokenStream,
     field_index: u64,
     attr: &FieldAttributes,
     is_option: bool,
 ) -> Option<proc_macro2::TokenStream> {
-    let name: proc_macro2::TokenStream = if is_option {
-        quote! { #field_name }
-    } else {
-        quote! { self.#field_name }
-    };
     let name_st = if is_option {
         quote! { *#name }
     } else {
@@ -187,10 +259,11 @@ fn field_type_to_write_method(
                 || path.is_ident("isize")
                 || path.is_ident("bool") =>
         {
-            if attr.signed {
-                Some(quote! { pbf.write_s_varint_field(#field_index, #name_st); })
-            } else if attr.fixed {
+            // fixed wins over signed: the two's complement bits already keep the sign (sfixed)
+            if attr.fixed {
                 Some(quote! { pbf.write_fixed_field(#field_index, #name_st); })
+            } else if attr.signed {
+                Some(quote! { pbf.write_s_varint_field(#field_index, #name_st); })
             } else {
                 Some(quote! { pbf.write_varint_field(#field_index, #name_st); })
             }
@@ -201,63 +274,146 @@ fn field_type_to_write_method(
             Some(quote! { pbf.write_string_field(#field_index, &#name); })
         }
 
+        // Handling Cow<str> and &str, which deref to a str just like String
+        Type::Path(TypePath { .. }) if is_cow_str(field_type) => {
+            Some(quote! { pbf.write_string_field(#field_index, &#name); })
+        }
+        Type::Reference(TypeReference { elem, .. }) if matches!(&**elem, Type::Path(TypePath { path, .. }) if path.is_ident("str")) => {
+            Some(quote! { pbf.write_string_field(#field_index, #name); })
+        }
+
+        // Handling any other &T by writing the referenced value, e.g. a shared enum
+        Type::Reference(TypeReference { elem, .. }) => {
+            field_type_to_write_method(elem, &quote! { (*#name) }, field_index, attr, is_option)
+        }
+
         // Handling Vec<T> (bytes fields)
         Type::Path(TypePath { path, .. }) if path.segments.last().unwrap().ident == "Vec" => {
             if let PathArguments::AngleBracketed(ref args) = path.segments.last().unwrap().arguments
-            {
-                if let Some(GenericArgument::Type(Type::Path(TypePath { path, .. }))) =
+                && let Some(GenericArgument::Type(inner_type @ Type::Path(TypePath { path, .. }))) =
                     args.args.first()
-                {
-                    if path.segments.last().unwrap().ident == "u8" {
-                        // If the type inside Vec is u8, use write_bytes_field
-                        return Some(quote! { pbf.write_bytes_field(#field_index, &#name_st); });
-                    } else {
-                        // Otherwise, use packed
-                        if attr.signed {
-                            return Some(
-                                quote! { pbf.write_packed_s_varint(#field_index, &#name_st); },
-                            );
-                        } else {
-                            return Some(
-                                quote! { pbf.write_packed_varint(#field_index, &#name_st); },
-                            );
+            {
+                if path.segments.last().unwrap().ident == "u8" {
+                    // If the type inside Vec is u8, use write_bytes_field
+                    return Some(quote! { pbf.write_bytes_field(#field_index, &#name_st); });
+                } else if attr.packed == Some(false) || attr.nested || attr.group {
+                    // Unpacked (messages can never be packed), write every element as its own field
+                    let inner_write = field_type_to_write_method(
+                        inner_type,
+                        &quote! { value },
+                        field_index,
+                        attr,
+                        true,
+                    )?;
+                    return Some(quote! {
+                        for value in #name.iter() {
+                            #inner_write
                         }
+                    });
+                } else {
+                    // Otherwise, use packed (fixed wins over signed: two's complement at fixed width)
+                    if attr.fixed {
+                        return Some(quote! { pbf.write_packed_fixed(#field_index, &#name_st); });
+                    } else if attr.signed {
+                        return Some(
+                            quote! { pbf.write_packed_s_varint(#field_index, &#name_st); },
+                        );
+                    } else {
+                        return Some(quote! { pbf.write_packed_varint(#field_index, &#name_st); });
                     }
                 }
             }
             None
         }
 
+        // Handling [T; N] like a Vec<T> of N elements
+        Type::Array(TypeArray { elem, .. }) => {
+            if let Type::Path(TypePath { path, .. }) = &**elem
+                && path.is_ident("u8")
+            {
+                Some(quote! { pbf.write_array_field(#field_index, &#name_st); })
+            } else if attr.fixed {
+                Some(quote! { pbf.write_packed_fixed(#field_index, &#name_st); })
+            } else if attr.signed {
+                Some(quote! { pbf.write_packed_s_varint(#field_index, &#name_st); })
+            } else {
+                Some(quote! { pbf.write_packed_varint(#field_index, &#name_st); })
+            }
+        }
+
+        // Handling maps (BTreeMap<K, V> or HashMap<K, V>) as repeated key/value entries
+        Type::Path(TypePath { .. }) if map_types(field_type).is_some() => {
+            let (key_type, value_type) = map_types(field_type)?;
+            let key_attr = FieldAttributes { tag: Some(1), ..Default::default() };
+            let value_attr = FieldAttributes { tag: Some(2), ..attr.clone() };
+            let key_write =
+                field_type_to_write_method(key_type, &quote! { key }, 1, &key_attr, true)?;
+            let value_write =
+                field_type_to_write_method(value_type, &quote! { value }, 2, &value_attr, true)?;
+            Some(quote! {
+                // each entry goes through a scratch buffer like any nested message
+                for (key, value) in #name.iter() {
+                    pbf.write_message_closure(#field_index, |pbf| {
+                        #key_write
+                        #value_write
+                    });
+                }
+            })
+        }
+
+        // Handling Box<T>, Rc<T> and Arc<T> by writing the pointee
+        Type::Path(TypePath { .. }) if pointer_inner_type(field_type).is_some() => {
+            let inner_type = pointer_inner_type(field_type)?;
+            let inner = if is_option {
+                quote! { (**#name) }
+            } else {
+                quote! { (*#name) }
+            };
+            field_type_to_write_method(inner_type, &inner, field_index, attr, false)
+        }
+
         // Handling Option<T>
         Type::Path(TypePath { path, .. }) if path.segments.last().unwrap().ident == "Option" => {
             if let PathArguments::AngleBracketed(ref args) = path.segments.last().unwrap().arguments
+                && let Some(GenericArgument::Type(inner_type)) = args.args.first()
+                && let Some(internal_field) = field_type_to_write_method(
+                    inner_type,
+                    &quote! { value },
+                    field_index,
+                    attr,
+                    true,
+                )
             {
-                if let Some(GenericArgument::Type(inner_type)) = args.args.first() {
-                    if let Some(internal_field) =
-                        field_type_to_write_method(inner_type, field_name, field_index, attr, true)
-                    {
-                        return Some(quote! {
-                            if let Some(#field_name) = &#name {
-                                #internal_field
-                            }
-                        });
+                return Some(quote! {
+                    if let Some(value) = &#name {
+                        #internal_field
                     }
-                }
+                });
             }
             None
         }
 
+        // Detecting Structs encoded as a legacy group
+        Type::Path(TypePath { .. }) if attr.group => {
+            Some(quote! { pbf.write_group(#field_index, &#name_st); })
+        }
+
         // Detecting Structs
         Type::Path(TypePath { .. }) if attr.nested => {
             Some(quote! { pbf.write_message(#field_index, &#name_st); })
         }
 
-        // Assume last case is an enum
+        // Enums stored as a fixed32
+        Type::Path(TypePath { .. }) if attr.fixed => Some(quote! {
+            pbf.write_fixed_field(#field_index, BitCast::to_u64(&#name_st) as u32);
+        }),
+
+        // Assume last case is an enum, borrowed so enums that are not Copy work too
         Type::Path(TypePath { .. }) => {
-            Some(quote! { pbf.write_varint_field(#field_index, #name_st); })
+            Some(quote! { pbf.write_varint_field(#field_index, BitCast::to_u64(&#name_st)); })
         }
 
-        // Other types (e.g., arrays or references can be extended here)
+        // Other unsupported types
         _ => None, // You could return Option::None for unsupported types or handle them
     }
 }
@@ -66,13 +66,139 @@
 //! }
 //! ```
 //!
-//! `ProtoRead` and `ProtoWrite` trait derives support 5 attributes:
+//! `ProtoRead` and `ProtoWrite` trait derives support 11 attributes:
 //!
 //! - `pbf(tag = 1)` -> Set the tag # of the field.
-//! - `pbf(fixed)` -> Set the type to `Fixed` (for 32-bit and 64-bit numbers).
-//! - `pbf(signed)` -> Set the type to `Signed` (to handle protobuf "sint" values).
+//! - `pbf(fixed)` -> Set the type to `Fixed` (for 32-bit and 64-bit numbers, enums are stored as
+//!   a fixed32). A fixed `Vec<T>` is packed with every element at its fixed width.
+//! - `pbf(signed)` -> Set the type to `Signed` (to handle protobuf "sint" values). Combined with
+//!   `fixed` the value is stored as a fixed width two's complement number ("sfixed").
 //! - `pbf(nested)` -> If a sub structure is present, set the type to `nested` so it can be properly derived.
-//! - `pbf(ignore)` -> Ignore the field.
+//!   A nested `Vec<T>` is written as one message per element.
+//! - `pbf(group)` -> Like `nested`, but encode the sub structure as a proto2 group (between a
+//!   start-group and an end-group key) instead of a length-delimited message.
+//! - `pbf(ignore)` -> Ignore the field. Zero-sized markers (`()`, `PhantomData<T>`, `PhantomPinned`)
+//!   are always ignored, so generic structs need no attribute on them.
+//! - `pbf(oneof)` -> The field is a `ProtoRead`/`ProtoWrite` enum (or `Option` of one) whose
+//!   variants carry their own tags. The tags of a derived enum are routed to it, and a variant tag
+//!   reused by another field of the struct is a compile error. A hand written enum that does not
+//!   set `ProtoRead::TAGS` receives every tag not claimed by another field.
+//! - `pbf(default = "expr")` -> When reading a fresh message (`decode`, `read_message`), initialize
+//!   the field to `expr` so it keeps that value if its tag is absent from the buffer.
+//! - `pbf(packed = false)` -> Write a `Vec<T>` as one field per element instead of a packed field.
+//!   This also allows repeated strings.
+//! - `pbf(skip_if_default)` -> Do not write the field while it equals `Default::default()`
+//!   (proto3 style). Reading leaves an absent field at its default, so it still round-trips.
+//! - `pbf(skip_if_empty)` -> Do not write a `Vec`, `String` or map field while it is empty. By
+//!   default an empty `Vec` is still written as a zero length packed field (two bytes), which other
+//!   proto3 encoders never emit; both forms read back as an empty `Vec`.
+//!
+//! A field without a `tag` takes the tag after the previous field's, so explicit and implicit tags
+//! can be interleaved. Two fields resolving to the same tag is rejected at compile time:
+//!
+//! ```compile_fail
+//! use pbf::{ProtoRead, ProtoWrite};
+//!
+//! #[derive(Default, ProtoRead, ProtoWrite)]
+//! struct Collision {
+//!     a: u32, // tag 0
+//!     b: u32, // tag 1
+//!     c: u32, // tag 2
+//!     #[pbf(tag = 2)] // error: pbf tag 2 is used by both `c` and `d`
+//!     d: u32,
+//! }
+//! ```
+//!
+//! The same goes for a field reusing the tag of a `oneof` variant:
+//!
+//! ```compile_fail
+//! use pbf::{ProtoRead, ProtoWrite};
+//!
+//! #[derive(Default, ProtoRead, ProtoWrite)]
+//! enum Choice {
+//!     #[default]
+//!     #[pbf(tag = 2)]
+//!     A,
+//! }
+//!
+//! #[derive(Default, ProtoRead, ProtoWrite)]
+//! struct Holder {
+//!     #[pbf(tag = 2)] // error: a pbf(oneof) variant tag is already used by another field
+//!     id: u32,
+//!     #[pbf(oneof)]
+//!     choice: Choice,
+//! }
+//! ```
+//!
+//! A struct holds at most one `oneof` field:
+//!
+//! ```compile_fail
+//! use pbf::ProtoWrite;
+//!
+//! #[derive(ProtoWrite)]
+//! enum Choice {
+//!     #[pbf(tag = 1)]
+//!     A(u32),
+//! }
+//!
+//! #[derive(ProtoWrite)]
+//! struct Holder {
+//!     #[pbf(oneof)]
+//!     first: Choice,
+//!     #[pbf(oneof)] // error: only one #[pbf(oneof)] field is supported per struct
+//!     second: Option<Choice>,
+//! }
+//! ```
+//!
+//! Tags must also be valid protobuf field numbers, so the reserved range 19000 to 19999 and
+//! anything above 536870911 are rejected the same way:
+//!
+//! ```compile_fail
+//! use pbf::ProtoWrite;
+//!
+//! #[derive(ProtoWrite)]
+//! struct Reserved {
+//!     #[pbf(tag = 19000)] // error: pbf tag 19000 of `a` is invalid
+//!     a: u32,
+//! }
+//! ```
+//!
+//! Fields of a named struct without a `tag` are numbered from 0, which this crate (and its
+//! JavaScript counterpart) decode fine but other protobuf implementations reject. A struct marked
+//! `#[pbf(strict_tags)]` numbers them from 1 and rejects tag 0 instead. It changes the encoded
+//! bytes of that struct, so both sides of the wire need the same definition.
+//!
+//! ```compile_fail
+//! use pbf::ProtoWrite;
+//!
+//! #[derive(ProtoWrite)]
+//! #[pbf(strict_tags)]
+//! struct Zero {
+//!     #[pbf(tag = 0)] // error: pbf tag 0 of `a` is invalid
+//!     a: u32,
+//! }
+//! ```
+//!
+//! `BTreeMap<K, V>` and `HashMap<K, V>` fields are encoded as protobuf `map` entries (key at
+//! tag 1, value at tag 2). Attributes on a map field apply to its values.
+//!
+//! Tuple structs such as `struct Pair(#[pbf(signed)] i32, String)` are supported as well; their
+//! positional fields are assigned sequential tags starting at 1.
+//!
+//! Arrays `[T; N]` are encoded like a packed `Vec<T>` (`[u8; N]` as bytes), and reading one panics
+//! if the field does not hold exactly `N` elements.
+//!
+//! `Cow<str>` fields are encoded like `String` (and read back as `Cow::Owned`), and `&str` fields
+//! can be written, which is handy for write-only structs. The same goes for any other reference
+//! such as `&'a Kind`, which is written like the value it points to.
+//!
+//! `Box<T>`, `Rc<T>` and `Arc<T>` fields are encoded like `T`, which allows recursive messages such
+//! as `struct Node { value: i32, #[pbf(nested)] child: Option<Box<Node>> }`.
+//!
+//! Generic structs and enums keep their lifetimes, type parameters and where clauses. A type
+//! parameter used by a `nested`, `group` or `oneof` field is bound by `ProtoWrite` (or
+//! `ProtoRead + Default`) automatically, so `struct Wrapper<T> { #[pbf(nested)] inner: T }`
+//! derives as is. Any other use of a type parameter needs the bounds written on the struct.
 //!
 //! Here is a more complex use case showcasing all the ways you can use derives:
 //!
@@ -141,6 +267,8 @@
 //! ```
 //!
 //! If you are using the `derive` feature, you can also derive the `BitCast` trait for your enum.
+//! The derive implements `TryBitCast` too, so `Protobuf::try_read_varint` can report unknown
+//! discriminants as an error instead of panicking.
 //!
 //! ```rust
 //! use pbf::BitCast;
@@ -152,6 +280,17 @@
 //!     C = 3,
 //! }
 //! ```
+//!
+//! For the common case, `pbf::encode(&value)` returns a message's bytes and
+//! `pbf::decode::<T>(&bytes)` reads them back into a fresh `T::default()`.
+//!
+//! With the `serde` feature enabled, any `Serialize`/`Deserialize` struct can be converted with
+//! `to_pbf_bytes` and `from_pbf_bytes`. Fields are assigned sequential tags starting at 0, just
+//! like the derives without `strict_tags`, signed integers are zigzag encoded and sequences of
+//! numbers are packed.
+//!
+//! The `std` feature adds `Protobuf::flush_to` for streaming output into any `std::io::Write`
+//! and `read_length_delimited` for parsing length-prefixed messages from any `std::io::Read`.
 
 extern crate pbf_core;
 #[cfg(feature = "derive")]